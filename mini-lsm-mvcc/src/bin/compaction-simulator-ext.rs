@@ -0,0 +1,1096 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulates the compaction strategies and policies that only the MVCC engine has: tiered compaction by bytes,
+//! the SST selection policies of leveled compaction, FIFO and time-window compaction. The strategies shared by
+//! all engines are simulated by `compaction-simulator`.
+
+mod wrapper;
+use wrapper::mini_lsm_wrapper;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, BytesMut};
+use clap::{Parser, ValueEnum};
+use mini_lsm_wrapper::compact::{
+    BigEndianTimePrefix, FifoCompactionController, FifoCompactionOptions,
+    LeveledCompactionController, LeveledCompactionOptions, SstSelectionPolicy,
+    TieredCompactionController, TieredCompactionOptions, TimeWindowCompactionController,
+    TimeWindowCompactionOptions,
+};
+use mini_lsm_wrapper::key::KeyBytes;
+use mini_lsm_wrapper::lsm_storage::LsmStorageState;
+use mini_lsm_wrapper::table::SsTable;
+use mini_lsm_wrapper::ttl::{Clock, ManualClock};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+enum Args {
+    Tiered {
+        /// Dump the generated ID instead of where the original data comes from.
+        /// For example, if SST 1, 2, 3 is compacted to another level, it should have
+        /// a new SST ID 4, 5, 6 as SSTs are immutable and write-once. With this flag
+        /// enabled, you will see the new level has SST 1, 2, 3 because the data of
+        /// 4, 5, 6 are originated from 1, 2, 3.
+        #[clap(long)]
+        dump_real_id: bool,
+        /// Only dump size information instead of the layer files. if this is enabled,
+        /// it will print one row per compaction iteration.
+        #[clap(long)]
+        size_only: bool,
+        #[clap(long, default_value = "8")]
+        num_tiers: usize,
+        #[clap(long, default_value = "200")]
+        max_size_amplification_percent: usize,
+        #[clap(long, default_value = "1")]
+        size_ratio: usize,
+        #[clap(long, default_value = "2")]
+        min_merge_width: usize,
+        #[clap(long)]
+        max_merge_width: Option<usize>,
+        #[clap(long)]
+        max_merge_mb: Option<u64>,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        /// The size of a flushed SST, and the target size of the compacted SSTs.
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+        /// Flush SSTs of random sizes between 1/4 and 7/4 of the SST size.
+        #[clap(long)]
+        random_flush_size: bool,
+    },
+    Leveled {
+        /// Dump the generated ID instead of where the original data comes from.
+        /// For example, if SST 1, 2, 3 is compacted to another level, it should have
+        /// a new SST ID 4, 5, 6 as SSTs are immutable and write-once. With this flag
+        /// enabled, you will see the new level has SST 1, 2, 3 because the data of
+        /// 4, 5, 6 are originated from 1, 2, 3.
+        #[clap(long)]
+        dump_real_id: bool,
+        /// Only dump size information instead of the layer files. if this is enabled,
+        /// it will print one row per compaction iteration.
+        #[clap(long)]
+        size_only: bool,
+        #[clap(long, default_value = "2")]
+        level0_file_num_compaction_trigger: usize,
+        #[clap(long, default_value = "2")]
+        level_size_multiplier: usize,
+        #[clap(long, default_value = "4")]
+        max_levels: usize,
+        #[clap(long, default_value = "128")]
+        base_level_size_mb: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+        /// How to select the SST to compact from a level.
+        #[clap(long, value_enum, default_value = "oldest-first")]
+        sst_selection: SstSelection,
+        /// Simulate the same flushes with each SST selection policy, and compare their write amplification.
+        #[clap(long)]
+        compare_sst_selection: bool,
+    },
+    Fifo {
+        /// Dump the generated ID instead of where the original data comes from.
+        /// For example, if SST 1, 2, 3 is merged into SST 4, it is shown as SST 1
+        /// with this flag disabled.
+        #[clap(long)]
+        dump_real_id: bool,
+        /// Only dump size information instead of the layer files. if this is enabled,
+        /// it will print one row per compaction iteration.
+        #[clap(long)]
+        size_only: bool,
+        #[clap(long, default_value = "1024")]
+        max_table_files_size_mb: usize,
+        /// Delete the SSTs whose newest write is older than this many seconds.
+        #[clap(long)]
+        ttl_secs: Option<u64>,
+        #[clap(long)]
+        intra_l0_min_merge_width: Option<usize>,
+        #[clap(long, default_value = "128")]
+        intra_l0_max_merged_size_mb: usize,
+        /// The simulated time between two flushes.
+        #[clap(long, default_value = "60")]
+        flush_interval_secs: u64,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+    },
+    TimeWindow {
+        /// Dump the generated ID instead of where the original data comes from.
+        /// For example, if SST 1, 2, 3 is merged into SST 4, it is shown as SST 1
+        /// with this flag disabled.
+        #[clap(long)]
+        dump_real_id: bool,
+        /// Only dump size information instead of the layer files. if this is enabled,
+        /// it will print one row per compaction iteration.
+        #[clap(long)]
+        size_only: bool,
+        /// The keys are the big-endian seconds of the writes, bucketed into windows of this many seconds.
+        #[clap(long, default_value = "3600")]
+        window_size_secs: u64,
+        #[clap(long, default_value = "4")]
+        min_threshold: usize,
+        #[clap(long, default_value = "32")]
+        max_threshold: usize,
+        /// The seconds of keys in each flush.
+        #[clap(long, default_value = "600")]
+        flush_interval_secs: u64,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SstSelection {
+    OldestFirst,
+    MinOverlappingRatio,
+    RoundRobin,
+    MostTombstones,
+}
+
+impl From<SstSelection> for SstSelectionPolicy {
+    fn from(selection: SstSelection) -> Self {
+        match selection {
+            SstSelection::OldestFirst => SstSelectionPolicy::OldestFirst,
+            SstSelection::MinOverlappingRatio => SstSelectionPolicy::MinOverlappingRatio,
+            SstSelection::RoundRobin => SstSelectionPolicy::RoundRobin,
+            SstSelection::MostTombstones => SstSelectionPolicy::MostTombstones,
+        }
+    }
+}
+
+pub struct MockStorage {
+    snapshot: LsmStorageState,
+    next_sst_id: usize,
+    /// Maps SST ID to the original flushed SST ID
+    file_list: HashMap<usize, usize>,
+    total_flushes: usize,
+    total_writes: usize,
+}
+
+impl Default for MockStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockStorage {
+    pub fn new() -> Self {
+        Self {
+            snapshot: LsmStorageState::empty(),
+            next_sst_id: 1,
+            file_list: Default::default(),
+            total_flushes: 0,
+            total_writes: 0,
+        }
+    }
+
+    fn generate_sst_id(&mut self) -> usize {
+        let id = self.next_sst_id;
+        self.next_sst_id += 1;
+        id
+    }
+
+    pub fn flush_sst_to_l0(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.l0_sstables.push(id);
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    /// Flushes an SST to the front of L0, which is newest first like in the storage engine.
+    pub fn flush_newest_sst_to_l0(&mut self) -> usize {
+        let id = self.flush_sst_to_l0();
+        self.snapshot.l0_sstables.pop();
+        self.snapshot.l0_sstables.insert(0, id);
+        id
+    }
+
+    pub fn flush_sst_to_new_tier(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.levels.insert(0, (id, vec![id]));
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    /// Adds a mock SST of `size` bytes, for the compaction strategies that only look at the sizes.
+    pub fn add_sst_of_size(&mut self, id: usize, size: u64) {
+        let key = KeyBytes::for_testing_from_bytes_no_ts(BytesMut::new().freeze());
+        self.snapshot.sstables.insert(
+            id,
+            Arc::new(SsTable::create_meta_only(id, size, key.clone(), key)),
+        );
+    }
+
+    /// The total size of the SSTs in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.snapshot
+            .sstables
+            .values()
+            .map(|sst| sst.table_size())
+            .sum()
+    }
+
+    pub fn remove(&mut self, files_to_remove: &[usize]) {
+        for file_id in files_to_remove {
+            let ret = self.file_list.remove(file_id);
+            assert!(ret.is_some(), "failed to remove file {}", file_id);
+        }
+    }
+
+    fn check_keys(&self) {
+        for (level, files) in &self.snapshot.levels {
+            if files.len() >= 2 {
+                for id in 0..(files.len() - 1) {
+                    let this_file = self.snapshot.sstables[&files[id]].clone();
+                    let next_file = self.snapshot.sstables[&files[id + 1]].clone();
+                    if this_file.last_key() >= next_file.first_key() {
+                        panic!(
+                            "invalid file arrangement in L{}: id={}, range={:x}..={:x}; id={}, range={:x}..={:x}",
+                            level,
+                            this_file.sst_id(),
+                            this_file.first_key().for_testing_key_ref().get_u64(),
+                            this_file.last_key().for_testing_key_ref().get_u64(),
+                            next_file.sst_id(),
+                            next_file.first_key().for_testing_key_ref().get_u64(),
+                            next_file.last_key().for_testing_key_ref().get_u64()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn dump_size_only(&self) {
+        print!("Levels: {}", self.snapshot.l0_sstables.len());
+        for (_, files) in &self.snapshot.levels {
+            print!(" {}", files.len());
+        }
+        println!();
+    }
+
+    pub fn dump_size_mb_only(&self) {
+        print!("Levels (MB):");
+        for (_, files) in &self.snapshot.levels {
+            let size = files
+                .iter()
+                .map(|id| self.snapshot.sstables[id].table_size())
+                .sum::<u64>();
+            print!(" {}", size / 1024 / 1024);
+        }
+        println!();
+    }
+
+    pub fn dump_original_id(&self, always_show_l0: bool, with_key: bool) {
+        if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
+            println!(
+                "L0 ({}): {:?}",
+                self.snapshot.l0_sstables.len(),
+                self.snapshot.l0_sstables,
+            );
+        }
+        for (level, files) in &self.snapshot.levels {
+            println!(
+                "L{level} ({}): {:?}",
+                files.len(),
+                files.iter().map(|x| self.file_list[x]).collect::<Vec<_>>()
+            );
+        }
+        if with_key {
+            self.check_keys();
+        }
+    }
+
+    pub fn dump_real_id(&self, always_show_l0: bool, with_key: bool) {
+        if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
+            println!(
+                "L0 ({}): {:?}",
+                self.snapshot.l0_sstables.len(),
+                self.snapshot.l0_sstables,
+            );
+        }
+        for (level, files) in &self.snapshot.levels {
+            println!("L{level} ({}): {:?}", files.len(), files);
+        }
+        if with_key {
+            self.check_keys();
+        }
+    }
+}
+
+fn generate_random_key_range() -> (KeyBytes, KeyBytes) {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    let begin: usize = rng.gen_range(0..(1 << 31));
+    let end: usize = begin + rng.gen_range((1 << 10)..(1 << 31));
+    let mut begin_bytes = BytesMut::new();
+    let mut end_bytes = BytesMut::new();
+    begin_bytes.put_u64(begin as u64);
+    end_bytes.put_u64(end as u64);
+    (
+        KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+        KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+    )
+}
+
+fn generate_random_split(
+    begin_bytes: KeyBytes,
+    end_bytes: KeyBytes,
+    split: usize,
+) -> Vec<(KeyBytes, KeyBytes)> {
+    let begin = begin_bytes.for_testing_key_ref().get_u64();
+    let end = end_bytes.for_testing_key_ref().get_u64();
+    let len = end - begin + 1;
+    let mut result = Vec::new();
+    let split = split as u64;
+    assert!(len >= split, "well, this is unfortunate... run again!");
+    for i in 0..split {
+        let nb = begin + len * i / split;
+        let ne = begin + len * (i + 1) / split - 1;
+        let mut begin_bytes = BytesMut::new();
+        let mut end_bytes = BytesMut::new();
+        begin_bytes.put_u64(nb);
+        end_bytes.put_u64(ne);
+        result.push((
+            KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+            KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+        ));
+    }
+    result
+}
+
+/// Simulates leveled compaction of `flushes`, which are the key ranges and the numbers of deletions of the
+/// flushed SSTs.
+fn simulate_leveled(
+    controller: &LeveledCompactionController,
+    flushes: &[(KeyBytes, KeyBytes, u64)],
+    level0_file_num_compaction_trigger: usize,
+    max_levels: usize,
+    sst_size_mb: usize,
+    dump_real_id: bool,
+    size_only: bool,
+) -> MockStorage {
+    let mut storage = MockStorage::new();
+    for i in 0..max_levels {
+        storage.snapshot.levels.push((i + 1, Vec::new()));
+    }
+    let mut max_space = 0;
+    for (i, (first_key, last_key, num_deletes)) in flushes.iter().enumerate() {
+        println!("=== Iteration {i} ===");
+        let id = storage.flush_sst_to_l0();
+        storage.snapshot.sstables.insert(
+            id,
+            Arc::new(
+                SsTable::create_meta_only(
+                    id,
+                    sst_size_mb as u64 * 1024 * 1024,
+                    first_key.clone(),
+                    last_key.clone(),
+                )
+                .with_num_deletes(*num_deletes),
+            ),
+        );
+        println!("--- After Flush ---");
+        if size_only {
+            storage.dump_size_only();
+        } else if dump_real_id {
+            storage.dump_real_id(false, true);
+        } else {
+            storage.dump_original_id(false, true);
+        }
+        let mut num_compactions = 0;
+        while let Some(task) = {
+            if !size_only {
+                println!("--- Compaction Task ---");
+            }
+            controller.generate_compaction_task(&storage.snapshot)
+        } {
+            let mut sst_ids = Vec::new();
+            let split_num = task.upper_level_sst_ids.len() + task.lower_level_sst_ids.len();
+            let mut first_keys = Vec::new();
+            let mut last_keys = Vec::new();
+            for file in task
+                .upper_level_sst_ids
+                .iter()
+                .chain(task.lower_level_sst_ids.iter())
+            {
+                first_keys.push(storage.snapshot.sstables[file].first_key().clone());
+                last_keys.push(storage.snapshot.sstables[file].last_key().clone());
+            }
+            let begin = first_keys.into_iter().min().unwrap();
+            let end = last_keys.into_iter().max().unwrap();
+            let splits = generate_random_split(begin, end, split_num);
+            // the deletions are spread over the outputs, and dropped at the bottom level
+            let num_deletes = if task.is_lower_level_bottom_level {
+                0
+            } else {
+                task.upper_level_sst_ids
+                    .iter()
+                    .chain(task.lower_level_sst_ids.iter())
+                    .map(|id| storage.snapshot.sstables[id].num_deletes())
+                    .sum::<u64>()
+                    / split_num as u64
+            };
+            for (id, file) in task
+                .upper_level_sst_ids
+                .iter()
+                .chain(task.lower_level_sst_ids.iter())
+                .enumerate()
+            {
+                let new_sst_id = storage.generate_sst_id();
+                sst_ids.push(new_sst_id);
+                storage.file_list.insert(new_sst_id, *file);
+                storage.total_writes += 1;
+                storage.snapshot.sstables.insert(
+                    new_sst_id,
+                    Arc::new(
+                        SsTable::create_meta_only(
+                            new_sst_id,
+                            sst_size_mb as u64 * 1024 * 1024,
+                            splits[id].0.clone(),
+                            splits[id].1.clone(),
+                        )
+                        .with_num_deletes(num_deletes),
+                    ),
+                );
+            }
+            print!(
+                "Upper L{} [{}] ",
+                task.upper_level.unwrap_or_default(),
+                task.upper_level_sst_ids
+                    .iter()
+                    .map(|id| format!(
+                        "{}.sst {:x}..={:x}",
+                        id,
+                        storage.snapshot.sstables[id]
+                            .first_key()
+                            .for_testing_key_ref()
+                            .get_u64(),
+                        storage.snapshot.sstables[id]
+                            .last_key()
+                            .for_testing_key_ref()
+                            .get_u64()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            print!(
+                "Lower L{} [{}] ",
+                task.lower_level,
+                task.lower_level_sst_ids
+                    .iter()
+                    .map(|id| format!(
+                        "{}.sst {:x}..={:x}",
+                        id,
+                        storage.snapshot.sstables[id]
+                            .first_key()
+                            .for_testing_key_ref()
+                            .get_u64(),
+                        storage.snapshot.sstables[id]
+                            .last_key()
+                            .for_testing_key_ref()
+                            .get_u64()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            println!(
+                "-> [{}]",
+                sst_ids
+                    .iter()
+                    .map(|id| format!(
+                        "{}.sst {:x}..={:x}",
+                        id,
+                        storage.snapshot.sstables[id]
+                            .first_key()
+                            .for_testing_key_ref()
+                            .get_u64(),
+                        storage.snapshot.sstables[id]
+                            .last_key()
+                            .for_testing_key_ref()
+                            .get_u64()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            max_space = max_space.max(storage.file_list.len());
+            let cursor = controller.compaction_cursor(
+                &storage.snapshot,
+                task.upper_level,
+                &task.upper_level_sst_ids,
+            );
+            let (snapshot, del) =
+                controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids, false);
+            storage.snapshot = snapshot;
+            if let Some((level, key)) = cursor {
+                storage.snapshot.compaction_cursors.insert(level, key);
+            }
+            for id in &del {
+                storage.snapshot.sstables.remove(id);
+            }
+            storage.remove(&del);
+            println!("--- After Compaction ---");
+            if size_only {
+                storage.dump_size_only();
+            } else if dump_real_id {
+                storage.dump_real_id(true, true);
+            } else {
+                storage.dump_original_id(true, true);
+            }
+            num_compactions += 1;
+            if num_compactions >= level0_file_num_compaction_trigger * max_levels * 2 {
+                panic!("compaction does not converge?");
+            }
+        }
+        if num_compactions == 0 {
+            println!("no compaction triggered");
+        } else {
+            println!("{num_compactions} compaction triggered in this iteration");
+        }
+        max_space = max_space.max(storage.file_list.len());
+        println!("--- Statistics ---");
+        println!(
+            "Write Amplification: {}/{}={:.3}x",
+            storage.total_writes,
+            storage.total_flushes,
+            storage.total_writes as f64 / storage.total_flushes as f64
+        );
+        println!(
+            "Maximum Space Usage: {}/{}={:.3}x",
+            max_space,
+            storage.total_flushes,
+            max_space as f64 / storage.total_flushes as f64
+        );
+        println!(
+            "Read Amplification: {}x",
+            storage.snapshot.l0_sstables.len()
+                + storage
+                    .snapshot
+                    .levels
+                    .iter()
+                    .filter(|(_, f)| !f.is_empty())
+                    .count()
+        );
+        println!();
+    }
+    storage
+}
+
+fn key_of_time(time: u64) -> KeyBytes {
+    let mut bytes = BytesMut::new();
+    bytes.put_u64(time);
+    KeyBytes::for_testing_from_bytes_no_ts(bytes.freeze())
+}
+
+fn main() {
+    let args = Args::parse();
+    match args {
+        Args::Tiered {
+            dump_real_id,
+            size_only,
+            num_tiers: level0_file_num_compaction_trigger,
+            max_size_amplification_percent,
+            size_ratio,
+            min_merge_width,
+            max_merge_width,
+            max_merge_mb,
+            iterations,
+            sst_size_mb,
+            random_flush_size,
+        } => {
+            use rand::Rng;
+            let controller = TieredCompactionController::new(TieredCompactionOptions {
+                num_tiers: level0_file_num_compaction_trigger,
+                max_size_amplification_percent,
+                size_ratio,
+                min_merge_width,
+                max_merge_width,
+            })
+            .with_max_merge_bytes(max_merge_mb.map(|size| size * 1024 * 1024));
+            let sst_size = sst_size_mb as u64 * 1024 * 1024;
+            let mut rng = rand::thread_rng();
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            let mut flushed_size = 0;
+            let mut written_size = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_new_tier();
+                let size = if random_flush_size {
+                    rng.gen_range(sst_size / 4..=sst_size * 7 / 4)
+                } else {
+                    sst_size
+                };
+                storage.add_sst_of_size(id, size);
+                flushed_size += size;
+                written_size += size;
+                println!("--- After Flush ---");
+                if size_only {
+                    storage.dump_size_mb_only();
+                } else if dump_real_id {
+                    storage.dump_real_id(false, false);
+                } else {
+                    storage.dump_original_id(false, false);
+                }
+                if !size_only {
+                    println!("--- Compaction Task ---");
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    if !size_only {
+                        println!("--- Compaction Task ---");
+                    }
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let input_files = task
+                        .tiers
+                        .iter()
+                        .flat_map(|(_, files)| files)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    // the inputs are rewritten into SSTs of the target size
+                    let mut remaining = input_files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].table_size())
+                        .sum::<u64>();
+                    written_size += remaining;
+                    let mut sst_ids = Vec::new();
+                    while remaining > 0 {
+                        let size = remaining.min(sst_size);
+                        remaining -= size;
+                        let new_sst_id = storage.generate_sst_id();
+                        let file = input_files[sst_ids.len().min(input_files.len() - 1)];
+                        sst_ids.push(new_sst_id);
+                        storage.file_list.insert(new_sst_id, file);
+                        storage.add_sst_of_size(new_sst_id, size);
+                        storage.total_writes += 1;
+                    }
+                    for (tier_id, files) in &task.tiers {
+                        print!("L{} {:?} ", tier_id, files);
+                    }
+                    println!("-> {:?}", sst_ids);
+                    max_space = max_space.max(storage.total_size());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    for id in &del {
+                        storage.snapshot.sstables.remove(id);
+                    }
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if size_only {
+                        storage.dump_size_mb_only();
+                    } else if dump_real_id {
+                        storage.dump_real_id(false, false);
+                    } else {
+                        storage.dump_original_id(false, false);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= level0_file_num_compaction_trigger * 3 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.total_size());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{} MB={:.3}x",
+                    written_size / 1024 / 1024,
+                    flushed_size / 1024 / 1024,
+                    written_size as f64 / flushed_size as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{} MB={:.3}x",
+                    max_space / 1024 / 1024,
+                    flushed_size / 1024 / 1024,
+                    max_space as f64 / flushed_size as f64
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                        + storage
+                            .snapshot
+                            .levels
+                            .iter()
+                            .filter(|(_, f)| !f.is_empty())
+                            .count()
+                );
+                println!();
+            }
+        }
+        Args::Leveled {
+            dump_real_id,
+            size_only,
+            level0_file_num_compaction_trigger,
+            level_size_multiplier,
+            max_levels,
+            base_level_size_mb,
+            iterations,
+            sst_size_mb,
+            sst_selection,
+            compare_sst_selection,
+        } => {
+            use rand::Rng;
+            let options = LeveledCompactionOptions {
+                level0_file_num_compaction_trigger,
+                level_size_multiplier,
+                max_levels,
+                base_level_size_mb,
+            };
+            let mut rng = rand::thread_rng();
+            let flushes = (0..iterations)
+                .map(|_| {
+                    let (first_key, last_key) = generate_random_key_range();
+                    (first_key, last_key, rng.gen_range(0..1000))
+                })
+                .collect::<Vec<_>>();
+            let selections = if compare_sst_selection {
+                SstSelection::value_variants().to_vec()
+            } else {
+                vec![sst_selection]
+            };
+            let mut results = Vec::new();
+            for selection in selections {
+                let controller = LeveledCompactionController::new(options.clone())
+                    .with_sst_selection(selection.into());
+                let storage = simulate_leveled(
+                    &controller,
+                    &flushes,
+                    level0_file_num_compaction_trigger,
+                    max_levels,
+                    sst_size_mb,
+                    dump_real_id,
+                    size_only,
+                );
+                results.push((selection, storage.total_writes, storage.total_flushes));
+            }
+            if compare_sst_selection {
+                println!("=== SST Selection Comparison ===");
+                for (selection, total_writes, total_flushes) in results {
+                    println!(
+                        "{:?}: Write Amplification: {}/{}={:.3}x",
+                        selection,
+                        total_writes,
+                        total_flushes,
+                        total_writes as f64 / total_flushes as f64
+                    );
+                }
+            }
+        }
+        Args::Fifo {
+            dump_real_id,
+            size_only,
+            max_table_files_size_mb,
+            ttl_secs,
+            intra_l0_min_merge_width,
+            intra_l0_max_merged_size_mb,
+            flush_interval_secs,
+            iterations,
+            sst_size_mb,
+        } => {
+            let clock = Arc::new(ManualClock::new(0));
+            let controller = FifoCompactionController::new(
+                FifoCompactionOptions {
+                    max_table_files_size: max_table_files_size_mb as u64 * 1024 * 1024,
+                    ttl: ttl_secs.map(Duration::from_secs),
+                    intra_l0_min_merge_width,
+                    intra_l0_max_merged_size: intra_l0_max_merged_size_mb as u64 * 1024 * 1024,
+                },
+                clock.clone(),
+            );
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                clock.advance(Duration::from_secs(flush_interval_secs));
+                let id = storage.flush_newest_sst_to_l0();
+                let (first_key, last_key) = generate_random_key_range();
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(
+                        SsTable::create_meta_only(
+                            id,
+                            sst_size_mb as u64 * 1024 * 1024,
+                            first_key,
+                            last_key,
+                        )
+                        .with_newest_write_time(clock.now()),
+                    ),
+                );
+                println!("--- After Flush ---");
+                if size_only {
+                    storage.dump_size_only();
+                } else if dump_real_id {
+                    storage.dump_real_id(true, false);
+                } else {
+                    storage.dump_original_id(true, false);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    if !size_only {
+                        println!("--- Compaction Task ---");
+                    }
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let mut sst_ids = Vec::new();
+                    if !task.merged_sst_ids.is_empty() {
+                        // the merged SSTs are written as one SST
+                        let merged_ssts = task
+                            .merged_sst_ids
+                            .iter()
+                            .map(|id| storage.snapshot.sstables[id].clone())
+                            .collect::<Vec<_>>();
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage
+                            .file_list
+                            .insert(new_sst_id, storage.file_list[&task.merged_sst_ids[0]]);
+                        storage.total_writes += merged_ssts.len();
+                        storage.snapshot.sstables.insert(
+                            new_sst_id,
+                            Arc::new(
+                                SsTable::create_meta_only(
+                                    new_sst_id,
+                                    merged_ssts.iter().map(|sst| sst.table_size()).sum(),
+                                    merged_ssts
+                                        .iter()
+                                        .map(|sst| sst.first_key())
+                                        .min()
+                                        .unwrap()
+                                        .clone(),
+                                    merged_ssts
+                                        .iter()
+                                        .map(|sst| sst.last_key())
+                                        .max()
+                                        .unwrap()
+                                        .clone(),
+                                )
+                                .with_newest_write_time(
+                                    merged_ssts
+                                        .iter()
+                                        .filter_map(|sst| sst.newest_write_time())
+                                        .max()
+                                        .unwrap(),
+                                ),
+                            ),
+                        );
+                    }
+                    println!(
+                        "Deleted {:?} Merged {:?} -> {:?}",
+                        task.deleted_sst_ids, task.merged_sst_ids, sst_ids
+                    );
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    for id in &del {
+                        storage.snapshot.sstables.remove(id);
+                    }
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if size_only {
+                        storage.dump_size_only();
+                    } else if dump_real_id {
+                        storage.dump_real_id(true, false);
+                    } else {
+                        storage.dump_original_id(true, false);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= iterations {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Total Size: {} MB",
+                    storage
+                        .snapshot
+                        .sstables
+                        .values()
+                        .map(|sst| sst.table_size())
+                        .sum::<u64>()
+                        / 1024
+                        / 1024
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                );
+                println!();
+            }
+        }
+        Args::TimeWindow {
+            dump_real_id,
+            size_only,
+            window_size_secs,
+            min_threshold,
+            max_threshold,
+            flush_interval_secs,
+            iterations,
+            sst_size_mb,
+        } => {
+            let controller = TimeWindowCompactionController::new(TimeWindowCompactionOptions {
+                time_extractor: Arc::new(BigEndianTimePrefix),
+                window_size: window_size_secs,
+                min_threshold,
+                max_threshold,
+            });
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                // each flush holds the keys written since the previous one
+                let id = storage.flush_sst_to_new_tier();
+                let begin = i as u64 * flush_interval_secs;
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(
+                        id,
+                        sst_size_mb as u64 * 1024 * 1024,
+                        key_of_time(begin),
+                        key_of_time(begin + flush_interval_secs - 1),
+                    )),
+                );
+                println!("--- After Flush ---");
+                if size_only {
+                    storage.dump_size_only();
+                } else if dump_real_id {
+                    storage.dump_real_id(false, false);
+                } else {
+                    storage.dump_original_id(false, false);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    if !size_only {
+                        println!("--- Compaction Task ---");
+                    }
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let ssts = task
+                        .sst_ids
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].clone())
+                        .collect::<Vec<_>>();
+                    let begin = ssts
+                        .iter()
+                        .map(|sst| sst.first_key().for_testing_key_ref().get_u64())
+                        .min()
+                        .unwrap();
+                    let end = ssts
+                        .iter()
+                        .map(|sst| sst.last_key().for_testing_key_ref().get_u64())
+                        .max()
+                        .unwrap();
+                    let size = ssts.iter().map(|sst| sst.table_size()).sum::<u64>();
+                    // the output is split at window boundaries, with sizes proportional to the keys in each window
+                    let mut sst_ids = Vec::new();
+                    let mut first = begin;
+                    while first <= end {
+                        let last = ((first / window_size_secs + 1) * window_size_secs - 1).min(end);
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage
+                            .file_list
+                            .insert(new_sst_id, storage.file_list[&task.sst_ids[0]]);
+                        storage.snapshot.sstables.insert(
+                            new_sst_id,
+                            Arc::new(SsTable::create_meta_only(
+                                new_sst_id,
+                                size * (last - first + 1) / (end - begin + 1),
+                                key_of_time(first),
+                                key_of_time(last),
+                            )),
+                        );
+                        first = last + 1;
+                    }
+                    storage.total_writes += ssts.len();
+                    println!("Window {} {:?} -> {:?}", task.window, task.sst_ids, sst_ids);
+                    max_space = max_space.max(storage.file_list.len());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    for id in &del {
+                        storage.snapshot.sstables.remove(id);
+                    }
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if size_only {
+                        storage.dump_size_only();
+                    } else if dump_real_id {
+                        storage.dump_real_id(false, false);
+                    } else {
+                        storage.dump_original_id(false, false);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= iterations {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.file_list.len());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {}/{}={:.3}x",
+                    storage.total_writes,
+                    storage.total_flushes,
+                    storage.total_writes as f64 / storage.total_flushes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {}/{}={:.3}x",
+                    max_space,
+                    storage.total_flushes,
+                    max_space as f64 / storage.total_flushes as f64
+                );
+                println!("Read Amplification: {}x", storage.snapshot.levels.len());
+                println!();
+            }
+        }
+    }
+}
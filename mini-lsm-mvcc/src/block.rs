@@ -15,17 +15,22 @@
 mod builder;
 mod iterator;
 
+use anyhow::{Result, anyhow, ensure};
 pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::iterators::EntryType;
+use crate::varint::put_varint;
+
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U32: usize = std::mem::size_of::<u32>();
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
 pub struct Block {
     pub(crate) data: Vec<u8>,
-    pub(crate) offsets: Vec<u16>,
+    pub(crate) offsets: Vec<u32>,
 }
 
 impl Block {
@@ -33,25 +38,78 @@ impl Block {
         let mut buf = self.data.clone();
         let offsets_len = self.offsets.len();
         for offset in &self.offsets {
-            buf.put_u16(*offset);
+            buf.put_u32(*offset);
         }
         // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        buf.put_u32(offsets_len as u32);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
         // get number of elements in the block
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        let entry_offsets_len = (&data[data.len() - SIZEOF_U32..]).get_u32() as usize;
+        let data_end = data.len() - SIZEOF_U32 - entry_offsets_len * SIZEOF_U32;
+        let offsets_raw = &data[data_end..data.len() - SIZEOF_U32];
         // get offset array
         let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
         // retrieve data
         let data = data[0..data_end].to_vec();
         Self { data, offsets }
     }
+
+    /// Decode a block written in the V1 SST format, with `u16` lengths, offsets and number of entries and no entry
+    /// types. The entries are re-encoded in the current format so that `BlockIterator` only needs to understand
+    /// one layout, and a `Put` of an empty value, which is a deletion in V1, becomes `EntryType::Delete`.
+    pub fn decode_legacy(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.len() >= SIZEOF_U16,
+            "corrupted legacy block: too short"
+        );
+        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        let data_end = entry_offsets_len
+            .checked_mul(SIZEOF_U16)
+            .and_then(|offsets_len| (data.len() - SIZEOF_U16).checked_sub(offsets_len))
+            .ok_or_else(|| anyhow!("corrupted legacy block: {} entries", entry_offsets_len))?;
+        let mut entries = &data[0..data_end];
+        let mut new_data = Vec::with_capacity(data_end + entry_offsets_len);
+        let mut offsets = Vec::with_capacity(entry_offsets_len);
+        while entries.has_remaining() {
+            offsets.push(new_data.len() as u32);
+            let overlap_len = take_legacy(&mut entries, SIZEOF_U16)?.get_u16();
+            let key_len = take_legacy(&mut entries, SIZEOF_U16)?.get_u16() as usize;
+            put_varint(&mut new_data, overlap_len as u64);
+            put_varint(&mut new_data, key_len as u64);
+            new_data.put(take_legacy(&mut entries, key_len)?);
+            new_data.put_u64(take_legacy(&mut entries, std::mem::size_of::<u64>())?.get_u64());
+            let value_len = take_legacy(&mut entries, SIZEOF_U16)?.get_u16() as usize;
+            let entry_type = match value_len {
+                0 => EntryType::Delete,
+                _ => EntryType::Put,
+            };
+            new_data.put_u8(entry_type as u8);
+            put_varint(&mut new_data, value_len as u64);
+            new_data.put(take_legacy(&mut entries, value_len)?);
+        }
+        ensure!(
+            offsets.len() == entry_offsets_len,
+            "corrupted legacy block: {} entries, expected {}",
+            offsets.len(),
+            entry_offsets_len
+        );
+        Ok(Self {
+            data: new_data,
+            offsets,
+        })
+    }
+}
+
+/// Splits the next `len` bytes off a legacy block.
+fn take_legacy<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    ensure!(buf.len() >= len, "corrupted legacy block: entry cut off");
+    let (taken, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(taken)
 }
@@ -17,8 +17,9 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use crate::{
-    block::SIZEOF_U16,
+    iterators::EntryType,
     key::{KeySlice, KeyVec},
+    varint::get_varint,
 };
 
 use super::Block;
@@ -31,6 +32,8 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the current value range in the block.data, corresponds to the current key
     value_range: (usize, usize),
+    /// the type of the current entry
+    entry_type: EntryType,
     /// the current index at the iterator position
     idx: usize,
     /// the first key in the block
@@ -39,9 +42,12 @@ pub struct BlockIterator {
 
 impl Block {
     fn get_first_key(&self) -> KeyVec {
+        if self.offsets.is_empty() {
+            return KeyVec::new();
+        }
         let mut buf = &self.data[..];
-        buf.get_u16();
-        let key_len = buf.get_u16() as usize;
+        get_varint(&mut buf);
+        let key_len = get_varint(&mut buf) as usize;
         let key = &buf[..key_len];
         buf.advance(key_len);
         KeyVec::from_vec_with_ts(key.to_vec(), buf.get_u64())
@@ -55,6 +61,7 @@ impl BlockIterator {
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
+            entry_type: EntryType::Put,
             idx: 0,
         }
     }
@@ -85,6 +92,12 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the type of the current entry.
+    pub fn entry_type(&self) -> EntryType {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.entry_type
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -117,10 +130,10 @@ impl BlockIterator {
     /// Index update will be handled by caller
     fn seek_to_offset(&mut self, offset: usize) {
         let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
+        // Since `get_varint()` will automatically move the ptr ahead here,
         // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
-        let key_len = entry.get_u16() as usize;
+        let overlap_len = get_varint(&mut entry) as usize;
+        let key_len = get_varint(&mut entry) as usize;
         let key = &entry[..key_len];
         self.key.clear();
         self.key.append(&self.first_key.key_ref()[..overlap_len]);
@@ -128,10 +141,9 @@ impl BlockIterator {
         entry.advance(key_len);
         let ts = entry.get_u64();
         self.key.set_ts(ts);
-        let value_len = entry.get_u16() as usize;
-        // REMEMBER TO CHANGE THIS every time you change the encoding!
-        let value_offset_begin =
-            offset + SIZEOF_U16 + SIZEOF_U16 + std::mem::size_of::<u64>() + key_len + SIZEOF_U16;
+        self.entry_type = EntryType::from_u8(entry.get_u8());
+        let value_len = get_varint(&mut entry) as usize;
+        let value_offset_begin = self.block.data.len() - entry.len();
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
         entry.advance(value_len);
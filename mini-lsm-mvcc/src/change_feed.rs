@@ -0,0 +1,422 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The change feed. `updates_since` reads the write batches committed after a ts from the WALs, which, unlike a
+//! `scan`, keep the deletions. The WAL of a flushed memtable is moved to the WAL archive instead of being removed
+//! while a registered consumer or an open `UpdateIterator` needs it, or while the `WalArchiveOptions` keep it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::iterators::EntryType;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::mvcc::watermark::Watermark;
+use crate::ttl::{decode_expiring_value, encode_expiring_value};
+use crate::wal::Wal;
+
+/// The directory of the WAL archive in the storage directory.
+const WAL_ARCHIVE_DIR: &str = "archive";
+
+/// How long the WALs of flushed memtables are kept in the WAL archive. The WALs a consumer needs are kept past
+/// both limits.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WalArchiveOptions {
+    /// Removes the archived WALs last written longer ago than this, no limit when `None`.
+    pub ttl: Option<Duration>,
+    /// Removes the oldest archived WALs while the archive is larger than this many bytes, no limit when `None`.
+    pub size_limit: Option<u64>,
+}
+
+/// A change made by a committed write batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Update {
+    Put {
+        key: Bytes,
+        value: Bytes,
+    },
+    /// A put by `put_with_ttl`, which expires at `expiry`, in milliseconds since the UNIX epoch.
+    PutWithExpiry {
+        key: Bytes,
+        value: Bytes,
+        expiry: u64,
+    },
+    Delete {
+        key: Bytes,
+    },
+    /// A deletion of the keys in `[start, end)`.
+    DeleteRange {
+        start: Bytes,
+        end: Bytes,
+    },
+    Merge {
+        key: Bytes,
+        operand: Bytes,
+    },
+}
+
+impl Update {
+    /// The record of the update in a WAL, as `(key, entry type, value)`.
+    pub(crate) fn into_record(self) -> (Bytes, EntryType, Bytes) {
+        match self {
+            Update::Put { key, value } => (key, EntryType::Put, value),
+            Update::PutWithExpiry { key, value, expiry } => (
+                key,
+                EntryType::ExpiringPut,
+                encode_expiring_value(expiry, &value).into(),
+            ),
+            Update::Delete { key } => (key, EntryType::Delete, Bytes::new()),
+            Update::DeleteRange { start, end } => (start, EntryType::RangeDelete, end),
+            Update::Merge { key, operand } => (key, EntryType::Merge, operand),
+        }
+    }
+}
+
+/// The changes made by a write batch or a transaction committed at `commit_ts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommittedBatch {
+    pub commit_ts: u64,
+    pub updates: Vec<Update>,
+}
+
+struct ArchivedWal {
+    /// The commit ts of the latest update in the WAL.
+    max_ts: u64,
+    size: u64,
+}
+
+#[derive(Default)]
+struct WalRetentionState {
+    /// The commit ts each registered consumer has read up to.
+    consumers: HashMap<String, u64>,
+    /// The ts the open `UpdateIterator`s read after.
+    readers: Watermark,
+    archived: BTreeMap<usize, ArchivedWal>,
+    /// The updates up to this ts may have been removed with their WALs.
+    truncated_ts: u64,
+}
+
+impl WalRetentionState {
+    /// The ts after which the updates are needed by a consumer or an iterator, if any.
+    fn needed_after(&self) -> Option<u64> {
+        self.consumers
+            .values()
+            .copied()
+            .chain(self.readers.watermark())
+            .min()
+    }
+}
+
+/// Which WALs of flushed memtables are kept for the change feed.
+#[derive(Default)]
+pub(crate) struct WalRetention {
+    state: Mutex<WalRetentionState>,
+}
+
+impl WalRetention {
+    /// Loads the WAL archive of the storage at `path`, whose memtables are those of `state`, and the consumers
+    /// recorded in the manifest.
+    pub(crate) fn open(
+        path: &Path,
+        state: &LsmStorageState,
+        last_commit_ts: u64,
+        consumers: HashMap<String, u64>,
+    ) -> Result<Self> {
+        let mut archived = BTreeMap::new();
+        // the ts of the first update in each WAL kept, from the archive and the memtables
+        let mut first_ts = BTreeMap::new();
+        let archive_dir = path.join(WAL_ARCHIVE_DIR);
+        if archive_dir.exists() {
+            for entry in std::fs::read_dir(&archive_dir)? {
+                let entry_path = entry?.path();
+                let Some(id) = entry_path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok())
+                else {
+                    continue;
+                };
+                let buf = std::fs::read(&entry_path)?;
+                let records = Wal::read_records(&buf)?;
+                let max_ts = records.iter().map(|(key, _, _)| key.ts()).max();
+                archived.insert(
+                    id,
+                    ArchivedWal {
+                        max_ts: max_ts.unwrap_or_default(),
+                        size: buf.len() as u64,
+                    },
+                );
+                first_ts.insert(id, records.first().map(|(key, _, _)| key.ts()));
+            }
+        }
+        for memtable in &state.imm_memtables {
+            let buf = std::fs::read(LsmStorageInner::path_of_wal_static(path, memtable.id()))?;
+            let records = Wal::read_records(&buf)?;
+            first_ts.insert(memtable.id(), records.first().map(|(key, _, _)| key.ts()));
+        }
+        // The WALs of the other flushed memtables are removed, so the updates before the oldest WAL kept may be
+        // gone.
+        let truncated_ts = match first_ts.into_values().flatten().next() {
+            Some(ts) => ts - 1,
+            None => last_commit_ts,
+        };
+        Ok(Self {
+            state: Mutex::new(WalRetentionState {
+                consumers,
+                archived,
+                truncated_ts,
+                ..Default::default()
+            }),
+        })
+    }
+}
+
+impl LsmStorageInner {
+    pub(crate) fn path_of_archived_wal(&self, id: usize) -> PathBuf {
+        self.path
+            .join(WAL_ARCHIVE_DIR)
+            .join(format!("{:05}.wal", id))
+    }
+
+    /// Registers a consumer of the change feed that has read the updates up to `ts`, or moves it to `ts` if it
+    /// is registered already. The WALs with the updates after `ts` are kept until the consumer moves past them,
+    /// across restarts.
+    pub fn register_update_consumer(&self, name: &str, ts: u64) -> Result<()> {
+        let state_lock = self.state_lock.lock();
+        let mut state = self.wal_retention.state.lock();
+        if ts < state.truncated_ts {
+            bail!(
+                "the updates after {} are no longer kept, the WALs up to {} are removed",
+                ts,
+                state.truncated_ts
+            );
+        }
+        self.manifest().add_record(
+            &state_lock,
+            ManifestRecord::UpdateConsumer(name.to_string(), Some(ts)),
+        )?;
+        state.consumers.insert(name.to_string(), ts);
+        Ok(())
+    }
+
+    /// Unregisters the consumer named `name`, returns false if there is none. The WALs only it needs are removed
+    /// on the next flush.
+    pub fn unregister_update_consumer(&self, name: &str) -> Result<bool> {
+        let state_lock = self.state_lock.lock();
+        let mut state = self.wal_retention.state.lock();
+        if !state.consumers.contains_key(name) {
+            return Ok(false);
+        }
+        self.manifest().add_record(
+            &state_lock,
+            ManifestRecord::UpdateConsumer(name.to_string(), None),
+        )?;
+        state.consumers.remove(name);
+        Ok(true)
+    }
+
+    /// Returns the write batches committed after `ts` in the order of their commit ts. The batches committed
+    /// when this is called are returned, and the WALs they are read from are kept until the iterator is dropped.
+    pub fn updates_since(self: &Arc<Self>, ts: u64) -> Result<UpdateIterator> {
+        if !self.options.enable_wal {
+            bail!("the change feed is read from the WALs, which are disabled");
+        }
+        {
+            let mut state = self.wal_retention.state.lock();
+            if ts < state.truncated_ts {
+                bail!(
+                    "the updates after {} are no longer kept, the WALs up to {} are removed",
+                    ts,
+                    state.truncated_ts
+                );
+            }
+            state.readers.add_reader(ts);
+        }
+        let mut iter = UpdateIterator {
+            storage: self.clone(),
+            since: ts,
+            until: self.mvcc().latest_commit_ts(),
+            wal_ids: VecDeque::new(),
+            batches: VecDeque::new(),
+        };
+        // The WAL of a memtable is listed before it is flushed, and the archive is listed after the flush, so
+        // none of the WALs are missed.
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        let mut wal_ids = BTreeSet::new();
+        for memtable in std::iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables) {
+            // the WAL may not be written out yet with `WalSyncPolicy::Never`
+            memtable.sync_wal()?;
+            wal_ids.insert(memtable.id());
+        }
+        wal_ids.extend(self.wal_retention.state.lock().archived.keys());
+        iter.wal_ids = wal_ids.into_iter().collect();
+        Ok(iter)
+    }
+
+    /// Moves the WAL of the flushed memtable `id`, whose latest update is at `max_ts`, to the WAL archive if it
+    /// is kept, or removes it. The archived WALs no longer kept are removed as well.
+    pub(crate) fn retire_wal(&self, id: usize, max_ts: u64) -> Result<()> {
+        let mut state = self.wal_retention.state.lock();
+        let path = self.path_of_wal(id);
+        let needed = state.needed_after().is_some_and(|ts| max_ts > ts);
+        if needed || self.options.wal_archive.is_some() {
+            let archive_dir = self.path.join(WAL_ARCHIVE_DIR);
+            std::fs::create_dir_all(&archive_dir)?;
+            let size = std::fs::metadata(&path)?.len();
+            std::fs::rename(&path, self.path_of_archived_wal(id))?;
+            File::open(&archive_dir)?.sync_all()?;
+            state.archived.insert(id, ArchivedWal { max_ts, size });
+        } else {
+            std::fs::remove_file(&path)?;
+            state.truncated_ts = state.truncated_ts.max(max_ts);
+        }
+        self.purge_wal_archive(&mut state)
+    }
+
+    /// Removes the oldest archived WALs that are neither needed nor kept by the `WalArchiveOptions`.
+    fn purge_wal_archive(&self, state: &mut WalRetentionState) -> Result<()> {
+        let needed_after = state.needed_after();
+        let mut archive_size = state.archived.values().map(|wal| wal.size).sum::<u64>();
+        while let Some(entry) = state.archived.first_entry() {
+            let (id, wal) = (*entry.key(), entry.get());
+            if needed_after.is_some_and(|ts| wal.max_ts > ts) {
+                break;
+            }
+            let path = self.path_of_archived_wal(id);
+            let expired = match &self.options.wal_archive {
+                None => true,
+                Some(options) => {
+                    let too_large = options.size_limit.is_some_and(|limit| archive_size > limit);
+                    let too_old = match options.ttl {
+                        Some(ttl) => {
+                            let modified = std::fs::metadata(&path)?.modified()?;
+                            modified.elapsed().unwrap_or_default() >= ttl
+                        }
+                        None => false,
+                    };
+                    too_large || too_old
+                }
+            };
+            if !expired {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            archive_size -= wal.size;
+            state.truncated_ts = state.truncated_ts.max(wal.max_ts);
+            entry.remove();
+        }
+        Ok(())
+    }
+}
+
+/// Iterates over the write batches committed after a ts, created by `updates_since`.
+pub struct UpdateIterator {
+    storage: Arc<LsmStorageInner>,
+    since: u64,
+    /// The latest commit ts when the iterator was created.
+    until: u64,
+    /// The WALs not read yet.
+    wal_ids: VecDeque<usize>,
+    /// The batches read from the last WAL and not returned yet.
+    batches: VecDeque<CommittedBatch>,
+}
+
+impl UpdateIterator {
+    fn read_wal(&self, id: usize) -> Result<Vec<CommittedBatch>> {
+        // a flush may move the WAL to the archive at any time
+        let buf = match std::fs::read(self.storage.path_of_wal(id)) {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                match std::fs::read(self.storage.path_of_archived_wal(id)) {
+                    // the WAL is only removed if its updates are up to `since`
+                    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                    buf => buf?,
+                }
+            }
+            buf => buf?,
+        };
+        let mut batches: Vec<CommittedBatch> = Vec::new();
+        for (key, entry_type, value) in Wal::read_records(&buf)? {
+            let commit_ts = key.ts();
+            if commit_ts <= self.since || commit_ts > self.until {
+                continue;
+            }
+            let key = key.into_inner();
+            let update = match entry_type {
+                EntryType::Put => Update::Put { key, value },
+                EntryType::ExpiringPut => {
+                    let (expiry, value) = decode_expiring_value(&value);
+                    Update::PutWithExpiry {
+                        key,
+                        value: Bytes::copy_from_slice(value),
+                        expiry,
+                    }
+                }
+                EntryType::Delete => Update::Delete { key },
+                EntryType::RangeDelete => Update::DeleteRange {
+                    start: key,
+                    end: value,
+                },
+                EntryType::Merge => Update::Merge {
+                    key,
+                    operand: value,
+                },
+                EntryType::ValuePointer => bail!("value pointer in WAL {}", id),
+            };
+            match batches.last_mut() {
+                Some(batch) if batch.commit_ts == commit_ts => batch.updates.push(update),
+                _ => batches.push(CommittedBatch {
+                    commit_ts,
+                    updates: vec![update],
+                }),
+            }
+        }
+        Ok(batches)
+    }
+}
+
+impl Iterator for UpdateIterator {
+    type Item = Result<CommittedBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.batches.is_empty() {
+            let id = self.wal_ids.pop_front()?;
+            match self.read_wal(id) {
+                Ok(batches) => self.batches = batches.into(),
+                Err(err) => {
+                    self.wal_ids.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+        self.batches.pop_front().map(Ok)
+    }
+}
+
+impl Drop for UpdateIterator {
+    fn drop(&mut self) {
+        let mut state = self.storage.wal_retention.state.lock();
+        state.readers.remove_reader(self.since);
+    }
+}
@@ -12,40 +12,71 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod fifo;
+mod filter;
 mod leveled;
 mod simple_leveled;
 mod tiered;
+mod time_window;
+mod tombstone;
 
 use std::collections::HashSet;
+use std::ops::Bound;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
-pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+pub use fifo::{FifoCompactionController, FifoCompactionOptions, FifoCompactionTask};
+pub use filter::{CompactionDecision, CompactionFilter, PrefixCompactionFilter};
+pub use leveled::{
+    LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask,
+    SstSelectionPolicy,
+};
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
+pub use time_window::{
+    BigEndianTimePrefix, KeyTimeExtractor, TimeWindowCompactionController,
+    TimeWindowCompactionOptions, TimeWindowCompactionTask,
+};
+pub use tombstone::TombstoneCompactionOptions;
+pub(crate) use tombstone::TombstoneTracker;
 
-use crate::iterators::StorageIterator;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::iterators::{EntryType, StorageIterator};
+use crate::key::{self, KeySlice, KeyVec};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState, range_overlap};
 use crate::manifest::ManifestRecord;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::rate_limiter::IoPriority;
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::ttl::{decode_expiring_value, encode_expiring_value, is_expired, user_value};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
     Simple(SimpleLeveledCompactionTask),
+    Fifo(FifoCompactionTask),
+    TimeWindow(TimeWindowCompactionTask),
     ForceFullCompaction {
         l0_sstables: Vec<usize>,
         l1_sstables: Vec<usize>,
     },
+    /// Moves SSTs to the lower level without rewriting them, which is how a leveled task whose upper SSTs
+    /// overlap neither the lower level nor each other is run.
+    TrivialMove {
+        // if upper_level is `None`, then it moves L0 SSTs
+        upper_level: Option<usize>,
+        // sorted by first key
+        sst_ids: Vec<usize>,
+        lower_level: usize,
+    },
 }
 
 impl CompactionTask {
@@ -55,7 +86,220 @@ impl CompactionTask {
             CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
             CompactionTask::Simple(task) => task.is_lower_level_bottom_level,
             CompactionTask::Tiered(task) => task.bottom_tier_included,
+            // the merged SSTs are the newest ones, and the older SSTs below them are not read
+            CompactionTask::Fifo(_) => false,
+            CompactionTask::TimeWindow(task) => task.covers_key_range,
+            CompactionTask::TrivialMove { .. } => false,
+        }
+    }
+
+    /// The level the task writes to, where 1 is the first level (or tier) below L0.
+    fn output_level(&self, snapshot: &LsmStorageState) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(task) => {
+                let first_tier = task.tiers[0].0;
+                snapshot
+                    .levels
+                    .iter()
+                    .position(|(tier_id, _)| *tier_id == first_tier)
+                    .unwrap()
+                    + 1
+            }
+            CompactionTask::TimeWindow(task) => {
+                snapshot
+                    .levels
+                    .iter()
+                    .position(|(_, tier_sst_ids)| {
+                        tier_sst_ids.iter().any(|id| task.sst_ids.contains(id))
+                    })
+                    .unwrap()
+                    + 1
+            }
+            CompactionTask::TrivialMove { lower_level, .. } => *lower_level,
+            CompactionTask::Fifo(_) => 0,
+        }
+    }
+
+    /// The SSTs of the level below the output level of a leveled task, sorted by first key.
+    fn grandparent_sst_ids(&self, snapshot: &LsmStorageState) -> Vec<usize> {
+        match self {
+            CompactionTask::Leveled(LeveledCompactionTask { lower_level, .. })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask { lower_level, .. }) => snapshot
+                .levels
+                .get(*lower_level)
+                .map(|(_, sst_ids)| sst_ids.clone())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The SSTs the task reads.
+    fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => upper_level_sst_ids
+                .iter()
+                .chain(lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, tier_sst_ids)| tier_sst_ids)
+                .copied()
+                .collect(),
+            // a trivial move reads no SSTs
+            CompactionTask::TrivialMove { .. } => Vec::new(),
+            CompactionTask::Fifo(task) => task.merged_sst_ids.clone(),
+            CompactionTask::TimeWindow(task) => task.sst_ids.clone(),
+        }
+    }
+
+    /// The newest write time of the SSTs the task reads, or 0 if none is known.
+    fn newest_write_time(&self, snapshot: &LsmStorageState) -> u64 {
+        self.input_sst_ids()
+            .iter()
+            .filter_map(|id| snapshot.sstables[id].newest_write_time())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The SSTs the task reads or moves.
+    fn sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::TrivialMove { sst_ids, .. } => sst_ids.clone(),
+            CompactionTask::Fifo(task) => task
+                .deleted_sst_ids
+                .iter()
+                .chain(&task.merged_sst_ids)
+                .copied()
+                .collect(),
+            _ => self.input_sst_ids(),
+        }
+    }
+
+    /// The levels the task reads and writes, where 0 is L0. A tiered or time-window task has none.
+    fn levels(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => vec![0, 1],
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                lower_level,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                lower_level,
+                ..
+            })
+            | CompactionTask::TrivialMove {
+                upper_level,
+                lower_level,
+                ..
+            } => vec![upper_level.unwrap_or(0), *lower_level],
+            CompactionTask::Fifo(_) => vec![0],
+            CompactionTask::Tiered(_) | CompactionTask::TimeWindow(_) => Vec::new(),
+        }
+    }
+
+    /// The trivial move of a leveled task, if the SSTs of its upper level overlap neither the lower level nor
+    /// each other.
+    fn trivial_move(
+        snapshot: &LsmStorageState,
+        upper_level: Option<usize>,
+        upper_level_sst_ids: &[usize],
+        lower_level: usize,
+        lower_level_sst_ids: &[usize],
+    ) -> Option<CompactionTask> {
+        if upper_level_sst_ids.is_empty() || !lower_level_sst_ids.is_empty() {
+            return None;
+        }
+        let mut sst_ids = upper_level_sst_ids.to_vec();
+        sst_ids.sort_by(|x, y| {
+            snapshot.sstables[x]
+                .first_key()
+                .cmp(snapshot.sstables[y].first_key())
+        });
+        let disjoint = sst_ids.windows(2).all(|pair| {
+            snapshot.sstables[&pair[0]].last_key() < snapshot.sstables[&pair[1]].first_key()
+        });
+        disjoint.then_some(CompactionTask::TrivialMove {
+            upper_level,
+            sst_ids,
+            lower_level,
+        })
+    }
+}
+
+/// The SSTs and levels of the running compaction tasks. The controllers generate tasks that do not conflict with
+/// them, so that tasks on different levels run concurrently.
+#[derive(Debug, Default)]
+pub(crate) struct RunningCompactions {
+    sst_ids: HashSet<usize>,
+    levels: HashSet<usize>,
+    num_tasks: usize,
+}
+
+impl RunningCompactions {
+    pub fn is_empty(&self) -> bool {
+        self.num_tasks == 0
+    }
+
+    /// Whether a running task reads or writes `level`, where 0 is L0.
+    pub fn is_level_busy(&self, level: usize) -> bool {
+        self.levels.contains(&level)
+    }
+
+    /// Whether `task` reads or writes an SST or a level of a running task. A task without levels conflicts with
+    /// every running task.
+    pub fn conflicts(&self, task: &CompactionTask) -> bool {
+        let levels = task.levels();
+        (levels.is_empty() && !self.is_empty())
+            || levels.iter().any(|level| self.is_level_busy(*level))
+            || task.sst_ids().iter().any(|id| self.sst_ids.contains(id))
+    }
+
+    pub fn add(&mut self, task: &CompactionTask) {
+        for sst_id in task.sst_ids() {
+            assert!(
+                self.sst_ids.insert(sst_id),
+                "{}.sst is being compacted",
+                sst_id
+            );
         }
+        for level in task.levels() {
+            assert!(
+                self.levels.insert(level),
+                "level {} is being compacted",
+                level
+            );
+        }
+        self.num_tasks += 1;
+    }
+
+    pub fn remove(&mut self, task: &CompactionTask) {
+        for sst_id in task.sst_ids() {
+            self.sst_ids.remove(&sst_id);
+        }
+        for level in task.levels() {
+            self.levels.remove(&level);
+        }
+        self.num_tasks -= 1;
     }
 }
 
@@ -63,21 +307,78 @@ pub(crate) enum CompactionController {
     Leveled(LeveledCompactionController),
     Tiered(TieredCompactionController),
     Simple(SimpleLeveledCompactionController),
+    Fifo(FifoCompactionController),
+    TimeWindow(TimeWindowCompactionController),
     NoCompaction,
 }
 
 impl CompactionController {
-    pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(|task| {
+                    CompactionTask::trivial_move(
+                        snapshot,
+                        task.upper_level,
+                        &task.upper_level_sst_ids,
+                        task.lower_level,
+                        &task.lower_level_sst_ids,
+                    )
+                    .unwrap_or(CompactionTask::Leveled(task))
+                }),
+            CompactionController::Simple(ctrl) => ctrl
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(|task| {
+                    CompactionTask::trivial_move(
+                        snapshot,
+                        task.upper_level,
+                        &task.upper_level_sst_ids,
+                        task.lower_level,
+                        &task.lower_level_sst_ids,
+                    )
+                    .unwrap_or(CompactionTask::Simple(task))
+                }),
+            CompactionController::Tiered(ctrl) => ctrl
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(CompactionTask::Tiered),
+            CompactionController::Fifo(ctrl) => ctrl
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(CompactionTask::Fifo),
+            CompactionController::TimeWindow(ctrl) => ctrl
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(CompactionTask::TimeWindow),
+            CompactionController::NoCompaction => unreachable!(),
+        }
+    }
+
+    /// Generates the task of `compact_range` that compacts the SSTs of `level` (0 for L0) overlapping the range
+    /// into the level below. Tiered compaction compacts the tiers from the first one overlapping the range at
+    /// once, so it only has a task for level 0. FIFO compaction has no level below L0, and time-window compaction
+    /// never merges across windows, so they have no task.
+    pub fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        level: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<CompactionTask> {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl
+                .generate_range_compaction_task(snapshot, level, lower, upper)
                 .map(CompactionTask::Leveled),
             CompactionController::Simple(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_range_compaction_task(snapshot, level, lower, upper)
                 .map(CompactionTask::Simple),
+            CompactionController::Tiered(_) if level > 0 => None,
             CompactionController::Tiered(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_range_compaction_task(snapshot, lower, upper)
                 .map(CompactionTask::Tiered),
+            CompactionController::Fifo(_) | CompactionController::TimeWindow(_) => None,
             CompactionController::NoCompaction => unreachable!(),
         }
     }
@@ -99,18 +400,115 @@ impl CompactionController {
             (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
+            (CompactionController::Fifo(ctrl), CompactionTask::Fifo(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            (CompactionController::TimeWindow(ctrl), CompactionTask::TimeWindow(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            (
+                CompactionController::Leveled(_) | CompactionController::Simple(_),
+                CompactionTask::TrivialMove {
+                    upper_level,
+                    sst_ids,
+                    lower_level,
+                },
+            ) => {
+                let snapshot =
+                    apply_trivial_move(snapshot, *upper_level, sst_ids, *lower_level, in_recovery);
+                (snapshot, Vec::new())
+            }
             _ => unreachable!(),
         }
     }
 }
 
 impl CompactionController {
+    /// The compaction cursor a task moves, as `(level, key)`.
+    pub fn compaction_cursor(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Option<(usize, Bytes)> {
+        match (self, task) {
+            (CompactionController::Leveled(ctrl), CompactionTask::Leveled(task)) => {
+                ctrl.compaction_cursor(snapshot, task.upper_level, &task.upper_level_sst_ids)
+            }
+            (
+                CompactionController::Leveled(ctrl),
+                CompactionTask::TrivialMove {
+                    upper_level,
+                    sst_ids,
+                    ..
+                },
+            ) => ctrl.compaction_cursor(snapshot, *upper_level, sst_ids),
+            _ => None,
+        }
+    }
+
     pub fn flush_to_l0(&self) -> bool {
         matches!(
             self,
-            Self::Leveled(_) | Self::Simple(_) | Self::NoCompaction
+            Self::Leveled(_) | Self::Simple(_) | Self::Fifo(_) | Self::NoCompaction
         )
     }
+
+    /// Whether tombstone compaction can move SSTs into the level (or tier) below.
+    pub fn compacts_tombstones(&self) -> bool {
+        matches!(self, Self::Leveled(_) | Self::Simple(_) | Self::Tiered(_))
+    }
+}
+
+/// Moves the SSTs of a trivial move to the lower level. The lower level is sorted by first key, except during
+/// recovery, where the SSTs are not loaded yet.
+fn apply_trivial_move(
+    snapshot: &LsmStorageState,
+    upper_level: Option<usize>,
+    sst_ids: &[usize],
+    lower_level: usize,
+    in_recovery: bool,
+) -> LsmStorageState {
+    let mut snapshot = snapshot.clone();
+    let mut sst_ids_to_move = sst_ids.iter().copied().collect::<HashSet<_>>();
+    let upper_level_ssts = match upper_level {
+        Some(upper_level) => &mut snapshot.levels[upper_level - 1].1,
+        None => &mut snapshot.l0_sstables,
+    };
+    upper_level_ssts.retain(|x| !sst_ids_to_move.remove(x));
+    assert!(sst_ids_to_move.is_empty(), "sst mismatched");
+    let mut lower_level_ssts = std::mem::take(&mut snapshot.levels[lower_level - 1].1);
+    lower_level_ssts.extend(sst_ids);
+    if !in_recovery {
+        lower_level_ssts.sort_by(|x, y| {
+            snapshot.sstables[x]
+                .first_key()
+                .cmp(snapshot.sstables[y].first_key())
+        });
+    }
+    snapshot.levels[lower_level - 1].1 = lower_level_ssts;
+    snapshot
+}
+
+/// The SSTs in `sst_ids` whose key ranges overlap the range.
+fn overlapping_ssts(
+    snapshot: &LsmStorageState,
+    sst_ids: &[usize],
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) -> Vec<usize> {
+    sst_ids
+        .iter()
+        .filter(|id| {
+            let sst = &snapshot.sstables[*id];
+            range_overlap(
+                lower,
+                upper,
+                sst.first_key().as_key_slice(),
+                sst.last_key().as_key_slice(),
+            )
+        })
+        .copied()
+        .collect()
 }
 
 #[derive(Debug, Clone)]
@@ -122,25 +520,225 @@ pub enum CompactionOptions {
     Tiered(TieredCompactionOptions),
     /// Simple leveled compaction
     Simple(SimpleLeveledCompactionOptions),
+    /// FIFO compaction, which deletes the oldest SSTs (= RocksDB's FIFO compaction)
+    Fifo(FifoCompactionOptions),
+    /// Time-window compaction for time-series keys, which only merges SSTs in the same time window of their keys
+    /// (= Cassandra's time window compaction strategy)
+    TimeWindow(TimeWindowCompactionOptions),
     /// In no compaction mode (week 1), always flush to L0
     NoCompaction,
 }
 
+/// The merge operands of a key below the watermark in a compaction, oldest first.
+struct MergeOperands {
+    /// The key of the latest operand.
+    key: KeyVec,
+    operands: Vec<Bytes>,
+    /// The value below the operands, which is `Some(None)` if the key does not exist, or `None` if unknown.
+    existing: Option<Option<Bytes>>,
+    /// The version below the operands if it is written as it is, which is the case if its value is in a value
+    /// log or expires, as the operands outlive its expiry.
+    kept_base: Option<(KeyVec, EntryType, Bytes)>,
+}
+
+/// How many bytes of the grandparent level, the level below the output level of a compaction, the current output
+/// SST overlaps. An output SST is cut once it overlaps too many of them (= LevelDB's `ShouldStopBefore`), so that
+/// compacting it into the grandparent level later does not read a large part of that level.
+struct GrandparentOverlap {
+    /// The grandparent SSTs, sorted by first key.
+    ssts: Vec<Arc<SsTable>>,
+    /// The first grandparent SST whose last key is not below the current key.
+    index: usize,
+    /// The size of the grandparent SSTs the current output SST has passed.
+    overlapped_bytes: u64,
+    /// Whether the current output SST has a key, as the grandparent SSTs before its first key do not overlap it.
+    seen_key: bool,
+    max_overlapped_bytes: u64,
+}
+
+impl GrandparentOverlap {
+    fn new(
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        max_overlapped_bytes: Option<u64>,
+    ) -> Self {
+        let ssts = match max_overlapped_bytes {
+            Some(_) => task
+                .grandparent_sst_ids(snapshot)
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect(),
+            None => Vec::new(),
+        };
+        Self {
+            ssts,
+            index: 0,
+            overlapped_bytes: 0,
+            seen_key: false,
+            max_overlapped_bytes: max_overlapped_bytes.unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Whether the current output SST should be cut before `key`, which is the next key written to it.
+    fn should_stop_before(&mut self, key: &[u8]) -> bool {
+        while self.index < self.ssts.len() && key > self.ssts[self.index].last_key().key_ref() {
+            if self.seen_key {
+                self.overlapped_bytes += self.ssts[self.index].table_size();
+            }
+            self.index += 1;
+        }
+        self.seen_key = true;
+        self.overlapped_bytes > self.max_overlapped_bytes
+    }
+
+    /// Starts a new output SST.
+    fn reset(&mut self) {
+        self.overlapped_bytes = 0;
+    }
+}
+
 impl LsmStorageInner {
+    /// Adds the parts of `range_tombstones` within `[lower, upper)` to an output SST of a compaction, so that
+    /// the key ranges of the output SSTs do not overlap.
+    fn add_range_tombstones(
+        builder: &mut SsTableBuilder,
+        range_tombstones: &[RangeTombstone],
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) {
+        for tombstone in range_tombstones {
+            if let Some(tombstone) = tombstone.clip(lower, upper) {
+                builder.add_range_tombstone(tombstone);
+            }
+        }
+    }
+
+    /// Whether a range tombstone below the watermark deletes `key`, where the keys are looked up in increasing
+    /// order from `cursor`.
+    fn is_range_deleted(
+        range_tombstones: &FragmentedRangeTombstones,
+        cursor: &mut usize,
+        key: KeySlice,
+    ) -> bool {
+        !range_tombstones.is_empty()
+            && range_tombstones.deletes_from_cursor(cursor, key.key_ref(), key.ts(), u64::MAX)
+    }
+
+    /// Returns the range tombstones below the watermark, which delete the versions they cover for every
+    /// reader, and the range tombstones to write to the output SSTs. At the bottom level, nothing older is left
+    /// for the tombstones below the watermark to delete after the compaction.
+    fn split_range_tombstones(
+        range_tombstones: Vec<RangeTombstone>,
+        watermark: u64,
+        compact_to_bottom_level: bool,
+    ) -> (FragmentedRangeTombstones, Vec<RangeTombstone>) {
+        let below_watermark = FragmentedRangeTombstones::new(
+            &range_tombstones
+                .iter()
+                .filter(|tombstone| tombstone.ts <= watermark)
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        let to_keep = if compact_to_bottom_level {
+            range_tombstones
+                .into_iter()
+                .filter(|tombstone| tombstone.ts > watermark)
+                .collect()
+        } else {
+            range_tombstones
+        };
+        (below_watermark, to_keep)
+    }
+
+    /// Folds merge operands into the entries to write. The operands are applied to the value below them if it
+    /// is known, which is the case if it is inline or deleted, or if nothing older is left at the bottom level.
+    /// Otherwise they are combined into one operand, followed by the value below them if it is kept.
+    fn fold_merge_operands(
+        &self,
+        merge_operands: MergeOperands,
+    ) -> Result<Vec<(KeyVec, EntryType, Bytes)>> {
+        let merge_operator = self.merge_operator()?;
+        let MergeOperands {
+            key,
+            operands,
+            existing,
+            kept_base,
+        } = merge_operands;
+        let operands = operands.iter().map(|x| &x[..]).collect::<Vec<_>>();
+        let mut entries = Vec::with_capacity(2);
+        match existing {
+            Some(existing) => {
+                let value =
+                    merge_operator.full_merge(key.key_ref(), existing.as_deref(), &operands)?;
+                entries.push((key, EntryType::Put, value));
+            }
+            None => {
+                let value = merge_operator.partial_merge_all(key.key_ref(), &operands)?;
+                entries.push((key, EntryType::Merge, value));
+            }
+        }
+        if let Some(base) = kept_base {
+            entries.push(base);
+        }
+        Ok(entries)
+    }
+
+    /// Applies the compaction filters in order to the latest version below the watermark of a key.
+    fn apply_compaction_filters(
+        compaction_filters: &[Arc<dyn CompactionFilter>],
+        output_level: usize,
+        key: KeySlice,
+        value: Bytes,
+    ) -> CompactionDecision {
+        let mut changed_value = None;
+        for filter in compaction_filters {
+            let value = changed_value.as_ref().unwrap_or(&value);
+            match filter.filter(output_level, key.key_ref(), value, key.ts()) {
+                CompactionDecision::Keep => {}
+                CompactionDecision::Remove => return CompactionDecision::Remove,
+                CompactionDecision::ChangeValue(value) => changed_value = Some(value),
+            }
+        }
+        changed_value.map_or(CompactionDecision::Keep, CompactionDecision::ChangeValue)
+    }
+
     fn compact_generate_sst_from_iter(
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
-        compact_to_bottom_level: bool,
+        range_tombstones: Vec<RangeTombstone>,
+        task: &CompactionTask,
+        output_level: usize,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
     ) -> Result<Vec<Arc<SsTable>>> {
+        let compact_to_bottom_level = task.compact_to_bottom_level();
         let mut builder = None;
         let mut new_sst = Vec::new();
         let watermark = self.mvcc().watermark();
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
-        'outer: while iter.is_valid() {
+        let snapshot = self.state.read().clone();
+        let now = self.options.clock.now();
+        // The outputs hold the newest writes of the inputs, which are in the state until the task is applied.
+        let newest_write_time = task.newest_write_time(&snapshot);
+        let (range_tombstones_below_watermark, range_tombstones_to_keep) =
+            Self::split_range_tombstones(range_tombstones, watermark, compact_to_bottom_level);
+        let mut range_tombstone_cursor = 0;
+        // The first key of the current output SST.
+        let mut sst_lower = lower.map(<[u8]>::to_vec);
+        // The time window of the current output SST, if the outputs are split by time window.
+        let mut sst_window = None;
+        let mut grandparent_overlap =
+            GrandparentOverlap::new(&snapshot, task, self.options.max_grandparent_overlap_bytes);
+        while iter.is_valid() {
+            if let Some(upper) = upper
+                && iter.key().key_ref() >= upper
+            {
+                break;
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(IoPriority::Low, newest_write_time));
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -148,10 +746,21 @@ impl LsmStorageInner {
                 first_key_below_watermark = true;
             }
 
+            if Self::is_range_deleted(
+                &range_tombstones_below_watermark,
+                &mut range_tombstone_cursor,
+                iter.key(),
+            ) {
+                iter.next()?;
+                continue;
+            }
+
+            // An expired value reads as a deletion at any ts, so it is written as one.
+            let expired = is_expired(iter.entry_type(), iter.value(), now);
             if compact_to_bottom_level
                 && !same_as_last_key
                 && iter.key().ts() <= watermark
-                && iter.value().is_empty()
+                && (iter.entry_type() == EntryType::Delete || expired)
             {
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
@@ -160,6 +769,8 @@ impl LsmStorageInner {
                 continue;
             }
 
+            // The entry type and value to write instead of the current ones, set by the compaction filters.
+            let mut filtered = None;
             if iter.key().ts() <= watermark {
                 if !first_key_below_watermark {
                     iter.next()?;
@@ -168,61 +779,298 @@ impl LsmStorageInner {
 
                 first_key_below_watermark = false;
 
-                if !compaction_filters.is_empty() {
-                    for filter in &compaction_filters {
-                        match filter {
-                            CompactionFilter::Prefix(x) => {
-                                if iter.key().key_ref().starts_with(x) {
-                                    iter.next()?;
-                                    continue 'outer;
-                                }
-                            }
+                if !compaction_filters.is_empty()
+                    && !expired
+                    && matches!(
+                        iter.entry_type(),
+                        EntryType::Put | EntryType::ValuePointer | EntryType::ExpiringPut
+                    )
+                {
+                    let value = match iter.entry_type() {
+                        EntryType::ValuePointer => {
+                            snapshot.read_value_pointer(iter.value(), iter.key())?
+                        }
+                        entry_type => Bytes::copy_from_slice(user_value(entry_type, iter.value())),
+                    };
+                    match Self::apply_compaction_filters(
+                        &compaction_filters,
+                        output_level,
+                        iter.key(),
+                        value,
+                    ) {
+                        CompactionDecision::Keep => {}
+                        CompactionDecision::Remove if compact_to_bottom_level => {
+                            last_key.clear();
+                            last_key.extend(iter.key().key_ref());
+                            iter.next()?;
+                            continue;
+                        }
+                        // A deletion keeps the older versions in the levels below from showing up again.
+                        CompactionDecision::Remove => {
+                            filtered = Some((EntryType::Delete, Bytes::new()));
+                        }
+                        // A changed value keeps the expiry time.
+                        CompactionDecision::ChangeValue(value)
+                            if iter.entry_type() == EntryType::ExpiringPut =>
+                        {
+                            let (expiry, _) = decode_expiring_value(iter.value());
+                            let value = encode_expiring_value(expiry, &value);
+                            filtered = Some((EntryType::ExpiringPut, value.into()));
+                        }
+                        CompactionDecision::ChangeValue(value) => {
+                            filtered = Some((EntryType::Put, value));
                         }
                     }
                 }
             }
+            if expired {
+                filtered = Some((EntryType::Delete, Bytes::new()));
+            }
 
             let builder_inner = builder.as_mut().unwrap();
 
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
+            // Time-window compaction splits the outputs at window boundaries only, so that each window is one SST.
+            let key_window = match &self.compaction_controller {
+                CompactionController::TimeWindow(ctrl) => {
+                    Some(ctrl.window_of(iter.key().key_ref()))
+                }
+                _ => None,
+            };
+            let split = match key_window {
+                Some(window) => sst_window.is_some() && sst_window != Some(window),
+                None => {
+                    let overlaps_grandparents = !same_as_last_key
+                        && grandparent_overlap.should_stop_before(iter.key().key_ref())
+                        && !builder_inner.is_empty();
+                    builder_inner.estimated_size() >= self.options.target_sst_size
+                        || overlaps_grandparents
+                }
+            };
+            sst_window = key_window;
+            if split && !same_as_last_key {
+                grandparent_overlap.reset();
+            }
+
+            if split && !same_as_last_key {
                 let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
+                let mut old_builder = builder.take().unwrap();
+                Self::add_range_tombstones(
+                    &mut old_builder,
+                    &range_tombstones_to_keep,
+                    sst_lower.as_deref(),
+                    Some(iter.key().key_ref()),
+                );
+                sst_lower = Some(iter.key().key_ref().to_vec());
                 let sst = Arc::new(old_builder.build(
                     sst_id,
                     Some(self.block_cache.clone()),
                     self.path_of_sst(sst_id),
                 )?);
                 new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(IoPriority::Low, newest_write_time));
             }
 
-            let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
-
             if !same_as_last_key {
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
             }
 
+            let builder_inner = builder.as_mut().unwrap();
+            if iter.key().ts() <= watermark && iter.entry_type() == EntryType::Merge {
+                // Collect the operands and the value below them, moving `iter` past them.
+                let key = iter.key().to_key_vec();
+                let mut operands = Vec::new();
+                let mut kept_base = None;
+                let existing = loop {
+                    if !iter.is_valid() || iter.key().key_ref() != key.key_ref() {
+                        break compact_to_bottom_level.then_some(None);
+                    }
+                    if Self::is_range_deleted(
+                        &range_tombstones_below_watermark,
+                        &mut range_tombstone_cursor,
+                        iter.key(),
+                    ) {
+                        break Some(None);
+                    }
+                    let value = Bytes::copy_from_slice(iter.value());
+                    match iter.entry_type() {
+                        EntryType::Merge => operands.push(value),
+                        EntryType::Put => break Some(Some(value)),
+                        EntryType::ExpiringPut
+                            if is_expired(EntryType::ExpiringPut, &value, now) =>
+                        {
+                            break Some(None);
+                        }
+                        entry_type @ (EntryType::ValuePointer | EntryType::ExpiringPut) => {
+                            kept_base = Some((iter.key().to_key_vec(), entry_type, value));
+                            iter.next()?;
+                            break None;
+                        }
+                        _ => break Some(None),
+                    }
+                    iter.next()?;
+                };
+                operands.reverse();
+                let merge_operands = MergeOperands {
+                    key,
+                    operands,
+                    existing,
+                    kept_base,
+                };
+                for (key, entry_type, value) in self.fold_merge_operands(merge_operands)? {
+                    builder_inner.add_with_type(key.as_key_slice(), &value, entry_type);
+                }
+                continue;
+            }
+            match filtered {
+                Some((entry_type, value)) => {
+                    builder_inner.add_with_type(iter.key(), &value, entry_type)
+                }
+                None => builder_inner.add_with_type(iter.key(), iter.value(), iter.entry_type()),
+            }
+
             iter.next()?;
         }
-        if let Some(builder) = builder {
-            let sst_id = self.next_sst_id(); // lock dropped here
-            let sst = Arc::new(builder.build(
-                sst_id,
-                Some(self.block_cache.clone()),
-                self.path_of_sst(sst_id),
-            )?);
-            new_sst.push(sst);
+        if builder.is_none() && !range_tombstones_to_keep.is_empty() {
+            builder = Some(self.new_sst_builder(IoPriority::Low, newest_write_time));
+        }
+        if let Some(mut builder) = builder {
+            Self::add_range_tombstones(
+                &mut builder,
+                &range_tombstones_to_keep,
+                sst_lower.as_deref(),
+                upper,
+            );
+            // All entries may have been dropped.
+            if !builder.is_empty() {
+                let sst_id = self.next_sst_id(); // lock dropped here
+                let sst = Arc::new(builder.build(
+                    sst_id,
+                    Some(self.block_cache.clone()),
+                    self.path_of_sst(sst_id),
+                )?);
+                new_sst.push(sst);
+            }
         }
         Ok(new_sst)
     }
 
+    /// Splits a task into up to `max_subcompactions` key ranges with about the same number of input data blocks,
+    /// and at least `target_sst_size` bytes of input each. Returns the keys between the ranges, which are first
+    /// keys of data blocks. The ranges are split by user key, so all versions of a key are compacted together.
+    pub(crate) fn subcompaction_boundaries(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Vec<Bytes> {
+        // the outputs of a time-window task are split by window rather than by key range
+        if let CompactionTask::TimeWindow(_) = task {
+            return Vec::new();
+        }
+        let input_sst_ids = task.input_sst_ids();
+        let input_size = input_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        let num_subcompactions = self
+            .options
+            .max_subcompactions
+            .min((input_size / self.options.target_sst_size as u64) as usize);
+        if num_subcompactions <= 1 {
+            return Vec::new();
+        }
+        let mut block_keys = input_sst_ids
+            .iter()
+            .flat_map(|id| &snapshot.sstables[id].block_meta)
+            .map(|meta| meta.first_key.key_ref())
+            .collect::<Vec<_>>();
+        block_keys.sort();
+        let mut boundaries = (1..num_subcompactions)
+            .map(|i| Bytes::copy_from_slice(block_keys[i * block_keys.len() / num_subcompactions]))
+            .collect::<Vec<_>>();
+        boundaries.dedup();
+        boundaries
+    }
+
     fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
+        let range_tombstones = task
+            .input_sst_ids()
+            .iter()
+            .flat_map(|id| snapshot.sstables[id].range_tombstones())
+            .cloned()
+            .collect::<Vec<_>>();
+        let boundaries = self.subcompaction_boundaries(&snapshot, task);
+        if boundaries.is_empty() {
+            return self.compact_subrange(task, &snapshot, range_tombstones, None, None);
+        }
+        println!(
+            "running {} subcompactions split at {:?}",
+            boundaries.len() + 1,
+            boundaries
+        );
+        let lowers = std::iter::once(None).chain(boundaries.iter().map(|key| Some(&key[..])));
+        let uppers = boundaries
+            .iter()
+            .map(|key| Some(&key[..]))
+            .chain(std::iter::once(None));
+        std::thread::scope(|scope| {
+            let handles = lowers
+                .zip(uppers)
+                .map(|(lower, upper)| {
+                    let range_tombstones = range_tombstones.clone();
+                    let snapshot = &snapshot;
+                    scope.spawn(move || {
+                        self.compact_subrange(task, snapshot, range_tombstones, lower, upper)
+                    })
+                })
+                .collect::<Vec<_>>();
+            // the ranges are in key order, and so are their outputs
+            let mut output = Vec::new();
+            for handle in handles {
+                output.extend(handle.join().map_err(|e| anyhow!("{:?}", e))??);
+            }
+            Ok(output)
+        })
+    }
+
+    /// An iterator over an SST, from `lower` if given.
+    fn sst_iter_from(sst: Arc<SsTable>, lower: Option<&[u8]>) -> Result<SsTableIterator> {
+        match lower {
+            Some(lower) => SsTableIterator::create_and_seek_to_key(
+                sst,
+                KeySlice::from_slice(lower, key::TS_RANGE_BEGIN),
+            ),
+            None => SsTableIterator::create_and_seek_to_first(sst),
+        }
+    }
+
+    /// An iterator over a sorted run of SSTs, from `lower` if given.
+    fn concat_iter_from(
+        ssts: Vec<Arc<SsTable>>,
+        lower: Option<&[u8]>,
+    ) -> Result<SstConcatIterator> {
+        match lower {
+            Some(lower) => SstConcatIterator::create_and_seek_to_key(
+                ssts,
+                KeySlice::from_slice(lower, key::TS_RANGE_BEGIN),
+            ),
+            None => SstConcatIterator::create_and_seek_to_first(ssts),
+        }
+    }
+
+    /// Compacts the keys of a task in `[lower, upper)`, where `None` is unbounded.
+    fn compact_subrange(
+        &self,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+        range_tombstones: Vec<RangeTombstone>,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<Vec<Arc<SsTable>>> {
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
@@ -230,8 +1078,9 @@ impl LsmStorageInner {
             } => {
                 let mut l0_iters = Vec::with_capacity(l0_sstables.len());
                 for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
+                    l0_iters.push(Box::new(Self::sst_iter_from(
                         snapshot.sstables.get(id).unwrap().clone(),
+                        lower,
                     )?));
                 }
                 let mut l1_iters = Vec::with_capacity(l1_sstables.len());
@@ -240,9 +1089,16 @@ impl LsmStorageInner {
                 }
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    Self::concat_iter_from(l1_iters, lower)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    range_tombstones,
+                    task,
+                    task.output_level(snapshot),
+                    lower,
+                    upper,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -263,22 +1119,27 @@ impl LsmStorageInner {
                     for id in upper_level_sst_ids.iter() {
                         upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
+                    let upper_iter = Self::concat_iter_from(upper_ssts, lower)?;
                     let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
                     for id in lower_level_sst_ids.iter() {
                         lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = Self::concat_iter_from(lower_ssts, lower)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        range_tombstones,
+                        task,
+                        task.output_level(snapshot),
+                        lower,
+                        upper,
                     )
                 }
                 None => {
                     let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
                     for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
+                        upper_iters.push(Box::new(Self::sst_iter_from(
                             snapshot.sstables.get(id).unwrap().clone(),
+                            lower,
                         )?));
                     }
                     let upper_iter = MergeIterator::create(upper_iters);
@@ -286,10 +1147,14 @@ impl LsmStorageInner {
                     for id in lower_level_sst_ids.iter() {
                         lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = Self::concat_iter_from(lower_ssts, lower)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        range_tombstones,
+                        task,
+                        task.output_level(snapshot),
+                        lower,
+                        upper,
                     )
                 }
             },
@@ -300,11 +1165,44 @@ impl LsmStorageInner {
                     for id in tier_sst_ids.iter() {
                         ssts.push(snapshot.sstables.get(id).unwrap().clone());
                     }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+                    iters.push(Box::new(Self::concat_iter_from(ssts, lower)?));
                 }
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
-                    task.compact_to_bottom_level(),
+                    range_tombstones,
+                    task,
+                    task.output_level(snapshot),
+                    lower,
+                    upper,
+                )
+            }
+            // the SSTs are relinked to the lower level when the result is applied
+            CompactionTask::TrivialMove { .. } => Ok(Vec::new()),
+            // the deleted SSTs are dropped when the result is applied
+            CompactionTask::Fifo(FifoCompactionTask { merged_sst_ids, .. })
+                if merged_sst_ids.is_empty() =>
+            {
+                Ok(Vec::new())
+            }
+            CompactionTask::Fifo(FifoCompactionTask {
+                merged_sst_ids: sst_ids,
+                ..
+            })
+            | CompactionTask::TimeWindow(TimeWindowCompactionTask { sst_ids, .. }) => {
+                let mut iters = Vec::with_capacity(sst_ids.len());
+                for id in sst_ids.iter() {
+                    iters.push(Box::new(Self::sst_iter_from(
+                        snapshot.sstables.get(id).unwrap().clone(),
+                        lower,
+                    )?));
+                }
+                self.compact_generate_sst_from_iter(
+                    MergeIterator::create(iters),
+                    range_tombstones,
+                    task,
+                    task.output_level(snapshot),
+                    lower,
+                    upper,
                 )
             }
         }
@@ -315,6 +1213,7 @@ impl LsmStorageInner {
             panic!("full compaction can only be called with compaction is not enabled")
         };
 
+        let _compaction_lock = self.compaction_lock.write();
         let snapshot = {
             let state = self.state.read();
             state.clone()
@@ -332,7 +1231,7 @@ impl LsmStorageInner {
         let sstables = self.compact(&compaction_task)?;
         let mut ids = Vec::with_capacity(sstables.len());
 
-        {
+        let value_logs_to_remove = {
             let state_lock = self.state_lock.lock();
             let mut state = self.state.read().as_ref().clone();
             for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
@@ -341,6 +1240,7 @@ impl LsmStorageInner {
             }
             for new_sst in sstables {
                 ids.push(new_sst.sst_id());
+                state.open_value_logs(&new_sst, &self.path)?;
                 let result = state.sstables.insert(new_sst.sst_id(), new_sst);
                 assert!(result.is_none());
             }
@@ -354,49 +1254,173 @@ impl LsmStorageInner {
                 .copied()
                 .collect::<Vec<_>>();
             assert!(l0_sstables_map.is_empty());
+            let value_logs_to_remove = state.remove_unreferenced_value_logs();
             *self.state.write() = Arc::new(state);
+            self.write_controller.notify();
             self.sync_dir()?;
             self.manifest.as_ref().unwrap().add_record(
                 &state_lock,
                 ManifestRecord::Compaction(compaction_task, ids.clone()),
             )?;
-        }
+            value_logs_to_remove
+        };
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
             std::fs::remove_file(self.path_of_sst(*sst))?;
         }
+        for value_log in value_logs_to_remove {
+            std::fs::remove_file(self.path_of_vlog(value_log.id()))?;
+        }
 
         println!("force full compaction done, new SSTs: {:?}", ids);
 
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
-        let snapshot = {
-            let state = self.state.read();
-            state.clone()
+    /// Compacts the SSTs overlapping the range down to the bottom level, which drops the deleted and overwritten
+    /// versions of the keys in the range below the watermark. The memtables are not flushed, and this returns
+    /// once the compaction is done. Without compaction enabled, this is a full compaction.
+    pub fn compact_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<()> {
+        let num_levels = match &self.options.compaction_options {
+            CompactionOptions::Leveled(options) => options.max_levels,
+            CompactionOptions::Simple(options) => options.max_levels,
+            CompactionOptions::Tiered(_)
+            | CompactionOptions::Fifo(_)
+            | CompactionOptions::TimeWindow(_) => 1,
+            CompactionOptions::NoCompaction => return self.force_full_compaction(),
         };
-        let task = self
-            .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
-            return Ok(());
+        let _compaction_lock = self.compaction_lock.write();
+        // Each task reads the output of the previous one, so the tasks are generated one at a time.
+        for level in 0..num_levels {
+            let snapshot = {
+                let state = self.state.read();
+                state.clone()
+            };
+            let Some(task) = self
+                .compaction_controller
+                .generate_range_compaction_task(&snapshot, level, lower, upper)
+            else {
+                continue;
+            };
+            println!("running range compaction task: {:?}", task);
+            self.run_compaction_task(&task)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a compaction task that does not conflict with the running ones, if there is one to run.
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.read();
+        let task = {
+            let mut running = self.running_compactions.lock();
+            let snapshot = {
+                let state = self.state.read();
+                state.clone()
+            };
+            let Some(task) = self
+                .compaction_controller
+                .generate_compaction_task(&snapshot, &running)
+                .or_else(|| self.generate_tombstone_compaction_task(&snapshot, &running))
+            else {
+                return Ok(());
+            };
+            running.add(&task);
+            task
         };
         self.dump_structure();
         println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+        let result = self.run_compaction_task(&task);
+        self.running_compactions.lock().remove(&task);
+        result
+    }
+
+    /// Generates a task that compacts an SST with dense deletions, or one marked by a scan, into the level below,
+    /// if the controller has nothing else to do. The SSTs with the highest ratio of deletions go first, and the
+    /// bottom level (or tier) is never picked, as its deletions are dropped once they fall below the watermark.
+    fn generate_tombstone_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<CompactionTask> {
+        let options = self.options.tombstone_compaction.as_ref()?;
+        // the levels whose SSTs are compacted into the level below, with the level passed to the range task
+        let num_upper_levels = snapshot.levels.len().saturating_sub(1);
+        let upper_levels = match &self.compaction_controller {
+            CompactionController::Leveled(_) | CompactionController::Simple(_) => {
+                std::iter::once((0, &snapshot.l0_sstables))
+                    .chain(
+                        snapshot.levels[..num_upper_levels]
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (_, ssts))| (i + 1, ssts)),
+                    )
+                    .collect::<Vec<_>>()
+            }
+            CompactionController::Tiered(_) => snapshot.levels[..num_upper_levels]
+                .iter()
+                .map(|(_, ssts)| (0, ssts))
+                .collect(),
+            // keep in sync with `CompactionController::compacts_tombstones`
+            _ => return None,
+        };
+        let marked_sst_ids = match &self.tombstone_tracker {
+            Some(tracker) => {
+                let candidates = upper_levels
+                    .iter()
+                    .flat_map(|(_, ssts)| ssts.iter().copied())
+                    .collect::<Vec<_>>();
+                tracker.marked_ssts(snapshot, &candidates)
+            }
+            None => HashSet::new(),
+        };
+        let mut dense_ssts = Vec::new();
+        for (level, ssts) in upper_levels {
+            for id in ssts {
+                let sst = &snapshot.sstables[id];
+                let ratio = sst.tombstone_ratio().unwrap_or(0.0);
+                let is_dense = sst.num_entries() >= options.min_entries
+                    && ratio > options.tombstone_ratio_threshold;
+                if is_dense || marked_sst_ids.contains(id) {
+                    dense_ssts.push((ratio, level, sst));
+                }
+            }
+        }
+        dense_ssts.sort_by(|x, y| y.0.total_cmp(&x.0));
+        dense_ssts.into_iter().find_map(|(_, level, sst)| {
+            self.compaction_controller
+                .generate_range_compaction_task(
+                    snapshot,
+                    level,
+                    Bound::Included(sst.first_key().key_ref()),
+                    Bound::Included(sst.last_key().key_ref()),
+                )
+                .filter(|task| !running.conflicts(task))
+        })
+    }
+
+    /// Runs a compaction task, and applies its result to the LSM state and the manifest. The caller holds
+    /// `compaction_lock`.
+    fn run_compaction_task(&self, task: &CompactionTask) -> Result<()> {
+        let sstables = self.compact(task)?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
-        let ssts_to_remove = {
+        let (ssts_to_remove, value_logs_to_remove) = {
             let state_lock = self.state_lock.lock();
             let mut snapshot = self.state.read().as_ref().clone();
             let mut new_sst_ids = Vec::new();
             for file_to_add in sstables {
                 new_sst_ids.push(file_to_add.sst_id());
+                snapshot.open_value_logs(&file_to_add, &self.path)?;
                 let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
                 assert!(result.is_none());
             }
+            let cursor = self
+                .compaction_controller
+                .compaction_cursor(&snapshot, task);
             let (mut snapshot, files_to_remove) = self
                 .compaction_controller
-                .apply_compaction_result(&snapshot, &task, &output, false);
+                .apply_compaction_result(&snapshot, task, &output, false);
+            if let Some((level, key)) = &cursor {
+                snapshot.compaction_cursors.insert(*level, key.clone());
+            }
 
             let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
             for file_to_remove in &files_to_remove {
@@ -404,13 +1428,23 @@ impl LsmStorageInner {
                 assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
                 ssts_to_remove.push(result.unwrap());
             }
+            let value_logs_to_remove = snapshot.remove_unreferenced_value_logs();
             let mut state = self.state.write();
             *state = Arc::new(snapshot);
             drop(state);
+            self.write_controller.notify();
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
-            ssts_to_remove
+            self.manifest().add_record(
+                &state_lock,
+                ManifestRecord::Compaction(task.clone(), new_sst_ids),
+            )?;
+            if let Some((level, key)) = cursor {
+                self.manifest().add_record(
+                    &state_lock,
+                    ManifestRecord::CompactionCursor(level, key.to_vec()),
+                )?;
+            }
+            (ssts_to_remove, value_logs_to_remove)
         };
         println!(
             "compaction finished: {} files removed, {} files added, output={:?}",
@@ -421,6 +1455,9 @@ impl LsmStorageInner {
         for sst in ssts_to_remove {
             std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
         }
+        for value_log in value_logs_to_remove {
+            std::fs::remove_file(self.path_of_vlog(value_log.id()))?;
+        }
         self.sync_dir()?;
 
         Ok(())
@@ -432,19 +1469,42 @@ impl LsmStorageInner {
     ) -> Result<Option<std::thread::JoinHandle<()>>> {
         if let CompactionOptions::Leveled(_)
         | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
+        | CompactionOptions::Tiered(_)
+        | CompactionOptions::Fifo(_)
+        | CompactionOptions::TimeWindow(_) = self.options.compaction_options
         {
             let this = self.clone();
+            let num_threads = self.options.max_background_compactions.max(1);
             let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+                // the compaction threads stop once `stop_tx` is dropped
+                let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+                std::thread::scope(|scope| {
+                    for _ in 0..num_threads {
+                        let this = &this;
+                        let stop_rx = stop_rx.clone();
+                        scope.spawn(move || {
+                            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                            loop {
+                                crossbeam_channel::select! {
+                                    recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
+                                        eprintln!("compaction failed: {}", e);
+                                    },
+                                    recv(stop_rx) -> _ => return
+                                }
+                            }
+                        });
                     }
-                }
+                    let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                    loop {
+                        crossbeam_channel::select! {
+                            recv(ticker) -> _ => if let Err(e) = this.trigger_value_log_gc() {
+                                eprintln!("value log gc failed: {}", e);
+                            },
+                            recv(rx) -> _ => break
+                        }
+                    }
+                    drop(stop_tx);
+                });
             });
             return Ok(Some(handle));
         }
@@ -472,8 +1532,13 @@ impl LsmStorageInner {
             let ticker = crossbeam_channel::tick(Duration::from_millis(50));
             loop {
                 crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("flush failed: {}", e);
+                    recv(ticker) -> _ => {
+                        if let Err(e) = this.trigger_flush() {
+                            eprintln!("flush failed: {}", e);
+                        }
+                        if let Err(e) = this.sync_wal_on_interval() {
+                            eprintln!("WAL sync failed: {}", e);
+                        }
                     },
                     recv(rx) -> _ => return
                 }
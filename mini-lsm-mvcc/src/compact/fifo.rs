@@ -0,0 +1,163 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::RunningCompactions;
+use crate::lsm_storage::LsmStorageState;
+use crate::ttl::Clock;
+
+/// A FIFO task either deletes the oldest SSTs or merges the newest ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FifoCompactionTask {
+    // the oldest L0 SSTs, oldest first, which are removed without being read
+    pub deleted_sst_ids: Vec<usize>,
+    // the newest L0 SSTs, newest first, which are merged into SSTs that take their place in L0
+    pub merged_sst_ids: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FifoCompactionOptions {
+    // Delete the oldest SSTs while all SSTs take more than this many bytes
+    pub max_table_files_size: u64,
+    // Delete the oldest SSTs whose newest write is older than this, disabled when `None`
+    pub ttl: Option<Duration>,
+    // Merge the newest SSTs into one once at least this many of them fit in `intra_l0_max_merged_size`, disabled
+    // when `None`
+    pub intra_l0_min_merge_width: Option<usize>,
+    pub intra_l0_max_merged_size: u64,
+}
+
+/// FIFO compaction keeps all SSTs in L0 and drops the oldest ones, for data that is only ever dropped by age.
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+    clock: Arc<dyn Clock>,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions, clock: Arc<dyn Clock>) -> Self {
+        Self { options, clock }
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<FifoCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task if none is running, as all tasks work on L0.
+    pub(crate) fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<FifoCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
+        assert!(
+            snapshot.levels.iter().all(|(_, files)| files.is_empty()),
+            "should not add ssts below l0 in fifo compaction"
+        );
+        let now = self.clock.now();
+        let mut total_size = snapshot
+            .l0_sstables
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        let mut deleted_sst_ids = Vec::new();
+        for id in snapshot.l0_sstables.iter().rev() {
+            let sst = &snapshot.sstables[id];
+            let expired = match (self.options.ttl, sst.newest_write_time()) {
+                (Some(ttl), Some(newest_write_time)) => {
+                    newest_write_time.saturating_add(ttl.as_millis() as u64) <= now
+                }
+                _ => false,
+            };
+            if !expired && total_size <= self.options.max_table_files_size {
+                break;
+            }
+            println!(
+                "compaction triggered by {}: deleting {}.sst",
+                if expired { "ttl" } else { "total size" },
+                id
+            );
+            total_size -= sst.table_size();
+            deleted_sst_ids.push(*id);
+        }
+        if !deleted_sst_ids.is_empty() {
+            return Some(FifoCompactionTask {
+                deleted_sst_ids,
+                merged_sst_ids: Vec::new(),
+            });
+        }
+
+        let min_merge_width = self.options.intra_l0_min_merge_width?;
+        let mut merged_size = 0;
+        let mut merged_sst_ids = Vec::new();
+        for id in &snapshot.l0_sstables {
+            let size = snapshot.sstables[id].table_size();
+            if merged_size + size > self.options.intra_l0_max_merged_size {
+                break;
+            }
+            merged_size += size;
+            merged_sst_ids.push(*id);
+        }
+        if merged_sst_ids.len() < min_merge_width.max(2) {
+            return None;
+        }
+        println!(
+            "compaction triggered by intra-l0 merge: {} SSTs of {} bytes",
+            merged_sst_ids.len(),
+            merged_size
+        );
+        Some(FifoCompactionTask {
+            deleted_sst_ids: Vec::new(),
+            merged_sst_ids,
+        })
+    }
+
+    /// Removes the deleted and merged SSTs from L0, and puts the output where the merged SSTs were, which stays
+    /// newer than the SSTs below them.
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &FifoCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let mut files_to_remove = task.deleted_sst_ids.clone();
+        files_to_remove.extend(&task.merged_sst_ids);
+        let position = task.merged_sst_ids.first().map(|first| {
+            snapshot
+                .l0_sstables
+                .iter()
+                .position(|id| id == first)
+                .expect("merged sst not in l0")
+        });
+        let mut to_remove = files_to_remove.iter().copied().collect::<HashSet<_>>();
+        snapshot.l0_sstables.retain(|id| !to_remove.remove(id));
+        assert!(to_remove.is_empty(), "sst mismatched");
+        if let Some(position) = position {
+            snapshot
+                .l0_sstables
+                .splice(position..position, output.iter().copied());
+        }
+        (snapshot, files_to_remove)
+    }
+}
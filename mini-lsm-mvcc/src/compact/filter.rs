@@ -0,0 +1,69 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+
+/// What a compaction does with an entry after consulting a `CompactionFilter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionDecision {
+    /// Write the entry as it is.
+    Keep,
+    /// Remove the key. The older versions of the key are removed as well, by a deletion unless the compaction
+    /// writes to the bottom level.
+    Remove,
+    /// Write the entry with a new value.
+    ChangeValue(Bytes),
+}
+
+/// A user-defined filter applied to the entries a compaction writes. Only the latest version below the MVCC
+/// watermark of each key is passed to the filters, as the versions above it are kept for the transactions that
+/// may read them. Deletions, merge operands and expired values are not passed to them, values stored in a value
+/// log are read before they are, and the values of `put_with_ttl` are passed without their expiry time.
+pub trait CompactionFilter: Send + Sync {
+    /// The name of the filter, which `remove_compaction_filter` refers to.
+    fn name(&self) -> &str;
+
+    /// Decides what to do with version `ts` of `key`, which the compaction writes to `level`. Level 1 is the
+    /// first level (or tier) below L0.
+    fn filter(&self, level: usize, key: &[u8], value: &[u8], ts: u64) -> CompactionDecision;
+}
+
+/// Removes the keys with a prefix.
+pub struct PrefixCompactionFilter {
+    name: String,
+    prefix: Bytes,
+}
+
+impl PrefixCompactionFilter {
+    pub fn new(prefix: Bytes) -> Self {
+        Self {
+            name: format!("prefix:{}", String::from_utf8_lossy(&prefix)),
+            prefix,
+        }
+    }
+}
+
+impl CompactionFilter for PrefixCompactionFilter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn filter(&self, _level: usize, key: &[u8], _value: &[u8], _ts: u64) -> CompactionDecision {
+        if key.starts_with(&self.prefix) {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
@@ -13,12 +13,15 @@
 // limitations under the License.
 
 use std::collections::HashSet;
+use std::ops::Bound;
 
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
+use super::{RunningCompactions, overlapping_ssts};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -36,13 +39,97 @@ pub struct LeveledCompactionOptions {
     pub base_level_size_mb: usize,
 }
 
+/// How leveled compaction selects the SST to compact from a level that exceeds its target size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SstSelectionPolicy {
+    /// The oldest SST, which has the smallest id.
+    #[default]
+    OldestFirst,
+    /// The SST that overlaps the fewest bytes of the next level relative to its own size.
+    MinOverlappingRatio,
+    /// The first SST after the compaction cursor of the level, which is the last key compacted from it, so
+    /// that the compactions sweep the key space of the level in turn.
+    RoundRobin,
+    /// The SST with the most point deletions and range tombstones.
+    MostTombstones,
+}
+
 pub struct LeveledCompactionController {
     options: LeveledCompactionOptions,
+    sst_selection: SstSelectionPolicy,
 }
 
 impl LeveledCompactionController {
     pub fn new(options: LeveledCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            sst_selection: SstSelectionPolicy::default(),
+        }
+    }
+
+    pub fn with_sst_selection(mut self, sst_selection: SstSelectionPolicy) -> Self {
+        self.sst_selection = sst_selection;
+        self
+    }
+
+    /// Selects the SST of `level` to compact into the level below.
+    fn select_sst(&self, snapshot: &LsmStorageState, level: usize) -> usize {
+        let sst_ids = &snapshot.levels[level - 1].1;
+        match self.sst_selection {
+            SstSelectionPolicy::OldestFirst => sst_ids.iter().min().copied().unwrap(),
+            SstSelectionPolicy::MinOverlappingRatio => sst_ids
+                .iter()
+                .map(|id| {
+                    let overlapping_size = self
+                        .find_overlapping_ssts(snapshot, &[*id], level + 1)
+                        .iter()
+                        .map(|id| snapshot.sstables[id].table_size())
+                        .sum::<u64>();
+                    let size = snapshot.sstables[id].table_size().max(1);
+                    (overlapping_size as f64 / size as f64, *id)
+                })
+                .min_by(|a, b| a.partial_cmp(b).unwrap())
+                .map(|(_, id)| id)
+                .unwrap(),
+            // the SSTs of a level are sorted by first key
+            SstSelectionPolicy::RoundRobin => *snapshot
+                .compaction_cursors
+                .get(&level)
+                .and_then(|cursor| {
+                    sst_ids
+                        .iter()
+                        .find(|id| snapshot.sstables[*id].first_key().key_ref() > &cursor[..])
+                })
+                .unwrap_or(&sst_ids[0]),
+            SstSelectionPolicy::MostTombstones => sst_ids
+                .iter()
+                .map(|id| {
+                    let sst = &snapshot.sstables[id];
+                    let num_tombstones = sst.num_deletes() + sst.range_tombstones().len() as u64;
+                    (num_tombstones, std::cmp::Reverse(*id))
+                })
+                .max()
+                .map(|(_, std::cmp::Reverse(id))| id)
+                .unwrap(),
+        }
+    }
+
+    /// The compaction cursor of the upper level of a task, which is the last key it compacts from the level, if
+    /// the SSTs are selected round-robin. L0 has no cursor, as all of its SSTs are compacted at once.
+    pub fn compaction_cursor(
+        &self,
+        snapshot: &LsmStorageState,
+        upper_level: Option<usize>,
+        upper_level_sst_ids: &[usize],
+    ) -> Option<(usize, Bytes)> {
+        if self.sst_selection != SstSelectionPolicy::RoundRobin {
+            return None;
+        }
+        let last_key = upper_level_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].last_key())
+            .max()?;
+        Some((upper_level?, Bytes::copy_from_slice(last_key.key_ref())))
     }
 
     fn find_overlapping_ssts(
@@ -78,6 +165,15 @@ impl LeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task that reads and writes none of the levels of the running tasks.
+    pub(crate) fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
     ) -> Option<LeveledCompactionTask> {
         // step 1: compute target level size
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
@@ -109,7 +205,10 @@ impl LeveledCompactionController {
         }
 
         // Flush L0 SST is the top priority
-        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+            && !running.is_level_busy(0)
+            && !running.is_level_busy(base_level)
+        {
             println!("flush L0 SST to base level {}", base_level);
             return Some(LeveledCompactionTask {
                 upper_level: None,
@@ -127,7 +226,8 @@ impl LeveledCompactionController {
         let mut priorities = Vec::with_capacity(self.options.max_levels);
         for level in 0..self.options.max_levels {
             let prio = real_level_size[level] as f64 / target_level_size[level] as f64;
-            if prio > 1.0 {
+            if prio > 1.0 && !running.is_level_busy(level + 1) && !running.is_level_busy(level + 2)
+            {
                 priorities.push((prio, level + 1));
             }
         }
@@ -149,7 +249,7 @@ impl LeveledCompactionController {
             );
 
             let level = *level;
-            let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
+            let selected_sst = self.select_sst(snapshot, level);
             println!(
                 "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
                 priorities
@@ -169,6 +269,40 @@ impl LeveledCompactionController {
         None
     }
 
+    /// Generates the task that compacts the SSTs of `upper_level` (0 for L0) overlapping the range into the
+    /// level below. All L0 SSTs are compacted if any of them overlaps the range, as they overlap each other.
+    pub fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        upper_level: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<LeveledCompactionTask> {
+        let upper_level_sst_ids = if upper_level == 0 {
+            if overlapping_ssts(snapshot, &snapshot.l0_sstables, lower, upper).is_empty() {
+                return None;
+            }
+            snapshot.l0_sstables.clone()
+        } else {
+            overlapping_ssts(snapshot, &snapshot.levels[upper_level - 1].1, lower, upper)
+        };
+        if upper_level_sst_ids.is_empty() {
+            return None;
+        }
+        let lower_level = upper_level + 1;
+        Some(LeveledCompactionTask {
+            upper_level: (upper_level > 0).then_some(upper_level),
+            lower_level_sst_ids: self.find_overlapping_ssts(
+                snapshot,
+                &upper_level_sst_ids,
+                lower_level,
+            ),
+            upper_level_sst_ids,
+            lower_level,
+            is_lower_level_bottom_level: lower_level == self.options.max_levels,
+        })
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -13,9 +13,11 @@
 // limitations under the License.
 
 use std::collections::HashSet;
+use std::ops::Bound;
 
 use serde::{Deserialize, Serialize};
 
+use super::{RunningCompactions, overlapping_ssts};
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Clone)]
@@ -25,7 +27,7 @@ pub struct SimpleLeveledCompactionOptions {
     pub max_levels: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -50,6 +52,15 @@ impl SimpleLeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task that reads and writes none of the levels of the running tasks.
+    pub(crate) fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
     ) -> Option<SimpleLeveledCompactionTask> {
         let mut level_sizes = Vec::new();
         level_sizes.push(snapshot.l0_sstables.len());
@@ -58,7 +69,10 @@ impl SimpleLeveledCompactionController {
         }
 
         // check level0_file_num_compaction_trigger for compaction of L0 to L1
-        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+            && !running.is_level_busy(0)
+            && !running.is_level_busy(1)
+        {
             println!(
                 "compaction triggered at level 0 because L0 has {} SSTs >= {}",
                 snapshot.l0_sstables.len(),
@@ -79,6 +93,9 @@ impl SimpleLeveledCompactionController {
             {
                 continue;
             }
+            if running.is_level_busy(i) || running.is_level_busy(i + 1) {
+                continue;
+            }
 
             let lower_level = i + 1;
             let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
@@ -103,6 +120,33 @@ impl SimpleLeveledCompactionController {
         None
     }
 
+    /// Generates the task that compacts `upper_level` (0 for L0) into the level below if any of its SSTs
+    /// overlaps the range. The tasks of simple leveled compaction always cover both levels.
+    pub fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        upper_level: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        let upper_level_sst_ids = if upper_level == 0 {
+            &snapshot.l0_sstables
+        } else {
+            &snapshot.levels[upper_level - 1].1
+        };
+        if overlapping_ssts(snapshot, upper_level_sst_ids, lower, upper).is_empty() {
+            return None;
+        }
+        let lower_level = upper_level + 1;
+        Some(SimpleLeveledCompactionTask {
+            upper_level: (upper_level > 0).then_some(upper_level),
+            upper_level_sst_ids: upper_level_sst_ids.clone(),
+            lower_level,
+            lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
+            is_lower_level_bottom_level: lower_level == self.options.max_levels,
+        })
+    }
+
     /// Apply the compaction result.
     ///
     /// The compactor will call this function with the compaction task and the list of SST ids generated. This function applies the
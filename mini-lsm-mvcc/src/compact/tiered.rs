@@ -13,17 +13,20 @@
 // limitations under the License.
 
 use std::collections::HashMap;
+use std::ops::Bound;
 
 use serde::{Deserialize, Serialize};
 
+use super::{RunningCompactions, overlapping_ssts};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
 }
 
+/// The triggers of tiered compaction compare the sizes of the tiers in bytes.
 #[derive(Debug, Clone)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
@@ -35,17 +38,48 @@ pub struct TieredCompactionOptions {
 
 pub struct TieredCompactionController {
     options: TieredCompactionOptions,
+    /// The most bytes a task reducing sorted runs merges, which still merges at least two tiers.
+    max_merge_bytes: Option<u64>,
 }
 
 impl TieredCompactionController {
     pub fn new(options: TieredCompactionOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            max_merge_bytes: None,
+        }
+    }
+
+    pub fn with_max_merge_bytes(mut self, max_merge_bytes: Option<u64>) -> Self {
+        self.max_merge_bytes = max_merge_bytes;
+        self
+    }
+
+    /// The size of a tier in bytes.
+    fn tier_size(snapshot: &LsmStorageState, sst_ids: &[usize]) -> u64 {
+        sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum()
     }
 
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task if none is running. Every task includes the newest tier, so tiered
+    /// compaction runs one task at a time.
+    pub(crate) fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<TieredCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
         assert!(
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
@@ -53,13 +87,14 @@ impl TieredCompactionController {
         if snapshot.levels.len() < self.options.num_tiers {
             return None;
         }
+        let tier_sizes = snapshot
+            .levels
+            .iter()
+            .map(|(_, sst_ids)| Self::tier_size(snapshot, sst_ids))
+            .collect::<Vec<_>>();
         // compaction triggered by space amplification ratio
-        let mut size = 0;
-        for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-        }
-        let space_amp_ratio =
-            (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
+        let size = tier_sizes[..tier_sizes.len() - 1].iter().sum::<u64>();
+        let space_amp_ratio = (size as f64) / (*tier_sizes.last().unwrap() as f64) * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
             println!(
                 "compaction triggered by space amplification ratio: {}",
@@ -74,8 +109,8 @@ impl TieredCompactionController {
         // compaction triggered by size ratio
         let mut size = 0;
         for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-            let next_level_size = snapshot.levels[id + 1].1.len();
+            size += tier_sizes[id];
+            let next_level_size = tier_sizes[id + 1];
             let current_size_ratio = next_level_size as f64 / size as f64;
             if current_size_ratio > size_ratio_trigger && id + 1 >= self.options.min_merge_width {
                 println!(
@@ -96,10 +131,19 @@ impl TieredCompactionController {
             }
         }
         // trying to reduce sorted runs without respecting size ratio
-        let num_tiers_to_take = snapshot
-            .levels
-            .len()
-            .min(self.options.max_merge_width.unwrap_or(usize::MAX));
+        let max_merge_width = self.options.max_merge_width.unwrap_or(usize::MAX);
+        let max_merge_bytes = self.max_merge_bytes.unwrap_or(u64::MAX);
+        let mut num_tiers_to_take = 0;
+        let mut size = 0;
+        for tier_size in &tier_sizes {
+            size += tier_size;
+            if num_tiers_to_take >= max_merge_width
+                || (num_tiers_to_take >= 2 && size > max_merge_bytes)
+            {
+                break;
+            }
+            num_tiers_to_take += 1;
+        }
         println!("compaction triggered by reducing sorted runs");
         Some(TieredCompactionTask {
             tiers: snapshot
@@ -108,7 +152,25 @@ impl TieredCompactionController {
                 .take(num_tiers_to_take)
                 .cloned()
                 .collect::<Vec<_>>(),
-            bottom_tier_included: snapshot.levels.len() >= num_tiers_to_take,
+            bottom_tier_included: num_tiers_to_take == snapshot.levels.len(),
+        })
+    }
+
+    /// Generates the task that compacts the tiers from the first one overlapping the range to the bottom tier.
+    /// The tiers in between hold older versions of the keys in the range, so they are compacted as well.
+    pub fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<TieredCompactionTask> {
+        let first_tier = snapshot
+            .levels
+            .iter()
+            .position(|(_, ssts)| !overlapping_ssts(snapshot, ssts, lower, upper).is_empty())?;
+        Some(TieredCompactionTask {
+            tiers: snapshot.levels[first_tier..].to_vec(),
+            bottom_tier_included: true,
         })
     }
 
@@ -141,9 +203,11 @@ impl TieredCompactionController {
                 levels.push((*tier_id, files.clone()));
             }
             if tier_to_remove.is_empty() && !new_tier_added {
-                // add the compacted tier to the LSM tree
+                // add the compacted tier to the LSM tree, unless all of its entries were dropped
                 new_tier_added = true;
-                levels.push((output[0], output.to_vec()));
+                if !output.is_empty() {
+                    levels.push((output[0], output.to_vec()));
+                }
             }
         }
         if !tier_to_remove.is_empty() {
@@ -0,0 +1,219 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::RunningCompactions;
+use crate::lsm_storage::LsmStorageState;
+
+/// Extracts the time of a key, for keys that are prefixed with their time.
+pub trait KeyTimeExtractor: Send + Sync {
+    /// The name of the extractor, for debugging.
+    fn name(&self) -> &str;
+
+    /// The time of `key`, in the unit of the window size. Keys are expected to be ordered by time, so that each
+    /// window is a key range.
+    fn time_of(&self, key: &[u8]) -> u64;
+}
+
+impl fmt::Debug for dyn KeyTimeExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeyTimeExtractor({})", self.name())
+    }
+}
+
+/// Reads the time from the first 8 bytes of a key as a big-endian `u64`, padding shorter keys with zeros.
+pub struct BigEndianTimePrefix;
+
+impl KeyTimeExtractor for BigEndianTimePrefix {
+    fn name(&self) -> &str {
+        "big-endian-time-prefix"
+    }
+
+    fn time_of(&self, key: &[u8]) -> u64 {
+        let mut prefix = [0; 8];
+        let len = key.len().min(8);
+        prefix[..len].copy_from_slice(&key[..len]);
+        u64::from_be_bytes(prefix)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeWindowCompactionTask {
+    // the window of the SSTs, which is the time of their first keys divided by the window size
+    pub window: u64,
+    pub sst_ids: Vec<usize>,
+    // no SST outside the task overlaps the key range of the task
+    pub covers_key_range: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimeWindowCompactionOptions {
+    pub time_extractor: Arc<dyn KeyTimeExtractor>,
+    pub window_size: u64,
+    // Merge at least this many SSTs of similar sizes in the newest window
+    pub min_threshold: usize,
+    // Merge at most this many SSTs of similar sizes in the newest window
+    pub max_threshold: usize,
+}
+
+/// Time-window compaction buckets SSTs by the window of their first keys, and only merges the SSTs of the same
+/// window. The newest window is compacted with size-tiered merging, and each older (closed) window is compacted
+/// into one SST. Each SST is a tier of its own, and the compaction outputs are split at window boundaries
+/// instead of by size, so that every compacted SST holds one window.
+pub struct TimeWindowCompactionController {
+    options: TimeWindowCompactionOptions,
+}
+
+impl TimeWindowCompactionController {
+    pub fn new(options: TimeWindowCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// The window of a key.
+    pub fn window_of(&self, key: &[u8]) -> u64 {
+        self.options.time_extractor.time_of(key) / self.options.window_size
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<TimeWindowCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task if none is running.
+    pub(crate) fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<TimeWindowCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
+        assert!(
+            snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in time-window compaction"
+        );
+        let mut windows = BTreeMap::<u64, Vec<usize>>::new();
+        for (_, sst_ids) in &snapshot.levels {
+            for id in sst_ids {
+                let window = self.window_of(snapshot.sstables[id].first_key().key_ref());
+                windows.entry(window).or_default().push(*id);
+            }
+        }
+        let (&newest_window, newest_sst_ids) = windows.iter().next_back()?;
+        // size-tiered merging of the SSTs in the newest window
+        let mut by_size = newest_sst_ids
+            .iter()
+            .map(|id| (snapshot.sstables[id].table_size(), *id))
+            .collect::<Vec<_>>();
+        by_size.sort();
+        let mut bucket_start = 0;
+        for end in 1..=by_size.len() {
+            let bucket = &by_size[bucket_start..end];
+            let average = bucket.iter().map(|(size, _)| size).sum::<u64>() / bucket.len() as u64;
+            // SSTs of similar sizes are within 50% of the average size of their bucket
+            if end < by_size.len() && by_size[end].0 <= average + average / 2 {
+                continue;
+            }
+            if bucket.len() >= self.options.min_threshold.max(2) {
+                let sst_ids = bucket
+                    .iter()
+                    .take(self.options.max_threshold)
+                    .map(|(_, id)| *id)
+                    .collect::<Vec<_>>();
+                println!(
+                    "compaction triggered by {} SSTs of similar sizes in the newest window {}",
+                    sst_ids.len(),
+                    newest_window
+                );
+                return Some(self.task(snapshot, newest_window, sst_ids));
+            }
+            bucket_start = end;
+        }
+        // each closed window is compacted into one SST
+        for (&window, sst_ids) in windows.iter().rev().skip(1) {
+            if sst_ids.len() >= 2 {
+                println!(
+                    "compaction triggered by {} SSTs in the closed window {}",
+                    sst_ids.len(),
+                    window
+                );
+                return Some(self.task(snapshot, window, sst_ids.clone()));
+            }
+        }
+        None
+    }
+
+    fn task(
+        &self,
+        snapshot: &LsmStorageState,
+        window: u64,
+        sst_ids: Vec<usize>,
+    ) -> TimeWindowCompactionTask {
+        let first_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key())
+            .min()
+            .unwrap();
+        let last_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].last_key())
+            .max()
+            .unwrap();
+        let covers_key_range = snapshot
+            .levels
+            .iter()
+            .flat_map(|(_, ids)| ids)
+            .filter(|id| !sst_ids.contains(id))
+            .all(|id| {
+                let sst = &snapshot.sstables[id];
+                sst.last_key() < first_key || sst.first_key() > last_key
+            });
+        TimeWindowCompactionTask {
+            window,
+            sst_ids,
+            covers_key_range,
+        }
+    }
+
+    /// Replaces the SSTs of the task with the output, each SST a tier of its own, where the newest of them was.
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &TimeWindowCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let mut to_remove = task.sst_ids.iter().copied().collect::<HashSet<_>>();
+        let position = snapshot
+            .levels
+            .iter()
+            .position(|(_, ids)| ids.iter().any(|id| to_remove.contains(id)))
+            .expect("sst not found");
+        snapshot
+            .levels
+            .retain(|(_, ids)| !ids.iter().any(|id| to_remove.remove(id)));
+        assert!(to_remove.is_empty(), "sst mismatched");
+        snapshot
+            .levels
+            .splice(position..position, output.iter().map(|id| (*id, vec![*id])));
+        (snapshot, task.sst_ids.clone())
+    }
+}
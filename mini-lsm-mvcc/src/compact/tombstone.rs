@@ -0,0 +1,116 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compaction of tombstones that the size-based triggers leave behind. An SST whose ratio of point deletions to
+//! entries is above a threshold, or that covers a range where a scan skipped many deletions in a row, is compacted
+//! into the level below, which moves its deletions towards the bottom level where they are dropped.
+
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::lsm_storage::{LsmStorageState, range_overlap};
+
+#[derive(Debug, Clone)]
+pub struct TombstoneCompactionOptions {
+    // Compact an SST once this fraction of its entries are deletions
+    pub tombstone_ratio_threshold: f64,
+    // The SSTs with fewer entries are not compacted by their ratio
+    pub min_entries: u64,
+    // Compact the SSTs under a range where a scan skipped this many deletions in a row, disabled when `None`
+    pub scan_skipped_deletes_trigger: Option<usize>,
+}
+
+/// The most disjoint ranges recorded between two tombstone compactions. The ranges recorded beyond it are dropped,
+/// as the compaction of the ranges already recorded is still pending.
+const MAX_SKIPPED_RANGES: usize = 1024;
+
+/// The key ranges where scans skipped many deletions in a row, and the SSTs marked for compaction because of them.
+#[derive(Debug)]
+pub(crate) struct TombstoneTracker {
+    /// The number of deletions a scan skips in a row before it records the range.
+    skipped_deletes_trigger: usize,
+    /// Disjoint ranges, as `first key -> last key` with both keys included. The overlapping ranges are merged, so
+    /// that scans repeating over the same keys do not grow it.
+    skipped_ranges: Mutex<BTreeMap<Bytes, Bytes>>,
+    marked_sst_ids: Mutex<HashSet<usize>>,
+}
+
+impl TombstoneTracker {
+    pub fn new(skipped_deletes_trigger: usize) -> Self {
+        Self {
+            skipped_deletes_trigger,
+            skipped_ranges: Mutex::new(BTreeMap::new()),
+            marked_sst_ids: Mutex::new(HashSet::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn num_skipped_ranges(&self) -> usize {
+        self.skipped_ranges.lock().len()
+    }
+
+    pub fn skipped_deletes_trigger(&self) -> usize {
+        self.skipped_deletes_trigger
+    }
+
+    /// Records that a scan skipped deletions from `first_key` to `last_key`, both included.
+    pub fn record_skipped_range(&self, first_key: &[u8], last_key: &[u8]) {
+        let mut skipped_ranges = self.skipped_ranges.lock();
+        let mut first_key = Bytes::copy_from_slice(first_key);
+        let mut last_key = Bytes::copy_from_slice(last_key);
+        // the ranges starting up to `last_key` and ending from `first_key` overlap the new one
+        let overlapping = skipped_ranges
+            .range(..=last_key.clone())
+            .rev()
+            .take_while(|(_, end)| **end >= first_key)
+            .map(|(start, _)| start.clone())
+            .collect::<Vec<_>>();
+        if overlapping.is_empty() && skipped_ranges.len() >= MAX_SKIPPED_RANGES {
+            return;
+        }
+        for start in overlapping {
+            let end = skipped_ranges.remove(&start).unwrap();
+            first_key = first_key.min(start);
+            last_key = last_key.max(end);
+        }
+        skipped_ranges.insert(first_key, last_key);
+    }
+
+    /// Marks the SSTs with deletions under the recorded ranges, among `candidates`, and returns all SSTs marked so
+    /// far that are still in the state.
+    pub fn marked_ssts(&self, snapshot: &LsmStorageState, candidates: &[usize]) -> HashSet<usize> {
+        let skipped_ranges = std::mem::take(&mut *self.skipped_ranges.lock());
+        let mut marked_sst_ids = self.marked_sst_ids.lock();
+        for (first_key, last_key) in skipped_ranges {
+            for id in candidates {
+                let sst = &snapshot.sstables[id];
+                if sst.num_deletes() > 0
+                    && range_overlap(
+                        Bound::Included(&first_key),
+                        Bound::Included(&last_key),
+                        sst.first_key().as_key_slice(),
+                        sst.last_key().as_key_slice(),
+                    )
+                {
+                    marked_sst_ids.insert(*id);
+                }
+            }
+        }
+        marked_sst_ids.retain(|id| snapshot.sstables.contains_key(id));
+        marked_sst_ids.clone()
+    }
+}
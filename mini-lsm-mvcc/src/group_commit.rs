@@ -0,0 +1,219 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Group commit. Writers queue their records, and the first writer that finds no leader becomes one: it takes
+//! the queued records, writes them to the WAL as one batch, syncs the WAL according to the `WalSyncPolicy`, and
+//! applies them to the memtable, then wakes up the writers it committed for. Each writer in the group gets its own
+//! ts, so the group is the same as the writes committed one after another.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+use crate::iterators::EntryType;
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageInner;
+
+/// When the WAL is synced after a write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// Sync before every write returns. Concurrent writes share a sync.
+    EveryCommit,
+    /// Sync once this much time has passed since the last sync, by a write or the flush thread. A crash loses the
+    /// writes of up to about one interval.
+    Interval(Duration),
+    /// Only sync on `sync` and when a memtable is frozen.
+    Never,
+}
+
+/// The groups committed so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupCommitStats {
+    pub groups: u64,
+    pub writes: u64,
+    pub wal_syncs: u64,
+}
+
+/// A record of a queued write, as `(key, value, entry type)`. The leader assigns the ts.
+pub(crate) type CommitRecord = (Bytes, Bytes, EntryType);
+
+#[derive(Default)]
+struct CommitQueueState {
+    next_write_id: u64,
+    /// The writes waiting for a leader, as `(write id, records)`.
+    pending: VecDeque<(u64, Vec<CommitRecord>)>,
+    /// The commit ts or the error of the writes committed by a leader but not yet returned.
+    results: HashMap<u64, Result<u64, String>>,
+    has_leader: bool,
+}
+
+/// The queue of writes waiting to be committed, and when the WAL was last synced.
+pub(crate) struct CommitQueue {
+    state: Mutex<CommitQueueState>,
+    cvar: Condvar,
+    /// The time of the last sync, and whether anything was written since.
+    last_sync: Mutex<(Instant, bool)>,
+    stats: Mutex<GroupCommitStats>,
+}
+
+impl Default for CommitQueue {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(CommitQueueState::default()),
+            cvar: Condvar::new(),
+            last_sync: Mutex::new((Instant::now(), false)),
+            stats: Mutex::new(GroupCommitStats::default()),
+        }
+    }
+}
+
+impl CommitQueue {
+    pub(crate) fn stats(&self) -> GroupCommitStats {
+        self.stats.lock().clone()
+    }
+}
+
+impl LsmStorageInner {
+    pub fn group_commit_stats(&self) -> GroupCommitStats {
+        self.commit_queue.stats()
+    }
+
+    /// Queues the records of a write, and returns its commit ts once a leader, which may be this write, has
+    /// committed it.
+    pub(crate) fn commit_write(&self, records: Vec<CommitRecord>) -> Result<u64> {
+        let queue = &self.commit_queue;
+        let mut state = queue.state.lock();
+        let write_id = state.next_write_id;
+        state.next_write_id += 1;
+        state.pending.push_back((write_id, records));
+        loop {
+            if let Some(result) = state.results.remove(&write_id) {
+                return result.map_err(|e| anyhow!("{}", e));
+            }
+            if state.has_leader {
+                queue.cvar.wait(&mut state);
+                continue;
+            }
+            // The write is still pending, as a leader takes the writes it commits out of the queue.
+            state.has_leader = true;
+            let mut group = Vec::new();
+            let mut group_size = 0;
+            while let Some((_, records)) = state.pending.front() {
+                let size = records
+                    .iter()
+                    .map(|(k, v, _)| k.len() + v.len())
+                    .sum::<usize>();
+                if !group.is_empty() && group_size + size > self.options.max_write_group_size {
+                    break;
+                }
+                group_size += size;
+                group.push(state.pending.pop_front().unwrap());
+            }
+            drop(state);
+            let result = self.write_group(&group);
+            state = queue.state.lock();
+            for (i, (id, _)) in group.iter().enumerate() {
+                let result = match &result {
+                    Ok((first_ts, Ok(()))) => Ok(first_ts + i as u64),
+                    Ok((first_ts, Err(e))) => Err(format!(
+                        "the write is committed at ts {}, but {:#}",
+                        first_ts + i as u64,
+                        e
+                    )),
+                    Err(e) => Err(format!("{:#}", e)),
+                };
+                state.results.insert(*id, result);
+            }
+            state.has_leader = false;
+            queue.cvar.notify_all();
+        }
+    }
+
+    /// Commits a group of writes with consecutive ts, and returns the ts of the first one. The writes are committed
+    /// and visible once they are in the memtable, so a failure to sync the WAL or to freeze the memtable after that
+    /// is returned along with the ts.
+    fn write_group(&self, group: &[(u64, Vec<CommitRecord>)]) -> Result<(u64, Result<()>)> {
+        let _lck = self.mvcc().write_lock.lock();
+        let first_ts = self.mvcc().latest_commit_ts() + 1;
+        let mut batch_datas = Vec::new();
+        for (i, (_, records)) in group.iter().enumerate() {
+            let ts = first_ts + i as u64;
+            for (key, value, entry_type) in records {
+                batch_datas.push((KeySlice::from_slice(key, ts), &value[..], *entry_type));
+            }
+        }
+        let guard = self.state.read();
+        guard.memtable.write_batch(&batch_datas)?;
+        self.mvcc()
+            .update_commit_ts(first_ts + group.len() as u64 - 1);
+        {
+            let mut stats = self.commit_queue.stats.lock();
+            stats.groups += 1;
+            stats.writes += group.len() as u64;
+        }
+        if self.should_sync_wal() {
+            if let Err(e) = guard.memtable.sync_wal() {
+                let e = e.context("failed to sync the WAL, so the write may be lost in a crash");
+                return Ok((first_ts, Err(e)));
+            }
+            self.commit_queue.stats.lock().wal_syncs += 1;
+        }
+        let size = guard.memtable.approximate_size();
+        drop(guard);
+        let frozen = self
+            .try_freeze(size)
+            .context("failed to freeze the memtable");
+        Ok((first_ts, frozen))
+    }
+
+    /// Whether a write syncs the WAL, which is also recorded as the last sync if so.
+    pub(crate) fn should_sync_wal(&self) -> bool {
+        if !self.options.enable_wal {
+            return false;
+        }
+        let mut last_sync = self.commit_queue.last_sync.lock();
+        let sync = match self.options.wal_sync {
+            WalSyncPolicy::EveryCommit => true,
+            WalSyncPolicy::Interval(interval) => last_sync.0.elapsed() >= interval,
+            WalSyncPolicy::Never => false,
+        };
+        *last_sync = if sync {
+            (Instant::now(), false)
+        } else {
+            (last_sync.0, true)
+        };
+        sync
+    }
+
+    /// Syncs the WAL if the interval of `WalSyncPolicy::Interval` has passed since the last sync and anything was
+    /// written since, so that a write is synced even if no write follows it.
+    pub(crate) fn sync_wal_on_interval(&self) -> Result<()> {
+        let WalSyncPolicy::Interval(interval) = self.options.wal_sync else {
+            return Ok(());
+        };
+        {
+            let mut last_sync = self.commit_queue.last_sync.lock();
+            if !last_sync.1 || last_sync.0.elapsed() < interval {
+                return Ok(());
+            }
+            *last_sync = (Instant::now(), false);
+        }
+        self.sync()?;
+        self.commit_queue.stats.lock().wal_syncs += 1;
+        Ok(())
+    }
+}
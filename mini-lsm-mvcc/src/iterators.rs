@@ -16,6 +16,52 @@ pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+/// The type of an entry stored in the LSM tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EntryType {
+    /// The value is stored inline.
+    Put = 0,
+    /// The value is stored in a value log, and the entry holds a `ValuePointer` to it.
+    ValuePointer = 1,
+    /// A range tombstone in a write batch, whose key is the start of the range and whose value is the end.
+    /// Range tombstones are not stored in blocks.
+    RangeDelete = 2,
+    /// A deletion of the key. The value is empty.
+    Delete = 3,
+    /// A merge operand, which the `MergeOperator` applies to the older versions of the key.
+    Merge = 4,
+    /// A value with an expiry time, which prefixes the value as a u64 of milliseconds since the UNIX epoch. The
+    /// entry is a deletion once the expiry time has passed.
+    ExpiringPut = 5,
+}
+
+impl EntryType {
+    /// Decode an entry type. Like `Buf::get_u8`, this panics on invalid input, as the blocks holding
+    /// entries are protected by checksums.
+    pub(crate) fn from_u8(tag: u8) -> Self {
+        match tag {
+            0 => EntryType::Put,
+            1 => EntryType::ValuePointer,
+            2 => EntryType::RangeDelete,
+            3 => EntryType::Delete,
+            4 => EntryType::Merge,
+            5 => EntryType::ExpiringPut,
+            _ => panic!("unknown entry type {}", tag),
+        }
+    }
+
+    /// The type of an entry written through an API that predates entry types, where an empty value is a
+    /// deletion.
+    pub(crate) fn from_value(value: &[u8]) -> Self {
+        if value.is_empty() {
+            EntryType::Delete
+        } else {
+            EntryType::Put
+        }
+    }
+}
+
 pub trait StorageIterator {
     type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
     where
@@ -27,6 +73,11 @@ pub trait StorageIterator {
     /// Get the current key.
     fn key(&self) -> Self::KeyType<'_>;
 
+    /// Get the type of the current entry.
+    fn entry_type(&self) -> EntryType {
+        EntryType::Put
+    }
+
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
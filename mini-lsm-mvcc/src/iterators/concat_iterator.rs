@@ -21,7 +21,7 @@ use crate::{
     table::{SsTable, SsTableIterator},
 };
 
-use super::StorageIterator;
+use super::{EntryType, StorageIterator};
 
 /// Concat multiple iterators ordered in key order and their key ranges do not overlap. We do not want to create the
 /// iterators when initializing this iterator to reduce the overhead of seeking.
@@ -116,6 +116,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn entry_type(&self) -> EntryType {
+        self.current.as_ref().unwrap().entry_type()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
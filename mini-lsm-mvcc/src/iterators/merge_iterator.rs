@@ -20,7 +20,7 @@ use anyhow::Result;
 
 use crate::key::KeySlice;
 
-use super::StorageIterator;
+use super::{EntryType, StorageIterator};
 
 struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
 
@@ -102,6 +102,10 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         self.current.as_ref().unwrap().1.value()
     }
 
+    fn entry_type(&self) -> EntryType {
+        self.current.as_ref().unwrap().1.entry_type()
+    }
+
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
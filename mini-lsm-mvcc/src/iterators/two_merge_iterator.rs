@@ -14,7 +14,7 @@
 
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{EntryType, StorageIterator};
 
 /// Merges two iterators of different types into one. If the two iterators have the same key, only
 /// produce the key once and prefer the entry from A.
@@ -83,6 +83,14 @@ impl<
         }
     }
 
+    fn entry_type(&self) -> EntryType {
+        if self.choose_a {
+            self.a.entry_type()
+        } else {
+            self.b.entry_type()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
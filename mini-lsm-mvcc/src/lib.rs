@@ -13,17 +13,27 @@
 // limitations under the License.
 
 pub mod block;
+pub mod change_feed;
 pub mod compact;
 pub mod debug;
+pub mod group_commit;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
 pub mod mvcc;
+pub mod range_tombstone;
+pub mod rate_limiter;
+pub mod replication;
 pub mod table;
+pub mod ttl;
+pub mod value_log;
+pub mod varint;
 pub mod wal;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
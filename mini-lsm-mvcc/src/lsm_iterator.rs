@@ -13,16 +13,22 @@
 // limitations under the License.
 
 use std::ops::Bound;
+use std::sync::Arc;
 
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use bytes::Bytes;
 
-use crate::iterators::StorageIterator;
+use crate::compact::TombstoneTracker;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{EntryType, StorageIterator};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::MergeOperator;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
 use crate::table::SsTableIterator;
+use crate::ttl::{is_expired, user_value};
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the course for multiple times.
 type LsmIteratorInner = TwoMergeIterator<
@@ -36,6 +42,19 @@ pub struct LsmIterator {
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    /// The snapshot being iterated, which holds the value logs that value pointers refer to.
+    snapshot: Arc<LsmStorageState>,
+    /// The value of the current entry if it is read from a value log or produced by the merge operator.
+    resolved_value: Option<Bytes>,
+    /// The range tombstones visible at `read_ts` that may delete keys in the iterated range.
+    range_tombstones: FragmentedRangeTombstones,
+    /// The fragment of `range_tombstones` the current key is looked up from, which moves forward with the keys.
+    range_tombstone_cursor: usize,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The time that expiring values are compared against.
+    now: u64,
+    /// Records the ranges where the iterator skips many deletions in a row.
+    tombstone_tracker: Option<Arc<TombstoneTracker>>,
 }
 
 impl LsmIterator {
@@ -43,6 +62,9 @@ impl LsmIterator {
         iter: LsmIteratorInner,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        snapshot: Arc<LsmStorageState>,
+        range_tombstones: Vec<RangeTombstone>,
+        storage: &LsmStorageInner,
     ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
@@ -50,6 +72,13 @@ impl LsmIterator {
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            snapshot,
+            resolved_value: None,
+            range_tombstones: FragmentedRangeTombstones::new(&range_tombstones),
+            range_tombstone_cursor: 0,
+            merge_operator: storage.options.merge_operator.clone(),
+            now: storage.options.clock.now(),
+            tombstone_tracker: storage.tombstone_tracker.clone(),
         };
         iter.move_to_key()?;
         Ok(iter)
@@ -57,19 +86,26 @@ impl LsmIterator {
 
     fn next_inner(&mut self) -> Result<()> {
         self.inner.next()?;
+        self.check_end_bound();
+        Ok(())
+    }
+
+    fn check_end_bound(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
+            return;
         }
         match self.end_bound.as_ref() {
             Bound::Unbounded => {}
             Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
             Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
         }
-        Ok(())
     }
 
     fn move_to_key(&mut self) -> Result<()> {
+        let mut num_skipped_deletes = 0;
+        let mut first_skipped_key = Vec::new();
+        let mut last_skipped_key = Vec::new();
         loop {
             while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
                 self.next_inner()?;
@@ -91,12 +127,90 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
+            if !self.is_deleted() {
                 break;
             }
+            if self.tombstone_tracker.is_some() {
+                if num_skipped_deletes == 0 {
+                    first_skipped_key.clone_from(&self.prev_key);
+                }
+                last_skipped_key.clone_from(&self.prev_key);
+            }
+            num_skipped_deletes += 1;
+        }
+        if let Some(tracker) = &self.tombstone_tracker
+            && num_skipped_deletes > 0
+            && num_skipped_deletes >= tracker.skipped_deletes_trigger()
+        {
+            tracker.record_skipped_range(&first_skipped_key, &last_skipped_key);
+        }
+        self.resolved_value = None;
+        if self.is_valid {
+            match self.inner.entry_type() {
+                EntryType::ValuePointer => self.resolved_value = Some(self.read_value_pointer()?),
+                EntryType::Merge => self.resolved_value = Some(self.resolve_merge()?),
+                _ => {}
+            }
         }
         Ok(())
     }
+
+    fn read_value_pointer(&self) -> Result<Bytes> {
+        self.snapshot
+            .read_value_pointer(self.inner.value(), self.inner.key())
+    }
+
+    /// Applies the merge operands from the current version down to the value below them. This moves `inner`
+    /// past the operands, so the current key is `prev_key` from here on.
+    fn resolve_merge(&mut self) -> Result<Bytes> {
+        let merge_operator = self
+            .merge_operator
+            .clone()
+            .ok_or_else(|| anyhow!("no merge operator is configured"))?;
+        let mut operands = Vec::new();
+        let existing = loop {
+            if !self.inner.is_valid()
+                || self.inner.key().key_ref() != self.prev_key
+                || self.is_deleted()
+            {
+                break None;
+            }
+            match self.inner.entry_type() {
+                EntryType::Merge => operands.push(Bytes::copy_from_slice(self.inner.value())),
+                entry_type @ (EntryType::Put | EntryType::ExpiringPut) => {
+                    let value = user_value(entry_type, self.inner.value());
+                    break Some(Bytes::copy_from_slice(value));
+                }
+                EntryType::ValuePointer => break Some(self.read_value_pointer()?),
+                _ => break None,
+            }
+            self.inner.next()?;
+        };
+        let operands = operands.iter().rev().map(|x| &x[..]).collect::<Vec<_>>();
+        merge_operator.full_merge(&self.prev_key, existing.as_deref(), &operands)
+    }
+
+    /// Whether the current version is a deletion, an expired value, or deleted by a range tombstone.
+    fn is_deleted(&mut self) -> bool {
+        let entry_type = self.inner.entry_type();
+        entry_type == EntryType::Delete
+            || is_expired(entry_type, self.inner.value(), self.now)
+            || self.is_range_deleted()
+    }
+
+    /// Whether the current version is deleted by a range tombstone.
+    fn is_range_deleted(&mut self) -> bool {
+        if self.range_tombstones.is_empty() {
+            return false;
+        }
+        let key = self.inner.key();
+        self.range_tombstones.deletes_from_cursor(
+            &mut self.range_tombstone_cursor,
+            key.key_ref(),
+            key.ts(),
+            self.read_ts,
+        )
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -107,15 +221,20 @@ impl StorageIterator for LsmIterator {
     }
 
     fn key(&self) -> &[u8] {
-        self.inner.key().key_ref()
+        &self.prev_key
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.resolved_value {
+            Some(value) => value,
+            None => user_value(self.inner.entry_type(), self.inner.value()),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
+        // `inner` may already be past the current key after resolving a merge, and `move_to_key` skips the
+        // remaining versions of the current key otherwise.
+        self.check_end_bound();
         self.move_to_key()?;
         Ok(())
     }
@@ -166,6 +285,13 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn entry_type(&self) -> EntryType {
+        if !self.is_valid() {
+            panic!("invalid access to the underlying iterator");
+        }
+        self.iter.entry_type()
+    }
+
     fn next(&mut self) -> Result<()> {
         // only move when the iterator is valid and not errored
         if self.has_errored {
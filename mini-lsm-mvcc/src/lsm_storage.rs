@@ -12,33 +12,47 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, ensure};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
 use crate::block::Block;
+use crate::change_feed::{UpdateIterator, WalArchiveOptions, WalRetention};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionFilter, CompactionOptions, FifoCompactionController,
+    LeveledCompactionController, LeveledCompactionOptions, RunningCompactions,
+    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SstSelectionPolicy,
+    TieredCompactionController, TimeWindowCompactionController, TombstoneCompactionOptions,
+    TombstoneTracker,
 };
-use crate::iterators::StorageIterator;
+use crate::group_commit::{CommitQueue, CommitRecord, GroupCommitStats, WalSyncPolicy};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{EntryType, StorageIterator};
 use crate::key::{self, KeySlice};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
 use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{MemTable, map_bound, map_key_bound_plus_ts};
+use crate::merge_operator::MergeOperator;
 use crate::mvcc::LsmMvccInner;
 use crate::mvcc::txn::{Transaction, TxnIterator};
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::ttl::{Clock, SystemClock, encode_expiring_value, expiry_of};
+use crate::value_log::{ValueLog, ValueLogBuilder, ValueSeparationOptions};
+use crate::wal::{WalRecoveryMode, WalRecoveryReport};
+use crate::write_stall::{
+    WriteController, WriteStallCondition, WriteStallOptions, WriteStallStats,
+};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
@@ -56,14 +70,37 @@ pub struct LsmStorageState {
     pub levels: Vec<(usize, Vec<usize>)>,
     /// SST objects.
     pub sstables: HashMap<usize, Arc<SsTable>>,
+    /// Value logs referenced by the SSTs.
+    pub value_logs: HashMap<usize, Arc<ValueLog>>,
+    /// The last key compacted from each level, for the round-robin SST selection of leveled compaction.
+    pub compaction_cursors: HashMap<usize, Bytes>,
 }
 
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
+    /// Puts a key-value pair that expires after the TTL.
+    PutWithTtl(T, T, Duration),
     Del(T),
+    /// Deletes the keys in `[lower, upper)`.
+    DelRange(T, T),
+    /// Writes a merge operand of a key.
+    Merge(T, T),
 }
 
 impl LsmStorageState {
+    /// Creates a state without memtable data, SSTs or levels, which the compaction simulator fills in.
+    pub fn empty() -> Self {
+        Self {
+            memtable: Arc::new(MemTable::create(0)),
+            imm_memtables: Vec::new(),
+            l0_sstables: Vec::new(),
+            levels: Vec::new(),
+            sstables: Default::default(),
+            value_logs: Default::default(),
+            compaction_cursors: Default::default(),
+        }
+    }
+
     fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
@@ -71,19 +108,27 @@ impl LsmStorageState {
                 ..=*max_levels)
                 .map(|level| (level, Vec::new()))
                 .collect::<Vec<_>>(),
-            CompactionOptions::Tiered(_) => Vec::new(),
+            CompactionOptions::Tiered(_)
+            | CompactionOptions::Fifo(_)
+            | CompactionOptions::TimeWindow(_) => Vec::new(),
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
-            memtable: Arc::new(MemTable::create(0)),
-            imm_memtables: Vec::new(),
-            l0_sstables: Vec::new(),
             levels,
-            sstables: Default::default(),
+            ..Self::empty()
         }
     }
 }
 
+/// What the storage recovered when it was opened.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoverySummary {
+    pub ssts_opened: usize,
+    pub value_logs_opened: usize,
+    /// The WALs of the memtables, by memtable id.
+    pub wals: BTreeMap<usize, WalRecoveryReport>,
+}
+
 #[derive(Debug, Clone)]
 pub struct LsmStorageOptions {
     // Block size in bytes
@@ -95,6 +140,41 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Codec for data blocks in newly-written SSTs, existing SSTs keep the codec they were written with
+    pub compression: CompressionType,
+    // Key-value separation for large values, disabled when `None`
+    pub value_separation: Option<ValueSeparationOptions>,
+    // Combines the operands written by `merge`, which fails when `None`
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // The time source for the expiry of values written by `put_with_ttl`
+    pub clock: Arc<dyn Clock>,
+    // The maximum number of threads a compaction is split across by key range, where 1 disables the split
+    pub max_subcompactions: usize,
+    // The number of background threads that run compaction tasks on different levels concurrently
+    pub max_background_compactions: usize,
+    // Limits the write rate of flushes and compactions, which can be changed through the shared limiter at runtime
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Delays and blocks writes when flushes or compactions fall behind, disabled when `None`
+    pub write_stall: Option<WriteStallOptions>,
+    // How leveled compaction selects the SST to compact from a level
+    pub sst_selection: SstSelectionPolicy,
+    // The most bytes a tiered compaction task reducing sorted runs merges, which still merges at least two tiers,
+    // unlimited when `None`
+    pub tiered_max_merge_bytes: Option<u64>,
+    // Compacts the SSTs with dense deletions and the ranges where scans skip many deletions, disabled when `None`
+    pub tombstone_compaction: Option<TombstoneCompactionOptions>,
+    // Cuts an output SST of a leveled compaction once it overlaps this many bytes of the level below its output
+    // level, disabled when `None`
+    pub max_grandparent_overlap_bytes: Option<u64>,
+    // When the WAL is synced after a write
+    pub wal_sync: WalSyncPolicy,
+    // The size of the records a group commit takes once it has more than one write, which bounds the latency a
+    // write adds to the writes grouped with it
+    pub max_write_group_size: usize,
+    // How the WALs are recovered if they have corrupted or incomplete write batches
+    pub wal_recovery_mode: WalRecoveryMode,
+    // Keeps the WALs of flushed memtables in the WAL archive for the change feed, disabled when `None`
+    pub wal_archive: Option<WalArchiveOptions>,
 }
 
 impl LsmStorageOptions {
@@ -106,6 +186,22 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            compression: CompressionType::None,
+            value_separation: None,
+            merge_operator: None,
+            clock: Arc::new(SystemClock),
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            rate_limiter: None,
+            write_stall: None,
+            sst_selection: SstSelectionPolicy::OldestFirst,
+            tiered_max_merge_bytes: None,
+            tombstone_compaction: None,
+            max_grandparent_overlap_bytes: None,
+            wal_sync: WalSyncPolicy::Never,
+            max_write_group_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            wal_archive: None,
         }
     }
 
@@ -117,6 +213,22 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            value_separation: None,
+            merge_operator: None,
+            clock: Arc::new(SystemClock),
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            rate_limiter: None,
+            write_stall: None,
+            sst_selection: SstSelectionPolicy::OldestFirst,
+            tiered_max_merge_bytes: None,
+            tombstone_compaction: None,
+            max_grandparent_overlap_bytes: None,
+            wal_sync: WalSyncPolicy::Never,
+            max_write_group_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            wal_archive: None,
         }
     }
 
@@ -128,11 +240,27 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            value_separation: None,
+            merge_operator: None,
+            clock: Arc::new(SystemClock),
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            rate_limiter: None,
+            write_stall: None,
+            sst_selection: SstSelectionPolicy::OldestFirst,
+            tiered_max_merge_bytes: None,
+            tombstone_compaction: None,
+            max_grandparent_overlap_bytes: None,
+            wal_sync: WalSyncPolicy::Never,
+            max_write_group_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            wal_archive: None,
         }
     }
 }
 
-fn range_overlap(
+pub(crate) fn range_overlap(
     user_begin: Bound<&[u8]>,
     user_end: Bound<&[u8]>,
     table_begin: KeySlice,
@@ -163,23 +291,29 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
-#[derive(Clone, Debug)]
-pub enum CompactionFilter {
-    Prefix(Bytes),
-}
-
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
     next_sst_id: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
     pub(crate) compaction_controller: CompactionController,
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
-    pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) compaction_filters: Arc<Mutex<Vec<Arc<dyn CompactionFilter>>>>,
+    /// Held shared by the compaction tasks of the background threads, which avoid each other through
+    /// `running_compactions`. Held exclusively by the full and range compactions and the value log GC, which
+    /// rewrite SSTs regardless of the running tasks.
+    pub(crate) compaction_lock: RwLock<()>,
+    pub(crate) running_compactions: Mutex<RunningCompactions>,
+    pub(crate) write_controller: WriteController,
+    pub(crate) commit_queue: CommitQueue,
+    /// Collects the ranges where scans skipped many deletions, if `tombstone_compaction` sets a trigger for them.
+    pub(crate) tombstone_tracker: Option<Arc<TombstoneTracker>>,
+    pub(crate) recovery_summary: RecoverySummary,
+    pub(crate) wal_retention: WalRetention,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -263,10 +397,14 @@ impl MiniLsm {
         }))
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+    pub fn add_compaction_filter(&self, compaction_filter: Arc<dyn CompactionFilter>) {
         self.inner.add_compaction_filter(compaction_filter)
     }
 
+    pub fn remove_compaction_filter(&self, name: &str) -> bool {
+        self.inner.remove_compaction_filter(name)
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
         self.inner.get(key)
     }
@@ -283,6 +421,18 @@ impl MiniLsm {
         self.inner.delete(key)
     }
 
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.delete_range(lower, upper)
+    }
+
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge(key, operand)
+    }
+
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.inner.put_with_ttl(key, value, ttl)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -310,6 +460,47 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    pub fn compact_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<()> {
+        self.inner.compact_range(lower, upper)
+    }
+
+    pub fn write_stall_condition(&self) -> WriteStallCondition {
+        self.inner.write_stall_condition()
+    }
+
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        self.inner.write_stall_stats()
+    }
+
+    pub fn group_commit_stats(&self) -> GroupCommitStats {
+        self.inner.group_commit_stats()
+    }
+
+    pub fn recovery_summary(&self) -> &RecoverySummary {
+        &self.inner.recovery_summary
+    }
+
+    pub fn updates_since(&self, ts: u64) -> Result<UpdateIterator> {
+        self.inner.updates_since(ts)
+    }
+
+    pub fn register_update_consumer(&self, name: &str, ts: u64) -> Result<()> {
+        self.inner.register_update_consumer(name, ts)
+    }
+
+    pub fn unregister_update_consumer(&self, name: &str) -> Result<bool> {
+        self.inner.unregister_update_consumer(name)
+    }
+
+    pub fn create_checkpoint(&self, path: impl AsRef<Path>) -> Result<u64> {
+        self.inner.create_checkpoint(path)
+    }
+
+    /// Only call this in test cases due to race conditions
+    pub fn force_value_log_gc(&self) -> Result<()> {
+        self.inner.trigger_value_log_gc()
+    }
 }
 
 impl LsmStorageInner {
@@ -326,9 +517,19 @@ impl LsmStorageInner {
         self.manifest.as_ref().unwrap()
     }
 
+    pub(crate) fn merge_operator(&self) -> Result<&Arc<dyn MergeOperator>> {
+        self.options
+            .merge_operator
+            .as_ref()
+            .ok_or_else(|| anyhow!("no merge operator is configured"))
+    }
+
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        if let Some(write_stall) = &options.write_stall {
+            write_stall.validate(options.num_memtable_limit)?;
+        }
         let mut state = LsmStorageState::create(&options);
         let path = path.as_ref();
         let mut next_sst_id = 1;
@@ -336,15 +537,23 @@ impl LsmStorageInner {
         let manifest;
 
         let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
-            }
+            CompactionOptions::Leveled(leveled_options) => CompactionController::Leveled(
+                LeveledCompactionController::new(leveled_options.clone())
+                    .with_sst_selection(options.sst_selection),
+            ),
+            CompactionOptions::Tiered(tiered_options) => CompactionController::Tiered(
+                TieredCompactionController::new(tiered_options.clone())
+                    .with_max_merge_bytes(options.tiered_max_merge_bytes),
+            ),
             CompactionOptions::Simple(options) => CompactionController::Simple(
                 SimpleLeveledCompactionController::new(options.clone()),
             ),
+            CompactionOptions::Fifo(fifo_options) => CompactionController::Fifo(
+                FifoCompactionController::new(fifo_options.clone(), options.clock.clone()),
+            ),
+            CompactionOptions::TimeWindow(options) => CompactionController::TimeWindow(
+                TimeWindowCompactionController::new(options.clone()),
+            ),
             CompactionOptions::NoCompaction => CompactionController::NoCompaction,
         };
 
@@ -353,6 +562,8 @@ impl LsmStorageInner {
         }
         let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
+        let mut recovery_summary = RecoverySummary::default();
+        let mut update_consumers = HashMap::new();
         if !manifest_path.exists() {
             if options.enable_wal {
                 state.memtable = Arc::new(MemTable::create_with_wal(
@@ -389,6 +600,21 @@ impl LsmStorageInner {
                         next_sst_id =
                             next_sst_id.max(output.iter().max().copied().unwrap_or_default());
                     }
+                    ManifestRecord::CompactionCursor(level, key) => {
+                        state.compaction_cursors.insert(level, key.into());
+                    }
+                    ManifestRecord::UpdateConsumer(name, Some(ts)) => {
+                        update_consumers.insert(name, ts);
+                    }
+                    ManifestRecord::UpdateConsumer(name, None) => {
+                        update_consumers.remove(&name);
+                    }
+                    ManifestRecord::ValueLogGc(replaced) => {
+                        for (old, new) in replaced {
+                            ensure!(state.replace_sst_id(old, new), "{}.sst not exist?", old);
+                            next_sst_id = next_sst_id.max(new);
+                        }
+                    }
                 }
             }
 
@@ -410,7 +636,28 @@ impl LsmStorageInner {
                 state.sstables.insert(table_id, Arc::new(sst));
                 sst_cnt += 1;
             }
-            println!("{} SSTs opened", sst_cnt);
+            recovery_summary.ssts_opened = sst_cnt;
+
+            // recover value logs, and remove the ones no SST references, which are left behind by a crash
+            // before the removal or before the SST referencing them was recorded
+            for sst in state.sstables.values().cloned().collect::<Vec<_>>() {
+                state.open_value_logs(&sst, path)?;
+            }
+            for entry in std::fs::read_dir(path)? {
+                let entry_path = entry?.path();
+                if entry_path.extension().is_some_and(|ext| ext == "vlog") {
+                    let log_id = entry_path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .and_then(|stem| stem.parse::<usize>().ok());
+                    if !log_id.is_some_and(|log_id| state.value_logs.contains_key(&log_id)) {
+                        std::fs::remove_file(&entry_path)?;
+                    }
+                }
+            }
+            recovery_summary.value_logs_opened = state.value_logs.len();
+            next_sst_id =
+                next_sst_id.max(state.value_logs.keys().max().copied().unwrap_or_default());
 
             next_sst_id += 1;
 
@@ -430,23 +677,32 @@ impl LsmStorageInner {
 
             // recover memtables
             if options.enable_wal {
-                let mut wal_cnt = 0;
+                // set once a point-in-time recovery stops at a corruption, which drops the WALs after it
+                let mut recovered_to_corruption = false;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
+                    let wal_path = Self::path_of_wal_static(path, *id);
+                    let (memtable, report) = if recovered_to_corruption {
+                        let truncated_bytes = std::fs::metadata(&wal_path)?.len();
+                        std::fs::remove_file(&wal_path)?;
+                        let memtable = MemTable::create_with_wal(*id, &wal_path)?;
+                        memtable.sync_wal()?;
+                        let report = WalRecoveryReport {
+                            truncated_bytes,
+                            ..Default::default()
+                        };
+                        (memtable, report)
+                    } else {
+                        MemTable::recover_from_wal(*id, &wal_path, options.wal_recovery_mode)?
+                    };
+                    recovered_to_corruption |= options.wal_recovery_mode
+                        == WalRecoveryMode::PointInTimeRecovery
+                        && report.corruption.is_some();
+                    recovery_summary.wals.insert(*id, report);
+                    last_commit_ts = last_commit_ts.max(memtable.max_ts());
                     if !memtable.is_empty() {
                         state.imm_memtables.insert(0, Arc::new(memtable));
-                        wal_cnt += 1;
                     }
                 }
-                println!("{} WALs recovered", wal_cnt);
                 state.memtable = Arc::new(MemTable::create_with_wal(
                     next_sst_id,
                     Self::path_of_wal_static(path, next_sst_id),
@@ -459,6 +715,18 @@ impl LsmStorageInner {
             manifest = m;
         };
 
+        // the ranges skipped by scans are only drained by the controllers that run tombstone compaction
+        let tombstone_tracker = options
+            .tombstone_compaction
+            .as_ref()
+            .and_then(|options| options.scan_skipped_deletes_trigger)
+            .filter(|_| compaction_controller.compacts_tombstones())
+            .map(|trigger| Arc::new(TombstoneTracker::new(trigger)));
+        let wal_retention = if options.enable_wal {
+            WalRetention::open(path, &state, last_commit_ts, update_consumers)?
+        } else {
+            WalRetention::default()
+        };
         let storage = Self {
             state: Arc::new(RwLock::new(Arc::new(state))),
             state_lock: Mutex::new(()),
@@ -470,17 +738,35 @@ impl LsmStorageInner {
             options: options.into(),
             mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            compaction_lock: RwLock::new(()),
+            running_compactions: Mutex::new(RunningCompactions::default()),
+            write_controller: WriteController::default(),
+            commit_queue: CommitQueue::default(),
+            recovery_summary,
+            tombstone_tracker,
+            wal_retention,
         };
         storage.sync_dir()?;
 
         Ok(storage)
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+    /// Registers a filter applied by the compactions that start from now on, after the filters already
+    /// registered.
+    pub fn add_compaction_filter(&self, compaction_filter: Arc<dyn CompactionFilter>) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
     }
 
+    /// Unregisters the compaction filters named `name`, returns false if there are none. Compactions already
+    /// running keep applying them.
+    pub fn remove_compaction_filter(&self, name: &str) -> bool {
+        let mut compaction_filters = self.compaction_filters.lock();
+        let len = compaction_filters.len();
+        compaction_filters.retain(|filter| filter.name() != name);
+        compaction_filters.len() < len
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.state.read().memtable.sync_wal()
     }
@@ -555,6 +841,8 @@ impl LsmStorageInner {
             level_iters.push(Box::new(level_iter));
         }
 
+        let range_tombstones =
+            snapshot.range_tombstones(Bound::Included(key), Bound::Included(key), read_ts);
         let iter = LsmIterator::new(
             TwoMergeIterator::create(
                 TwoMergeIterator::create(memtable_iter, l0_iter)?,
@@ -562,44 +850,94 @@ impl LsmStorageInner {
             )?,
             Bound::Unbounded,
             read_ts,
+            snapshot,
+            range_tombstones,
+            self,
         )?;
 
-        if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
+        if iter.is_valid() && iter.key() == key {
             return Ok(Some(Bytes::copy_from_slice(iter.value())));
         }
         Ok(None)
     }
 
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
-        let ts = self.mvcc().latest_commit_ts() + 1;
-        let mut batch_datas: Vec<(key::Key<&[u8]>, &[u8])> = vec![];
-        let size;
+        self.stall_write();
+        let now = self.options.clock.now();
+        // The ts is assigned when the write is committed, with the writes grouped with it.
+        let mut records: Vec<CommitRecord> = vec![];
         for record in batch {
             match record {
                 WriteBatchRecord::Del(key) => {
                     let key = key.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
-                    batch_datas.push((KeySlice::from_slice(key, ts), b""));
+                    records.push((Bytes::copy_from_slice(key), Bytes::new(), EntryType::Delete));
                 }
                 WriteBatchRecord::Put(key, value) => {
                     let key = key.as_ref();
                     let value = value.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
-                    batch_datas.push((KeySlice::from_slice(key, ts), value));
+                    records.push((
+                        Bytes::copy_from_slice(key),
+                        Bytes::copy_from_slice(value),
+                        EntryType::Put,
+                    ));
+                }
+                WriteBatchRecord::PutWithTtl(key, value, ttl) => {
+                    let key = key.as_ref();
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    let value = encode_expiring_value(expiry_of(now, *ttl), value.as_ref());
+                    records.push((
+                        Bytes::copy_from_slice(key),
+                        value.into(),
+                        EntryType::ExpiringPut,
+                    ));
+                }
+                WriteBatchRecord::DelRange(lower, upper) => {
+                    let (lower, upper) = (lower.as_ref(), upper.as_ref());
+                    assert!(lower < upper, "range cannot be empty");
+                    // A range tombstone does not delete the versions written at its own ts, so the earlier
+                    // writes of the batch in the range are dropped here.
+                    records.retain(|(key, _, entry_type)| {
+                        *entry_type == EntryType::RangeDelete
+                            || &key[..] < lower
+                            || &key[..] >= upper
+                    });
+                    records.push((
+                        Bytes::copy_from_slice(lower),
+                        Bytes::copy_from_slice(upper),
+                        EntryType::RangeDelete,
+                    ));
+                }
+                WriteBatchRecord::Merge(key, operand) => {
+                    let (key, operand) = (key.as_ref(), operand.as_ref());
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    let merge_operator = self.merge_operator()?;
+                    // All entries of a batch share one ts, so an operand is combined with the earlier write of its
+                    // key in the batch.
+                    let earlier = records
+                        .iter_mut()
+                        .rev()
+                        .find(|(earlier_key, _, entry_type)| {
+                            *entry_type != EntryType::RangeDelete && earlier_key == key
+                        });
+                    match earlier {
+                        Some((_, value, entry_type)) => {
+                            let (merged_type, merged) =
+                                merge_operator.merge_into(key, *entry_type, value, operand, now)?;
+                            *entry_type = merged_type;
+                            *value = merged;
+                        }
+                        None => records.push((
+                            Bytes::copy_from_slice(key),
+                            Bytes::copy_from_slice(operand),
+                            EntryType::Merge,
+                        )),
+                    }
                 }
             }
         }
-        {
-            let guard = self.state.read();
-            guard.memtable.put_batch(&batch_datas)?;
-            size = guard.memtable.approximate_size();
-        }
-        self.try_freeze(size)?;
-
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        self.commit_write(records)
     }
 
     pub fn write_batch<T: AsRef<[u8]>>(
@@ -618,6 +956,15 @@ impl LsmStorageInner {
                     WriteBatchRecord::Put(key, value) => {
                         txn.put(key.as_ref(), value.as_ref());
                     }
+                    WriteBatchRecord::DelRange(lower, upper) => {
+                        txn.delete_range(lower.as_ref(), upper.as_ref());
+                    }
+                    WriteBatchRecord::PutWithTtl(key, value, ttl) => {
+                        txn.put_with_ttl(key.as_ref(), value.as_ref(), *ttl);
+                    }
+                    WriteBatchRecord::Merge(key, operand) => {
+                        txn.merge(key.as_ref(), operand.as_ref())?;
+                    }
                 }
             }
             txn.commit()?;
@@ -637,6 +984,18 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Put a key-value pair that expires after `ttl` into the storage. Once expired, the key reads as deleted.
+    pub fn put_with_ttl(self: &Arc<Self>, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        if !self.options.serializable {
+            self.write_batch_inner(&[WriteBatchRecord::PutWithTtl(key, value, ttl)])?;
+        } else {
+            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            txn.put_with_ttl(key, value, ttl);
+            txn.commit()?;
+        }
+        Ok(())
+    }
+
     /// Remove a key from the storage by writing an empty value.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
         if !self.options.serializable {
@@ -649,7 +1008,31 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
+    /// Remove the keys in `[lower, upper)` from the storage by writing a range tombstone.
+    pub fn delete_range(self: &Arc<Self>, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if !self.options.serializable {
+            self.write_batch_inner(&[WriteBatchRecord::DelRange(lower, upper)])?;
+        } else {
+            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            txn.delete_range(lower, upper);
+            txn.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Write a merge operand of a key, which is applied to its value by the merge operator when it is read.
+    pub fn merge(self: &Arc<Self>, key: &[u8], operand: &[u8]) -> Result<()> {
+        if !self.options.serializable {
+            self.write_batch_inner(&[WriteBatchRecord::Merge(key, operand)])?;
+        } else {
+            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            txn.merge(key, operand)?;
+            txn.commit()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn try_freeze(&self, estimated_size: usize) -> Result<()> {
         if estimated_size >= self.options.target_sst_size {
             let state_lock = self.state_lock.lock();
             let guard = self.state.read();
@@ -670,6 +1053,43 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
+    pub(crate) fn path_of_vlog_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.vlog", id))
+    }
+
+    pub(crate) fn path_of_vlog(&self, id: usize) -> PathBuf {
+        Self::path_of_vlog_static(&self.path, id)
+    }
+
+    /// Create a builder for a new SST, separating large values into a new value log if enabled. The SST is
+    /// written through the rate limiter, if any, at `priority`, and records `newest_write_time`.
+    pub(crate) fn new_sst_builder(
+        &self,
+        priority: IoPriority,
+        newest_write_time: u64,
+    ) -> SsTableBuilder {
+        let mut builder = match &self.options.value_separation {
+            Some(value_separation) => {
+                let log_id = self.next_sst_id();
+                SsTableBuilder::new_with_value_log(
+                    self.options.block_size,
+                    self.options.compression,
+                    ValueLogBuilder::new(log_id, self.path_of_vlog(log_id)),
+                    value_separation.min_value_size,
+                )
+            }
+            None => SsTableBuilder::new_with_compression(
+                self.options.block_size,
+                self.options.compression,
+            ),
+        };
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            builder.set_rate_limiter(rate_limiter.clone(), priority);
+        }
+        builder.set_newest_write_time(newest_write_time);
+        builder
+    }
+
     pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
@@ -737,7 +1157,8 @@ impl LsmStorageInner {
                 .clone();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
+        // The memtable was written before now.
+        let mut builder = self.new_sst_builder(IoPriority::High, self.options.clock.now());
         flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
         let sst = Arc::new(builder.build(
@@ -762,13 +1183,15 @@ impl LsmStorageInner {
                 snapshot.levels.insert(0, (sst_id, vec![sst_id]));
             }
             println!("flushed {}.sst with size={}", sst_id, sst.table_size());
+            snapshot.open_value_logs(&sst, &self.path)?;
             snapshot.sstables.insert(sst_id, sst);
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        self.write_controller.notify();
 
         if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
+            self.retire_wal(sst_id, flush_memtable.max_ts())?;
         }
 
         self.manifest()
@@ -884,10 +1307,14 @@ impl LsmStorageInner {
         let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
         let iter = TwoMergeIterator::create(iter, MergeIterator::create(level_iters))?;
 
+        let range_tombstones = snapshot.range_tombstones(lower, upper, read_ts);
         Ok(FusedIterator::new(LsmIterator::new(
             iter,
             map_bound(upper),
             read_ts,
+            snapshot,
+            range_tombstones,
+            self,
         )?))
     }
 }
@@ -33,6 +33,12 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// SSTs rewritten by value log GC, as `(old SST id, new SST id)`.
+    ValueLogGc(Vec<(usize, usize)>),
+    /// The last key compacted from a level, as `(level, key)`, for the round-robin SST selection.
+    CompactionCursor(usize, Vec<u8>),
+    /// A consumer of the change feed moved to a ts, or unregistered when `None`.
+    UpdateConsumer(String, Option<u64>),
 }
 
 impl Manifest {
@@ -15,7 +15,7 @@
 use std::ops::Bound;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 
 use anyhow::Result;
 use bytes::Bytes;
@@ -23,20 +23,34 @@ use crossbeam_skiplist::SkipMap;
 use crossbeam_skiplist::map::Entry;
 use ouroboros::self_referencing;
 
-use crate::iterators::StorageIterator;
+use crate::iterators::{EntryType, StorageIterator};
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{Wal, WalRecoveryMode, WalRecoveryReport};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    pub(crate) map: Arc<SkipMap<KeyBytes, (EntryType, Bytes)>>,
+    /// Range tombstones, as `(start, ts) -> end`.
+    pub(crate) range_tombstones: Arc<SkipMap<KeyBytes, Bytes>>,
     wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
+    max_ts: Arc<AtomicU64>,
+}
+
+/// Inserts the range tombstone `[start, end)` keyed by `(start, ts)`. The records of a batch share a ts, so the
+/// tombstones of a batch starting at the same key are merged by keeping the furthest end.
+pub(crate) fn insert_range_tombstone(
+    range_tombstones: &SkipMap<KeyBytes, Bytes>,
+    key: KeyBytes,
+    end: Bytes,
+) {
+    range_tombstones.compare_insert(key, end.clone(), |existing| *existing < end);
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -94,8 +108,10 @@ impl MemTable {
         Self {
             id,
             map: Arc::new(SkipMap::new()),
+            range_tombstones: Arc::new(SkipMap::new()),
             wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            max_ts: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -104,29 +120,46 @@ impl MemTable {
         Ok(Self {
             id,
             map: Arc::new(SkipMap::new()),
+            range_tombstones: Arc::new(SkipMap::new()),
             wal: Some(Wal::create(path.as_ref())?),
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            max_ts: Arc::new(AtomicU64::new(0)),
         })
     }
 
-    /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+    /// Create a memtable from WAL, and report what the recovery replayed and dropped.
+    pub fn recover_from_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        mode: WalRecoveryMode,
+    ) -> Result<(Self, WalRecoveryReport)> {
         let map = Arc::new(SkipMap::new());
-        Ok(Self {
+        let range_tombstones = Arc::new(SkipMap::new());
+        let (wal, report) = Wal::recover_with_mode(path.as_ref(), &map, &range_tombstones, mode)?;
+        let max_ts = map
+            .iter()
+            .map(|entry| entry.key().ts())
+            .chain(range_tombstones.iter().map(|entry| entry.key().ts()))
+            .max()
+            .unwrap_or_default();
+        let memtable = Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
+            wal: Some(wal),
             map,
+            range_tombstones,
             approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+            max_ts: Arc::new(AtomicU64::new(max_ts)),
+        };
+        Ok((memtable, report))
     }
 
-    /// Get a value by key. Should not be used in week 3.
+    /// Get a value by key, where a deletion has an empty value. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
         let key_bytes = KeyBytes::from_bytes_with_ts(
             Bytes::from_static(unsafe { std::mem::transmute::<&[u8], &[u8]>(key.key_ref()) }),
             key.ts(),
         );
-        self.map.get(&key_bytes).map(|e| e.value().clone())
+        self.map.get(&key_bytes).map(|e| e.value().1.clone())
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -157,21 +190,41 @@ impl MemTable {
         self.put_batch(&[(key, value)])
     }
 
-    /// Implement this in week 3, day 5.
+    /// Implement this in week 3, day 5. An empty value is written as a deletion.
     pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        let data = data
+            .iter()
+            .map(|(key, value)| (*key, *value, EntryType::from_value(value)))
+            .collect::<Vec<_>>();
+        self.write_batch(&data)
+    }
+
+    /// Write a batch of entries, where an `EntryType::RangeDelete` entry holds the end of the range as its value.
+    pub fn write_batch(&self, data: &[(KeySlice, &[u8], EntryType)]) -> Result<()> {
+        // Write the WAL first, so that a batch the WAL rejects never becomes visible in the memtable.
+        if let Some(ref wal) = self.wal {
+            wal.put_batch(data)?;
+        }
         let mut estimated_size = 0;
-        for (key, value) in data {
+        let mut max_ts = 0;
+        for (key, value, entry_type) in data {
             estimated_size += key.raw_len() + value.len();
-            self.map.insert(
-                key.to_key_vec().into_key_bytes(),
-                Bytes::copy_from_slice(value),
-            );
+            max_ts = max_ts.max(key.ts());
+            let key = key.to_key_vec().into_key_bytes();
+            let value = Bytes::copy_from_slice(value);
+            match entry_type {
+                EntryType::RangeDelete => {
+                    insert_range_tombstone(&self.range_tombstones, key, value);
+                }
+                _ => {
+                    self.map.insert(key, (*entry_type, value));
+                }
+            }
         }
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
-        if let Some(ref wal) = self.wal {
-            wal.put_batch(data)?;
-        }
+        self.max_ts
+            .fetch_max(max_ts, std::sync::atomic::Ordering::Relaxed);
         Ok(())
     }
 
@@ -188,17 +241,40 @@ impl MemTable {
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
             iter_builder: |map| map.range((lower, upper)),
-            item: (KeyBytes::new(), Bytes::new()),
+            item: (KeyBytes::new(), EntryType::Put, Bytes::new()),
         }
         .build();
         iter.next().unwrap();
         iter
     }
 
+    /// Get the range tombstones that may delete keys in the given range.
+    pub fn range_tombstones(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Vec<RangeTombstone> {
+        self.range_tombstones
+            .iter()
+            .map(|entry| {
+                RangeTombstone::new(
+                    Bytes::copy_from_slice(entry.key().key_ref()),
+                    entry.value().clone(),
+                    entry.key().ts(),
+                )
+            })
+            .filter(|tombstone| tombstone.overlaps(lower, upper))
+            .collect()
+    }
+
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+            let (entry_type, value) = entry.value();
+            builder.add_with_type(entry.key().as_key_slice(), value, *entry_type);
+        }
+        for tombstone in self.range_tombstones(Bound::Unbounded, Bound::Unbounded) {
+            builder.add_range_tombstone(tombstone);
         }
         Ok(())
     }
@@ -214,7 +290,12 @@ impl MemTable {
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.is_empty()
+    }
+
+    /// The largest ts of the entries and range tombstones in the mem-table.
+    pub fn max_ts(&self) -> u64 {
+        self.max_ts.load(std::sync::atomic::Ordering::Relaxed)
     }
 }
 
@@ -223,7 +304,7 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
     KeyBytes,
     (Bound<KeyBytes>, Bound<KeyBytes>),
     KeyBytes,
-    Bytes,
+    (EntryType, Bytes),
 >;
 
 /// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
@@ -233,20 +314,22 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
 #[self_referencing]
 pub struct MemTableIterator {
     /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<KeyBytes, Bytes>>,
+    map: Arc<SkipMap<KeyBytes, (EntryType, Bytes)>>,
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (KeyBytes, Bytes),
+    /// Stores the current key, entry type and value.
+    item: (KeyBytes, EntryType, Bytes),
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, KeyBytes, (EntryType, Bytes)>>,
+    ) -> (KeyBytes, EntryType, Bytes) {
         entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
+            .map(|x| (x.key().clone(), x.value().0, x.value().1.clone()))
+            .unwrap_or_else(|| (KeyBytes::new(), EntryType::Put, Bytes::new()))
     }
 }
 
@@ -254,13 +337,17 @@ impl StorageIterator for MemTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().2[..]
     }
 
     fn key(&self) -> KeySlice {
         self.borrow_item().0.as_key_slice()
     }
 
+    fn entry_type(&self) -> EntryType {
+        self.borrow_item().1
+    }
+
     fn is_valid(&self) -> bool {
         !self.borrow_item().0.is_empty()
     }
@@ -0,0 +1,85 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merge operators for read-modify-write without a read. `merge` writes an operand of a key, which is stored
+//! as an `EntryType::Merge` entry. Readers apply the operands to the value below them when they read the key,
+//! and compaction folds the operands below the watermark.
+
+use std::fmt;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::iterators::EntryType;
+use crate::ttl::{decode_expiring_value, encode_expiring_value, is_expired};
+
+/// Combines the operands written by `merge` with the value of a key.
+pub trait MergeOperator: Send + Sync {
+    /// The name of the operator, for debugging.
+    fn name(&self) -> &str;
+
+    /// Applies `operands`, oldest first, to `existing`, the value of `key` before them, which is `None` if the
+    /// key does not exist or is deleted.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes>;
+
+    /// Combines two consecutive operands of `key` into one that has the same effect as applying `older` and
+    /// then `newer`. All entries of a write batch share one timestamp, so this is also how operands of the same
+    /// key in one batch or transaction are combined.
+    fn partial_merge(&self, key: &[u8], older: &[u8], newer: &[u8]) -> Result<Bytes>;
+}
+
+impl dyn MergeOperator {
+    /// Combines `operands`, oldest first, into one operand.
+    pub(crate) fn partial_merge_all(&self, key: &[u8], operands: &[&[u8]]) -> Result<Bytes> {
+        let mut merged = Bytes::copy_from_slice(operands[0]);
+        for operand in &operands[1..] {
+            merged = self.partial_merge(key, &merged, operand)?;
+        }
+        Ok(merged)
+    }
+
+    /// Combines `operand` with an earlier write of `key` at the same ts, and returns the entry that replaces the
+    /// write. An expiring value keeps its expiry time.
+    pub(crate) fn merge_into(
+        &self,
+        key: &[u8],
+        entry_type: EntryType,
+        value: &[u8],
+        operand: &[u8],
+        now: u64,
+    ) -> Result<(EntryType, Bytes)> {
+        if entry_type == EntryType::Delete || is_expired(entry_type, value, now) {
+            return Ok((EntryType::Put, self.full_merge(key, None, &[operand])?));
+        }
+        match entry_type {
+            EntryType::Merge => Ok((EntryType::Merge, self.partial_merge(key, value, operand)?)),
+            EntryType::ExpiringPut => {
+                let (expiry, value) = decode_expiring_value(value);
+                let merged = self.full_merge(key, Some(value), &[operand])?;
+                let merged = encode_expiring_value(expiry, &merged);
+                Ok((EntryType::ExpiringPut, merged.into()))
+            }
+            _ => Ok((
+                EntryType::Put,
+                self.full_merge(key, Some(value), &[operand])?,
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MergeOperator({})", self.name())
+    }
+}
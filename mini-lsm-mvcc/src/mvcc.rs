@@ -32,6 +32,8 @@ use self::{txn::Transaction, watermark::Watermark};
 
 pub(crate) struct CommittedTxnData {
     pub(crate) key_hashes: HashSet<u32>,
+    /// Whether the transaction deleted any range, which is not covered by `key_hashes`.
+    pub(crate) deletes_ranges: bool,
     #[allow(dead_code)]
     pub(crate) read_ts: u64,
     #[allow(dead_code)]
@@ -77,6 +79,7 @@ impl LsmMvccInner {
             inner,
             read_ts,
             local_storage: Arc::new(SkipMap::new()),
+            local_range_deletes: Mutex::new(Vec::new()),
             committed: Arc::new(AtomicBool::new(false)),
             key_hashes: if serializable {
                 Some(Mutex::new((HashSet::new(), HashSet::new())))
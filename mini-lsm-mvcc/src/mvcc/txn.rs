@@ -19,6 +19,7 @@ use std::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
+    time::Duration,
 };
 
 use anyhow::{Result, bail};
@@ -28,17 +29,21 @@ use ouroboros::self_referencing;
 use parking_lot::Mutex;
 
 use crate::{
-    iterators::{StorageIterator, two_merge_iterator::TwoMergeIterator},
+    iterators::{EntryType, StorageIterator, two_merge_iterator::TwoMergeIterator},
     lsm_iterator::{FusedIterator, LsmIterator},
     lsm_storage::{LsmStorageInner, WriteBatchRecord},
     mem_table::map_bound,
     mvcc::CommittedTxnData,
+    ttl::{decode_expiring_value, encode_expiring_value, expiry_of, is_expired, user_value},
 };
 
 pub struct Transaction {
     pub(crate) read_ts: u64,
     pub(crate) inner: Arc<LsmStorageInner>,
-    pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
+    pub(crate) local_storage: Arc<SkipMap<Bytes, (EntryType, Bytes)>>,
+    /// Ranges deleted by the transaction, as `[lower, upper)`. Writes to `local_storage` made before a range
+    /// is deleted are removed, so the local entries always take precedence over the ranges.
+    pub(crate) local_range_deletes: Mutex<Vec<(Bytes, Bytes)>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
     pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
@@ -55,11 +60,19 @@ impl Transaction {
             read_set.insert(farmhash::hash32(key));
         }
         if let Some(entry) = self.local_storage.get(key) {
-            if entry.value().is_empty() {
+            let (entry_type, value) = entry.value();
+            if is_expired(*entry_type, value, self.inner.options.clock.now()) {
                 return Ok(None);
-            } else {
-                return Ok(Some(entry.value().clone()));
             }
+            return match entry_type {
+                EntryType::Delete => Ok(None),
+                EntryType::Merge => Ok(Some(self.resolve_local_merge(key, value)?)),
+                EntryType::ExpiringPut => Ok(Some(value.slice(std::mem::size_of::<u64>()..))),
+                _ => Ok(Some(value.clone())),
+            };
+        }
+        if self.is_range_deleted_locally(key) {
+            return Ok(None);
         }
         self.inner.get_with_ts(key, self.read_ts)
     }
@@ -71,7 +84,7 @@ impl Transaction {
         let mut local_iter = TxnLocalIteratorBuilder {
             map: self.local_storage.clone(),
             iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
-            item: (Bytes::new(), Bytes::new()),
+            item: (Bytes::new(), EntryType::Put, Bytes::new()),
         }
         .build();
         let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
@@ -90,8 +103,30 @@ impl Transaction {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (EntryType::Put, Bytes::copy_from_slice(value)),
+        );
+        if let Some(key_hashes) = &self.key_hashes {
+            let mut key_hashes = key_hashes.lock();
+            let (write_hashes, _) = &mut *key_hashes;
+            write_hashes.insert(farmhash::hash32(key));
+        }
+    }
+
+    /// Puts a key-value pair that expires `ttl` after this call.
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let expiry = expiry_of(self.inner.options.clock.now(), ttl);
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (
+                EntryType::ExpiringPut,
+                encode_expiring_value(expiry, value).into(),
+            ),
+        );
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
@@ -103,13 +138,80 @@ impl Transaction {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (EntryType::Delete, Bytes::new()),
+        );
+        if let Some(key_hashes) = &self.key_hashes {
+            let mut key_hashes = key_hashes.lock();
+            let (write_hashes, _) = &mut *key_hashes;
+            write_hashes.insert(farmhash::hash32(key));
+        }
+    }
+
+    /// Writes a merge operand of `key`. Like `put`, this does not read the key, so it does not conflict with
+    /// other writes of the key under `serializable`.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let merge_operator = self.inner.merge_operator()?;
+        // All writes of the transaction are committed at one ts, so an operand is combined with the local write
+        // of its key.
+        let entry = match self.local_storage.get(key) {
+            Some(entry) => {
+                let (entry_type, value) = entry.value();
+                let now = self.inner.options.clock.now();
+                merge_operator.merge_into(key, *entry_type, value, operand, now)?
+            }
+            None => (EntryType::Merge, Bytes::copy_from_slice(operand)),
+        };
         self.local_storage
-            .insert(Bytes::copy_from_slice(key), Bytes::new());
+            .insert(Bytes::copy_from_slice(key), entry);
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
             write_hashes.insert(farmhash::hash32(key));
         }
+        Ok(())
+    }
+
+    /// Applies a merge operand in `local_storage` to the value of `key` at `read_ts`. A range deleted by the
+    /// transaction that contains `key` was deleted before the merge, as it would have removed the operand
+    /// otherwise.
+    fn resolve_local_merge(&self, key: &[u8], operand: &[u8]) -> Result<Bytes> {
+        let existing = if self.is_range_deleted_locally(key) {
+            None
+        } else {
+            self.inner.get_with_ts(key, self.read_ts)?
+        };
+        self.inner
+            .merge_operator()?
+            .full_merge(key, existing.as_deref(), &[operand])
+    }
+
+    /// Deletes the keys in `[lower, upper)`.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        assert!(lower < upper, "range cannot be empty");
+        let (lower, upper) = (Bytes::copy_from_slice(lower), Bytes::copy_from_slice(upper));
+        for entry in self
+            .local_storage
+            .range::<Bytes, _>((Bound::Included(&lower), Bound::Excluded(&upper)))
+        {
+            entry.remove();
+        }
+        self.local_range_deletes.lock().push((lower, upper));
+    }
+
+    /// Whether `key` is in a range deleted by the transaction. The caller checks `local_storage` first.
+    fn is_range_deleted_locally(&self, key: &[u8]) -> bool {
+        self.local_range_deletes
+            .lock()
+            .iter()
+            .any(|(lower, upper)| &lower[..] <= key && key < &upper[..])
     }
 
     pub fn commit(&self) -> Result<()> {
@@ -118,6 +220,7 @@ impl Transaction {
             .expect("cannot operate on committed txn!");
         let _commit_lock = self.inner.mvcc().commit_lock.lock();
         let serializability_check;
+        let local_range_deletes = std::mem::take(&mut *self.local_range_deletes.lock());
         if let Some(guard) = &self.key_hashes {
             let guard = guard.lock();
             let (write_set, read_set) = &*guard;
@@ -125,9 +228,13 @@ impl Transaction {
                 "commit txn: write_set: {:?}, read_set: {:?}",
                 write_set, read_set
             );
-            if !write_set.is_empty() {
+            if !write_set.is_empty() || !local_range_deletes.is_empty() {
                 let committed_txns = self.inner.mvcc().committed_txns.lock();
                 for (_, txn_data) in committed_txns.range((self.read_ts + 1)..) {
+                    // The read set only holds key hashes, so a range deletion conflicts with any read.
+                    if txn_data.deletes_ranges && !read_set.is_empty() {
+                        bail!("serializable check failed");
+                    }
                     for key_hash in read_set {
                         if txn_data.key_hashes.contains(key_hash) {
                             bail!("serializable check failed");
@@ -139,16 +246,29 @@ impl Transaction {
         } else {
             serializability_check = false;
         }
-        let batch = self
-            .local_storage
-            .iter()
-            .map(|entry| {
-                if entry.value().is_empty() {
-                    WriteBatchRecord::Del(entry.key().clone())
-                } else {
-                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+        let deletes_ranges = !local_range_deletes.is_empty();
+        // Range deletions go first, so that they do not delete the local writes made after them.
+        let batch = local_range_deletes
+            .into_iter()
+            .map(|(lower, upper)| WriteBatchRecord::DelRange(lower, upper))
+            .chain(self.local_storage.iter().map(|entry| {
+                let (entry_type, value) = entry.value();
+                match entry_type {
+                    EntryType::Delete => WriteBatchRecord::Del(entry.key().clone()),
+                    EntryType::Merge => WriteBatchRecord::Merge(entry.key().clone(), value.clone()),
+                    // The expiry time was fixed by `put_with_ttl`, so the batch is given the TTL left.
+                    EntryType::ExpiringPut => {
+                        let (expiry, user_value) = decode_expiring_value(value);
+                        let ttl = expiry.saturating_sub(self.inner.options.clock.now());
+                        WriteBatchRecord::PutWithTtl(
+                            entry.key().clone(),
+                            Bytes::copy_from_slice(user_value),
+                            Duration::from_millis(ttl),
+                        )
+                    }
+                    _ => WriteBatchRecord::Put(entry.key().clone(), value.clone()),
                 }
-            })
+            }))
             .collect::<Vec<_>>();
         let ts = self.inner.write_batch_inner(&batch)?;
         if serializability_check {
@@ -160,6 +280,7 @@ impl Transaction {
                 ts,
                 CommittedTxnData {
                     key_hashes: std::mem::take(write_set),
+                    deletes_ranges,
                     read_ts: self.read_ts,
                     commit_ts: ts,
                 },
@@ -186,26 +307,33 @@ impl Drop for Transaction {
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    Bytes,
+    (Bound<Bytes>, Bound<Bytes>),
+    Bytes,
+    (EntryType, Bytes),
+>;
 
 #[self_referencing]
 pub struct TxnLocalIterator {
     /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<Bytes, (EntryType, Bytes)>>,
     /// Stores a skipmap iterator that refers to the lifetime of `TxnLocalIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
-    item: (Bytes, Bytes),
+    item: (Bytes, EntryType, Bytes),
 }
 
 impl TxnLocalIterator {
-    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, Bytes, (EntryType, Bytes)>>,
+    ) -> (Bytes, EntryType, Bytes) {
         entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::new(), Bytes::new()))
+            .map(|x| (x.key().clone(), x.value().0, x.value().1.clone()))
+            .unwrap_or_else(|| (Bytes::new(), EntryType::Put, Bytes::new()))
     }
 }
 
@@ -213,7 +341,11 @@ impl StorageIterator for TxnLocalIterator {
     type KeyType<'a> = &'a [u8];
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().2[..]
+    }
+
+    fn entry_type(&self) -> EntryType {
+        self.borrow_item().1
     }
 
     fn key(&self) -> &[u8] {
@@ -234,6 +366,8 @@ impl StorageIterator for TxnLocalIterator {
 pub struct TxnIterator {
     txn: Arc<Transaction>,
     iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    /// The value of the current entry if it is a local merge operand applied to the value in the storage.
+    merged_value: Option<Bytes>,
 }
 
 impl TxnIterator {
@@ -241,7 +375,11 @@ impl TxnIterator {
         txn: Arc<Transaction>,
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
-        let mut iter = Self { txn, iter };
+        let mut iter = Self {
+            txn,
+            iter,
+            merged_value: None,
+        };
         iter.skip_deletes()?;
         if iter.is_valid() {
             iter.add_to_read_set(iter.key());
@@ -250,12 +388,30 @@ impl TxnIterator {
     }
 
     fn skip_deletes(&mut self) -> Result<()> {
-        while self.iter.is_valid() && self.iter.value().is_empty() {
+        let now = self.txn.inner.options.clock.now();
+        while self.iter.is_valid()
+            && (self.iter.entry_type() == EntryType::Delete
+                || is_expired(self.iter.entry_type(), self.iter.value(), now)
+                || self.is_range_deleted_locally())
+        {
             self.iter.next()?;
         }
+        self.merged_value = None;
+        if self.iter.is_valid() && self.iter.entry_type() == EntryType::Merge {
+            let merged = self
+                .txn
+                .resolve_local_merge(self.iter.key(), self.iter.value())?;
+            self.merged_value = Some(merged);
+        }
         Ok(())
     }
 
+    /// Whether the current entry comes from the storage and is in a range deleted by the transaction.
+    fn is_range_deleted_locally(&self) -> bool {
+        let key = self.iter.key();
+        !self.txn.local_storage.contains_key(key) && self.txn.is_range_deleted_locally(key)
+    }
+
     fn add_to_read_set(&self, key: &[u8]) {
         if let Some(guard) = &self.txn.key_hashes {
             let mut guard = guard.lock();
@@ -272,7 +428,10 @@ impl StorageIterator for TxnIterator {
         Self: 'a;
 
     fn value(&self) -> &[u8] {
-        self.iter.value()
+        match &self.merged_value {
+            Some(value) => value,
+            None => user_value(self.iter.entry_type(), self.iter.value()),
+        }
     }
 
     fn key(&self) -> Self::KeyType<'_> {
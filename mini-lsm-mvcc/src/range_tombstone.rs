@@ -0,0 +1,279 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Range tombstones written by `delete_range`. A tombstone written at commit ts `T` deletes every version
+//! with a ts below `T` of the keys in `[start, end)`. As timestamps order all writes, it does not matter
+//! which memtable or SST a tombstone is stored in relative to the data it covers.
+
+use std::ops::Bound;
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::key::{KeyBytes, TS_RANGE_BEGIN};
+use crate::lsm_storage::LsmStorageState;
+use crate::varint::{get_varint, put_varint};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    /// The first key deleted.
+    pub start: Bytes,
+    /// The first key after the deleted range.
+    pub end: Bytes,
+    /// The commit ts of the deletion.
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, ts: u64) -> Self {
+        Self { start, end, ts }
+    }
+
+    /// Whether the tombstone deletes version `ts` of `key`.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        ts < self.ts && self.start <= key && key < self.end
+    }
+
+    /// Whether the tombstone deletes any key in the given range.
+    pub fn overlaps(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let below_upper = match upper {
+            Bound::Included(upper) => self.start <= upper,
+            Bound::Excluded(upper) => self.start < upper,
+            Bound::Unbounded => true,
+        };
+        let above_lower = match lower {
+            Bound::Included(lower) | Bound::Excluded(lower) => self.end > lower,
+            Bound::Unbounded => true,
+        };
+        below_upper && above_lower
+    }
+
+    /// The part of the tombstone within `[lower, upper)`, where `None` means unbounded.
+    pub(crate) fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Option<Self> {
+        let start = match lower {
+            Some(lower) if lower > &self.start[..] => Bytes::copy_from_slice(lower),
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if upper < &self.end[..] => Bytes::copy_from_slice(upper),
+            _ => self.end.clone(),
+        };
+        (start < end).then(|| Self::new(start, end, self.ts))
+    }
+
+    /// The smallest internal key the tombstone deletes, used to extend the key range of an SST.
+    pub(crate) fn first_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes_with_ts(self.start.clone(), self.ts)
+    }
+
+    /// An internal key ordered after every key the tombstone deletes and before every version of `end`, used to
+    /// extend the key range of an SST.
+    pub(crate) fn last_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes_with_ts(self.end.clone(), TS_RANGE_BEGIN)
+    }
+
+    /// Encode the range tombstone block of an SST: `count (u32) | (start_len (varint) | start | end_len (varint)
+    /// | end | ts (u64))* | checksum (u32)`.
+    pub(crate) fn encode_range_tombstones(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.put_u32(tombstones.len() as u32);
+        for tombstone in tombstones {
+            put_varint(buf, tombstone.start.len() as u64);
+            buf.put_slice(&tombstone.start);
+            put_varint(buf, tombstone.end.len() as u64);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.ts);
+        }
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    /// Decode the range tombstone block of an SST.
+    pub(crate) fn decode_range_tombstones(buf: &[u8]) -> Result<Vec<RangeTombstone>> {
+        let checksum_offset = buf.len() - std::mem::size_of::<u32>();
+        if (&buf[checksum_offset..]).get_u32() != crc32fast::hash(&buf[..checksum_offset]) {
+            bail!("checksum mismatched for range tombstones");
+        }
+        let mut buf = &buf[..checksum_offset];
+        let count = buf.get_u32() as usize;
+        let mut tombstones = Vec::with_capacity(count);
+        for _ in 0..count {
+            let start_len = get_varint(&mut buf) as usize;
+            let start = Bytes::copy_from_slice(&buf[..start_len]);
+            buf.advance(start_len);
+            let end_len = get_varint(&mut buf) as usize;
+            let end = Bytes::copy_from_slice(&buf[..end_len]);
+            buf.advance(end_len);
+            let ts = buf.get_u64();
+            tombstones.push(RangeTombstone::new(start, end, ts));
+        }
+        Ok(tombstones)
+    }
+}
+
+/// The part of the range tombstones within `[start, end)`, where no tombstone starts or ends inside the fragment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RangeTombstoneFragment {
+    pub(crate) start: Bytes,
+    pub(crate) end: Bytes,
+    /// The commit ts of the tombstones covering the fragment, in descending order.
+    pub(crate) ts: Vec<u64>,
+}
+
+impl RangeTombstoneFragment {
+    /// Whether the fragment deletes version `ts` of a key in it for a reader at `read_ts`.
+    fn deletes(&self, ts: u64, read_ts: u64) -> bool {
+        self.ts
+            .iter()
+            .find(|tombstone_ts| **tombstone_ts <= read_ts)
+            .is_some_and(|tombstone_ts| *tombstone_ts > ts)
+    }
+}
+
+/// Range tombstones split into sorted, non-overlapping fragments, so that the tombstones covering a key are
+/// found with a binary search, or by moving a cursor forward as the keys looked up increase.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FragmentedRangeTombstones {
+    fragments: Vec<RangeTombstoneFragment>,
+}
+
+impl FragmentedRangeTombstones {
+    pub(crate) fn new(tombstones: &[RangeTombstone]) -> Self {
+        let mut tombstones = tombstones
+            .iter()
+            .filter(|tombstone| tombstone.start < tombstone.end)
+            .collect::<Vec<_>>();
+        tombstones.sort_by(|a, b| a.start.cmp(&b.start));
+        let mut bounds = tombstones
+            .iter()
+            .flat_map(|tombstone| [&tombstone.start, &tombstone.end])
+            .collect::<Vec<_>>();
+        bounds.sort();
+        bounds.dedup();
+        // sweep the bounds, keeping the tombstones that cover the space between each pair of them
+        let mut fragments = Vec::new();
+        let mut active: Vec<&RangeTombstone> = Vec::new();
+        let mut next = 0;
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            active.retain(|tombstone| tombstone.end > *start);
+            while next < tombstones.len() && tombstones[next].start == *start {
+                active.push(tombstones[next]);
+                next += 1;
+            }
+            if active.is_empty() {
+                continue;
+            }
+            let mut ts = active
+                .iter()
+                .map(|tombstone| tombstone.ts)
+                .collect::<Vec<_>>();
+            ts.sort_unstable_by(|a, b| b.cmp(a));
+            ts.dedup();
+            fragments.push(RangeTombstoneFragment {
+                start: start.clone(),
+                end: end.clone(),
+                ts,
+            });
+        }
+        Self { fragments }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// The index of the first fragment that ends after `key`.
+    fn seek(&self, key: &[u8]) -> usize {
+        self.fragments
+            .partition_point(|fragment| &fragment.end[..] <= key)
+    }
+
+    /// The fragment at `idx` if it covers `key`.
+    fn fragment_covering(&self, idx: usize, key: &[u8]) -> Option<&RangeTombstoneFragment> {
+        self.fragments
+            .get(idx)
+            .filter(|fragment| &fragment.start[..] <= key)
+    }
+
+    /// Whether version `ts` of `key` is deleted for a reader at `read_ts`. The keys are looked up in increasing
+    /// order, where `cursor` is the fragment the last key was found in, starting from 0, and only moves forward.
+    pub(crate) fn deletes_from_cursor(
+        &self,
+        cursor: &mut usize,
+        key: &[u8],
+        ts: u64,
+        read_ts: u64,
+    ) -> bool {
+        if self
+            .fragments
+            .get(*cursor)
+            .is_some_and(|fragment| &fragment.end[..] <= key)
+        {
+            *cursor +=
+                self.fragments[*cursor..].partition_point(|fragment| &fragment.end[..] <= key);
+        }
+        self.fragment_covering(*cursor, key)
+            .is_some_and(|fragment| fragment.deletes(ts, read_ts))
+    }
+
+    /// The tombstones that may delete keys in the given range, split at the bounds of the fragments.
+    pub(crate) fn overlapping(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> impl Iterator<Item = RangeTombstone> + '_ {
+        let begin = match lower {
+            Bound::Included(lower) | Bound::Excluded(lower) => self.seek(lower),
+            Bound::Unbounded => 0,
+        };
+        let end = match upper {
+            Bound::Included(upper) => self
+                .fragments
+                .partition_point(|fragment| &fragment.start[..] <= upper),
+            Bound::Excluded(upper) => self
+                .fragments
+                .partition_point(|fragment| &fragment.start[..] < upper),
+            Bound::Unbounded => self.fragments.len(),
+        };
+        self.fragments[begin..end.max(begin)]
+            .iter()
+            .flat_map(|fragment| {
+                fragment.ts.iter().map(|ts| {
+                    RangeTombstone::new(fragment.start.clone(), fragment.end.clone(), *ts)
+                })
+            })
+    }
+}
+
+impl LsmStorageState {
+    /// Collects the range tombstones visible at `read_ts` that may delete keys in the given range, from the
+    /// memtables and all SSTs. Range tombstones are kept in memory, so this does not read any block.
+    pub(crate) fn range_tombstones(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Vec<RangeTombstone> {
+        let mut tombstones = self.memtable.range_tombstones(lower, upper);
+        for memtable in &self.imm_memtables {
+            tombstones.extend(memtable.range_tombstones(lower, upper));
+        }
+        for sst in self.sstables.values() {
+            tombstones.extend(sst.range_tombstone_fragments().overlapping(lower, upper));
+        }
+        tombstones.retain(|tombstone| tombstone.ts <= read_ts);
+        tombstones
+    }
+}
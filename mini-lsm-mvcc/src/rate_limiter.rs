@@ -0,0 +1,136 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limiting of background writes. Flushes and compactions take tokens from a shared token bucket before
+//! they write SST and value log data, so they do not use up the disk bandwidth of foreground reads.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// How often the bucket is refilled. The bucket holds the tokens of one refill period, which is also the most a
+/// single request is granted.
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+
+/// The priority of a write. A low priority write waits while any high priority write is waiting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Flushes, which block writes once too many memtables are waiting.
+    High,
+    /// Compactions and value log garbage collection.
+    Low,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes per second, or 0 for no limit.
+    bytes_per_second: u64,
+    available: u64,
+    last_refill: Instant,
+    high_waiting: usize,
+}
+
+impl Bucket {
+    fn capacity(&self) -> u64 {
+        (self.bytes_per_second * REFILL_PERIOD.as_millis() as u64 / 1000).max(1)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill);
+        let tokens = (self.bytes_per_second as u128 * elapsed.as_nanos() / 1_000_000_000) as u64;
+        if tokens > 0 {
+            self.available = (self.available + tokens).min(self.capacity());
+            self.last_refill = now;
+        }
+    }
+}
+
+/// A token bucket shared by the flushes and compactions of a storage. The rate can be changed while they run.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+    cvar: Condvar,
+    total_bytes: [AtomicU64; 2],
+}
+
+impl RateLimiter {
+    /// Create a rate limiter that lets through `bytes_per_second`, or everything if it is 0.
+    pub fn new(bytes_per_second: u64) -> Self {
+        let mut bucket = Bucket {
+            bytes_per_second,
+            available: 0,
+            last_refill: Instant::now(),
+            high_waiting: 0,
+        };
+        bucket.available = bucket.capacity();
+        Self {
+            bucket: Mutex::new(bucket),
+            cvar: Condvar::new(),
+            total_bytes: Default::default(),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bucket.lock().bytes_per_second
+    }
+
+    /// Change the rate, or remove the limit if it is 0. Waiting writes continue at the new rate.
+    pub fn set_bytes_per_second(&self, bytes_per_second: u64) {
+        let mut bucket = self.bucket.lock();
+        bucket.refill(Instant::now());
+        bucket.bytes_per_second = bytes_per_second;
+        bucket.available = bucket.available.min(bucket.capacity());
+        self.cvar.notify_all();
+    }
+
+    /// The bytes granted to the writes of a priority so far.
+    pub fn total_bytes(&self, priority: IoPriority) -> u64 {
+        self.total_bytes[priority as usize].load(Ordering::Relaxed)
+    }
+
+    /// Wait until some of `bytes` may be written, and return how many. It is at most the tokens of one refill
+    /// period, so a large write is made of several requests.
+    pub fn request(&self, bytes: usize, priority: IoPriority) -> usize {
+        let mut bucket = self.bucket.lock();
+        if priority == IoPriority::High {
+            bucket.high_waiting += 1;
+        }
+        let granted = loop {
+            if bucket.bytes_per_second == 0 {
+                break bytes;
+            }
+            bucket.refill(Instant::now());
+            let wanted = (bytes as u64).min(bucket.capacity());
+            if priority == IoPriority::Low && bucket.high_waiting > 0 {
+                self.cvar.wait_for(&mut bucket, REFILL_PERIOD);
+                continue;
+            }
+            if bucket.available >= wanted {
+                bucket.available -= wanted;
+                break wanted as usize;
+            }
+            let missing = (wanted - bucket.available) as u128;
+            let wait = missing * 1_000_000_000 / bucket.bytes_per_second as u128;
+            self.cvar
+                .wait_for(&mut bucket, Duration::from_nanos(wait as u64 + 1));
+        };
+        if priority == IoPriority::High {
+            bucket.high_waiting -= 1;
+            self.cvar.notify_all();
+        }
+        self.total_bytes[priority as usize].fetch_add(granted as u64, Ordering::Relaxed);
+        granted
+    }
+}
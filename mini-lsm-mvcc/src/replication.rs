@@ -0,0 +1,401 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! WAL-shipping replication. A `Follower` is bootstrapped from a checkpoint of the leader, and a `WalShipper`
+//! ships it the write batches the leader commits after the checkpoint, read from the change feed. Each message is
+//! `leader_ts (u64) | batch_size (u32) | records | checksum (u32)`, where the records are encoded as in a WAL and
+//! keep the commit ts of the leader, and `leader_ts` is the latest commit ts of the leader when the message is
+//! shipped. A message without records is a heartbeat: every batch up to its `leader_ts` was shipped before it.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use bytes::{Buf, Bytes};
+use parking_lot::Mutex;
+
+use crate::change_feed::Update;
+use crate::iterators::EntryType;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+use crate::mvcc::txn::TxnIterator;
+use crate::wal::{Wal, WalRecord};
+
+/// The length of `leader_ts (u64) | batch_size (u32)` at the start of a message.
+const MESSAGE_HEADER_SIZE: usize = 12;
+
+/// The default of the largest `batch_size` a follower reads from a stream.
+const DEFAULT_MAX_BATCH_SIZE: usize = 64 << 20;
+
+impl LsmStorageInner {
+    /// Creates a checkpoint of the storage in the new directory `path`, which opens as a storage of its own, and
+    /// returns the commit ts it has the writes up to. The SSTs and value logs are hard linked, and the manifest
+    /// and the WALs are copied.
+    pub fn create_checkpoint(&self, path: impl AsRef<Path>) -> Result<u64> {
+        let path = path.as_ref();
+        if !self.options.enable_wal {
+            bail!("a checkpoint copies the memtables from the WALs, which are disabled");
+        }
+        if path.exists() {
+            bail!("checkpoint directory {} already exists", path.display());
+        }
+        std::fs::create_dir_all(path)?;
+        let (checkpoint_ts, files) = {
+            // no write is committed and no memtable is frozen or flushed while the files are listed
+            let _write_lock = self.mvcc().write_lock.lock();
+            let state_lock = self.state_lock.lock();
+            // the WALs of the frozen memtables are no longer written to
+            if !self.state.read().memtable.is_empty() {
+                self.force_freeze_memtable(&state_lock)?;
+            }
+            let snapshot = {
+                let guard = self.state.read();
+                Arc::clone(&guard)
+            };
+            for memtable in std::iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables) {
+                memtable.sync_wal()?;
+            }
+            let mut files = Vec::new();
+            for id in snapshot.sstables.keys() {
+                files.push(CheckpointFile::open(
+                    self.path_of_sst(*id),
+                    Self::path_of_sst_static(path, *id),
+                    true,
+                )?);
+            }
+            for id in snapshot.value_logs.keys() {
+                files.push(CheckpointFile::open(
+                    self.path_of_vlog(*id),
+                    Self::path_of_vlog_static(path, *id),
+                    true,
+                )?);
+            }
+            // the WALs of the memtables recovered empty are not in the state, but the manifest still lists them
+            for entry in std::fs::read_dir(&self.path)? {
+                let entry_path = entry?.path();
+                if entry_path.extension().is_some_and(|ext| ext == "wal") {
+                    let to = path.join(entry_path.file_name().unwrap());
+                    files.push(CheckpointFile::open(entry_path, to, false)?);
+                }
+            }
+            files.push(CheckpointFile::open(
+                self.path.join("MANIFEST"),
+                path.join("MANIFEST"),
+                false,
+            )?);
+            (self.mvcc().latest_commit_ts(), files)
+        };
+        // The files are copied from the handles opened above, as a flush or a compaction may remove them, and up
+        // to the sizes listed, as the WAL of the new memtable and the manifest are appended to.
+        for file in files {
+            file.copy()?;
+        }
+        File::open(path)?.sync_all()?;
+        Ok(checkpoint_ts)
+    }
+
+    /// Applies the records of the batches a leader committed at the commit ts of the leader. The batches up to
+    /// the latest commit ts are already applied, or are in the checkpoint the storage is bootstrapped from.
+    fn apply_replicated_records(&self, records: &[WalRecord]) -> Result<()> {
+        let _lck = self.mvcc().write_lock.lock();
+        for records in records.chunk_by(|(a, _, _), (b, _, _)| a.ts() == b.ts()) {
+            let commit_ts = records[0].0.ts();
+            if commit_ts <= self.mvcc().latest_commit_ts() {
+                continue;
+            }
+            let data = records
+                .iter()
+                .map(|(key, entry_type, value)| (key.as_key_slice(), &value[..], *entry_type))
+                .collect::<Vec<_>>();
+            let size = {
+                let guard = self.state.read();
+                guard.memtable.write_batch(&data)?;
+                if self.should_sync_wal() {
+                    guard.memtable.sync_wal()?;
+                }
+                guard.memtable.approximate_size()
+            };
+            self.try_freeze(size)?;
+            self.mvcc().update_commit_ts(commit_ts);
+        }
+        Ok(())
+    }
+
+    /// Moves the latest commit ts to the `leader_ts` of a heartbeat, as the leader committed no batch the storage
+    /// has not applied up to it.
+    fn advance_replicated_ts(&self, leader_ts: u64) {
+        let _lck = self.mvcc().write_lock.lock();
+        if leader_ts > self.mvcc().latest_commit_ts() {
+            self.mvcc().update_commit_ts(leader_ts);
+        }
+    }
+}
+
+/// A file of the storage a checkpoint has, as of when the checkpoint is created.
+struct CheckpointFile {
+    from: PathBuf,
+    to: PathBuf,
+    file: File,
+    size: u64,
+    /// Whether the file is immutable, and hard linked if it is still in the storage.
+    link: bool,
+}
+
+impl CheckpointFile {
+    fn open(from: PathBuf, to: PathBuf, link: bool) -> Result<Self> {
+        let file = File::open(&from)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            from,
+            to,
+            file,
+            size,
+            link,
+        })
+    }
+
+    fn copy(self) -> Result<()> {
+        if self.link && std::fs::hard_link(&self.from, &self.to).is_ok() {
+            return Ok(());
+        }
+        let mut to = File::create(&self.to)?;
+        std::io::copy(&mut (&self.file).take(self.size), &mut to)?;
+        to.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Ships the write batches a leader commits to a follower.
+pub struct WalShipper {
+    leader: Arc<MiniLsm>,
+    /// The update consumer the shipper is registered as, which keeps the WALs of the batches not shipped.
+    name: String,
+    /// The commit ts up to which the batches are shipped.
+    shipped_ts: u64,
+}
+
+impl WalShipper {
+    /// Ships the batches the leader commits after `since`, which is the commit ts of the checkpoint a follower is
+    /// bootstrapped from, or the `applied_ts` of a follower that resumes. Fails if the WALs after `since` are no
+    /// longer kept, in which case the follower has to be bootstrapped from a new checkpoint.
+    pub fn new(leader: Arc<MiniLsm>, name: &str, since: u64) -> Result<Self> {
+        leader.register_update_consumer(name, since)?;
+        Ok(Self {
+            leader,
+            name: name.to_string(),
+            shipped_ts: since,
+        })
+    }
+
+    /// Creates a checkpoint of the leader in `path` to bootstrap a follower from, and ships the batches the leader
+    /// commits after it.
+    pub fn with_checkpoint(
+        leader: Arc<MiniLsm>,
+        name: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        // keeps the WALs of the batches committed while the checkpoint is created
+        let since = leader.inner.mvcc().latest_commit_ts();
+        let mut shipper = Self::new(leader, name, since)?;
+        let checkpoint_ts = shipper.leader.create_checkpoint(path)?;
+        shipper
+            .leader
+            .register_update_consumer(name, checkpoint_ts)?;
+        shipper.shipped_ts = checkpoint_ts;
+        Ok(shipper)
+    }
+
+    pub fn shipped_ts(&self) -> u64 {
+        self.shipped_ts
+    }
+
+    /// Writes the batches committed since the last call to `writer`, followed by a heartbeat, and returns how many
+    /// batches are written. The shipper no longer keeps the WALs of the batches written.
+    pub fn ship(&mut self, mut writer: impl Write) -> Result<usize> {
+        let leader_ts = self.leader.inner.mvcc().latest_commit_ts();
+        let mut shipped = 0;
+        for batch in self.leader.updates_since(self.shipped_ts)? {
+            let batch = batch?;
+            if batch.commit_ts > leader_ts {
+                break;
+            }
+            let records = batch
+                .updates
+                .into_iter()
+                .map(Update::into_record)
+                .collect::<Vec<_>>();
+            let data = records
+                .iter()
+                .map(|(key, entry_type, value)| {
+                    (
+                        KeySlice::from_slice(key, batch.commit_ts),
+                        &value[..],
+                        *entry_type,
+                    )
+                })
+                .collect::<Vec<_>>();
+            write_message(&mut writer, leader_ts, &data)?;
+            shipped += 1;
+        }
+        write_message(&mut writer, leader_ts, &[])?;
+        writer.flush()?;
+        self.shipped_ts = leader_ts;
+        self.leader
+            .register_update_consumer(&self.name, leader_ts)?;
+        Ok(shipped)
+    }
+}
+
+impl Drop for WalShipper {
+    fn drop(&mut self) {
+        self.leader.unregister_update_consumer(&self.name).ok();
+    }
+}
+
+fn write_message(
+    writer: &mut impl Write,
+    leader_ts: u64,
+    data: &[(KeySlice, &[u8], EntryType)],
+) -> Result<()> {
+    let frame = Wal::encode_batch_frame(data)?;
+    writer.write_all(&leader_ts.to_be_bytes())?;
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+struct FollowerState {
+    /// The latest commit ts of the leader the follower knows of.
+    leader_ts: u64,
+    /// When the follower last applied every batch up to `leader_ts`.
+    caught_up_at: Option<Instant>,
+}
+
+/// A read-only replica of a leader, which applies the write batches shipped by a `WalShipper` at the commit ts of
+/// the leader. A read is a snapshot at the latest batch applied, and fails if the follower has not caught up with
+/// the leader within the last `max_staleness`.
+pub struct Follower {
+    storage: Arc<MiniLsm>,
+    max_staleness: Duration,
+    /// The largest `batch_size` of a message read by `replicate_from`, which is allocated before it is read.
+    max_batch_size: usize,
+    state: Mutex<FollowerState>,
+}
+
+impl Follower {
+    /// Opens the follower in `path`, which is a checkpoint of the leader created by `create_checkpoint`, or the
+    /// directory of a follower opened before, which resumes from its `applied_ts`.
+    pub fn open(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        max_staleness: Duration,
+    ) -> Result<Self> {
+        if !options.enable_wal {
+            bail!("a follower keeps the batches it applies in the WAL, which is disabled");
+        }
+        Ok(Self {
+            storage: MiniLsm::open(path, options)?,
+            max_staleness,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            state: Mutex::new(FollowerState {
+                leader_ts: 0,
+                caught_up_at: None,
+            }),
+        })
+    }
+
+    /// Sets the largest `batch_size` of a message `replicate_from` accepts, which has to be at least the size of
+    /// the largest batch the leader commits.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// The commit ts of the latest batch applied.
+    pub fn applied_ts(&self) -> u64 {
+        self.storage.inner.mvcc().latest_commit_ts()
+    }
+
+    /// Applies a message shipped by a `WalShipper`.
+    pub fn apply(&self, message: &[u8]) -> Result<()> {
+        if message.len() < MESSAGE_HEADER_SIZE {
+            bail!("malformed replication message");
+        }
+        let leader_ts = (&message[..8]).get_u64();
+        let records = Wal::decode_batch_frame(&message[8..])?;
+        if records.is_empty() {
+            self.storage.inner.advance_replicated_ts(leader_ts);
+        } else {
+            self.storage.inner.apply_replicated_records(&records)?;
+        }
+        let mut state = self.state.lock();
+        state.leader_ts = state.leader_ts.max(leader_ts);
+        if self.applied_ts() >= state.leader_ts {
+            state.caught_up_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Applies the messages read from `reader`, such as a socket a `WalShipper` writes to, until it is closed.
+    pub fn replicate_from(&self, mut reader: impl Read) -> Result<()> {
+        loop {
+            let mut header = [0; MESSAGE_HEADER_SIZE];
+            // the stream may only end between two messages
+            if reader.read(&mut header[..1])? == 0 {
+                return Ok(());
+            }
+            reader.read_exact(&mut header[1..])?;
+            let batch_size = (&header[8..]).get_u32() as usize;
+            if batch_size > self.max_batch_size {
+                bail!(
+                    "replication message of {} bytes is larger than the limit of {} bytes",
+                    batch_size,
+                    self.max_batch_size
+                );
+            }
+            let mut message = header.to_vec();
+            message.resize(MESSAGE_HEADER_SIZE + batch_size + 4, 0);
+            reader.read_exact(&mut message[MESSAGE_HEADER_SIZE..])?;
+            self.apply(&message)?;
+        }
+    }
+
+    fn check_staleness(&self) -> Result<()> {
+        let state = self.state.lock();
+        match state.caught_up_at {
+            Some(caught_up_at) if caught_up_at.elapsed() <= self.max_staleness => Ok(()),
+            _ => bail!(
+                "the follower has not caught up with the leader in the last {:?}",
+                self.max_staleness
+            ),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.check_staleness()?;
+        self.storage.get(key)
+    }
+
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.check_staleness()?;
+        self.storage.scan(lower, upper)
+    }
+
+    pub fn close(&self) -> Result<()> {
+        self.storage.close()
+    }
+}
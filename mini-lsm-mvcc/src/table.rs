@@ -14,23 +14,75 @@
 
 pub(crate) mod bloom;
 mod builder;
+mod compression;
 mod iterator;
 
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{Result, anyhow, bail};
 pub use builder::SsTableBuilder;
 use bytes::{Buf, BufMut};
+pub use compression::CompressionType;
 pub use iterator::SsTableIterator;
 
 use crate::block::Block;
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::varint::{get_varint, put_varint, varint_len};
 
 use self::bloom::Bloom;
 
+/// Marks the footer of an SST written in a versioned format ("MLSM").
+const SST_FOOTER_MAGIC: u32 = 0x4d4c_534d;
+
+/// Revisions of the SST file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum SstFormatVersion {
+    /// The original format: `u16` key and value lengths in blocks and block meta, `u16` offsets inside blocks and
+    /// `u32` offsets of blocks, block meta and bloom filter. Blocks are followed only by their checksum, entries
+    /// have no entry type and a deletion is a `Put` of an empty value. There is no footer.
+    V1 = 1,
+    /// Varint key and value lengths, `u32` offsets inside blocks and `u64` offsets of blocks, block meta and
+    /// bloom filter. Each block entry has an `EntryType` byte after its timestamp, and each block is compressed
+    /// by the codec whose tag is stored before its checksum. The block meta records the bytes referenced in each
+    /// value log, the time of the newest write, the number of `EntryType::Delete` entries and the number of
+    /// entries. A range tombstone block follows the bloom filter, and its offset (u64) is followed by a footer of
+    /// `version (u32) | magic (u32)`.
+    V2 = 2,
+}
+
+impl SstFormatVersion {
+    pub const LATEST: Self = SstFormatVersion::V2;
+
+    fn from_u32(version: u32) -> Result<Self> {
+        match version {
+            2 => Ok(SstFormatVersion::V2),
+            _ => bail!("unsupported SST format version {}", version),
+        }
+    }
+}
+
+/// The block meta section of an SST.
+pub struct DecodedBlockMeta {
+    pub block_meta: Vec<BlockMeta>,
+    pub max_ts: u64,
+    /// Bytes of the records the SST references in each value log, empty in V1.
+    pub value_log_refs: BTreeMap<usize, u64>,
+    /// 0 in V1.
+    pub newest_write_time: u64,
+    /// 0 in V1.
+    pub num_deletes: u64,
+    /// 0 in V1.
+    pub num_entries: u64,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
     /// Offset of this data block.
@@ -42,22 +94,35 @@ pub struct BlockMeta {
 }
 
 impl BlockMeta {
-    /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
+    /// Encode block meta to a buffer in the latest format.
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        max_ts: u64,
+        value_log_refs: &BTreeMap<usize, u64>,
+        newest_write_time: u64,
+        num_deletes: u64,
+        num_entries: u64,
+        buf: &mut Vec<u8>,
+    ) {
         let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
         for meta in block_meta {
             // The size of offset
-            estimated_size += std::mem::size_of::<u32>();
+            estimated_size += std::mem::size_of::<u64>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += varint_len(meta.first_key.key_len() as u64);
             // The size of actual key
             estimated_size += meta.first_key.raw_len();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += varint_len(meta.last_key.key_len() as u64);
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
         estimated_size += std::mem::size_of::<u64>(); // max timestamp
+        estimated_size += std::mem::size_of::<u32>(); // number of value logs
+        estimated_size += value_log_refs.len() * std::mem::size_of::<u64>() * 2; // value log id and bytes
+        estimated_size += std::mem::size_of::<u64>(); // newest write time
+        estimated_size += std::mem::size_of::<u64>(); // number of deletes
+        estimated_size += std::mem::size_of::<u64>(); // number of entries
         estimated_size += std::mem::size_of::<u32>(); // checksum
 
         // Reserve the space to improve performance, especially when the size of incoming data is
@@ -66,30 +131,46 @@ impl BlockMeta {
         let original_len = buf.len();
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put_u64(meta.offset as u64);
+            put_varint(buf, meta.first_key.key_len() as u64);
             buf.put_slice(meta.first_key.key_ref());
             buf.put_u64(meta.first_key.ts());
-            buf.put_u16(meta.last_key.key_len() as u16);
+            put_varint(buf, meta.last_key.key_len() as u64);
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
         buf.put_u64(max_ts);
+        buf.put_u32(value_log_refs.len() as u32);
+        for (log_id, bytes) in value_log_refs {
+            buf.put_u64(*log_id as u64);
+            buf.put_u64(*bytes);
+        }
+        buf.put_u64(newest_write_time);
+        buf.put_u64(num_deletes);
+        buf.put_u64(num_entries);
         buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    /// Decode block meta from a buffer, along with the properties of the SST stored after it.
+    pub fn decode_block_meta(
+        mut buf: &[u8],
+        version: SstFormatVersion,
+    ) -> Result<DecodedBlockMeta> {
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
         for _ in 0..num {
-            let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let (offset, first_key_len) = match version {
+                SstFormatVersion::V1 => (buf.get_u32() as usize, buf.get_u16() as usize),
+                _ => (buf.get_u64() as usize, get_varint(&mut buf) as usize),
+            };
             let first_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
-            let last_key_len: usize = buf.get_u16() as usize;
+            let last_key_len = match version {
+                SstFormatVersion::V1 => buf.get_u16() as usize,
+                _ => get_varint(&mut buf) as usize,
+            };
             let last_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
             block_meta.push(BlockMeta {
@@ -99,11 +180,28 @@ impl BlockMeta {
             });
         }
         let max_ts = buf.get_u64();
+        let mut value_log_refs = BTreeMap::new();
+        let (mut newest_write_time, mut num_deletes, mut num_entries) = (0, 0, 0);
+        if version >= SstFormatVersion::V2 {
+            for _ in 0..buf.get_u32() {
+                value_log_refs.insert(buf.get_u64() as usize, buf.get_u64());
+            }
+            newest_write_time = buf.get_u64();
+            num_deletes = buf.get_u64();
+            num_entries = buf.get_u64();
+        }
         if buf.get_u32() != checksum {
             bail!("meta checksum mismatched");
         }
 
-        Ok((block_meta, max_ts))
+        Ok(DecodedBlockMeta {
+            block_meta,
+            max_ts,
+            value_log_refs,
+            newest_write_time,
+            num_deletes,
+            num_entries,
+        })
     }
 }
 
@@ -127,8 +225,31 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
+        Self::create_with_rate_limiter(path, data, None)
+    }
+
+    /// Create a new file object, writing the file no faster than the rate limiter allows.
+    pub fn create_with_rate_limiter(
+        path: &Path,
+        data: Vec<u8>,
+        rate_limiter: Option<(&RateLimiter, IoPriority)>,
+    ) -> Result<Self> {
+        match rate_limiter {
+            Some((rate_limiter, priority)) => {
+                let mut file = File::create(path)?;
+                let mut written = 0;
+                while written < data.len() {
+                    let len = rate_limiter.request(data.len() - written, priority);
+                    file.write_all(&data[written..written + len])?;
+                    written += len;
+                }
+                file.sync_all()?;
+            }
+            None => {
+                std::fs::write(path, &data)?;
+                File::open(path)?.sync_all()?;
+            }
+        }
         Ok(FileObject(
             Some(File::options().read(true).write(false).open(path)?),
             data.len() as u64,
@@ -156,6 +277,19 @@ pub struct SsTable {
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
     max_ts: u64,
+    /// The time of the newest write to this SST in milliseconds since the UNIX epoch, or 0 if unknown.
+    newest_write_time: u64,
+    /// The number of `EntryType::Delete` entries in this SST, or 0 if unknown.
+    num_deletes: u64,
+    /// The number of entries in this SST, or 0 if unknown.
+    num_entries: u64,
+    format_version: SstFormatVersion,
+    /// Bytes of the records this SST references in each value log.
+    value_log_refs: BTreeMap<usize, u64>,
+    /// The range tombstones stored in this SST.
+    range_tombstones: Vec<RangeTombstone>,
+    /// The range tombstones of this SST split into fragments, to find the ones covering a key.
+    range_tombstone_fragments: FragmentedRangeTombstones,
 }
 impl SsTable {
     #[cfg(test)]
@@ -166,27 +300,100 @@ impl SsTable {
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
+        let raw_footer = file.read(len - 8, 8)?;
+        // Each section is followed by its offset. `sections_end` is where the offset following the last section
+        // ends; the last section is the bloom filter in V1.
+        let (format_version, sections_end) = if (&raw_footer[4..]).get_u32() == SST_FOOTER_MAGIC {
+            let format_version = SstFormatVersion::from_u32((&raw_footer[..4]).get_u32())?;
+            (format_version, len - 8)
+        } else {
+            (SstFormatVersion::V1, len)
+        };
+        let offset_size = match format_version {
+            SstFormatVersion::V1 => 4,
+            _ => 8,
+        };
+        // Reads the offset of the section that ends at `end`.
+        let read_offset = |end: u64| -> Result<u64> {
+            let raw_offset = file.read(end - offset_size, offset_size)?;
+            Ok(match format_version {
+                SstFormatVersion::V1 => (&raw_offset[..]).get_u32() as u64,
+                _ => (&raw_offset[..]).get_u64(),
+            })
+        };
+        let (range_tombstones, bloom_end) = if format_version >= SstFormatVersion::V2 {
+            let range_tombstones_offset = read_offset(sections_end)?;
+            let raw_range_tombstones = file.read(
+                range_tombstones_offset,
+                sections_end - offset_size - range_tombstones_offset,
+            )?;
+            (
+                RangeTombstone::decode_range_tombstones(&raw_range_tombstones)?,
+                range_tombstones_offset,
+            )
+        } else {
+            (Vec::new(), sections_end)
+        };
+        let bloom_offset = read_offset(bloom_end)?;
+        let block_meta_offset = read_offset(bloom_offset)?;
+        let raw_bloom = file.read(bloom_offset, bloom_end - offset_size - bloom_offset)?;
         let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let raw_meta = file.read(
+            block_meta_offset,
+            bloom_offset - offset_size - block_meta_offset,
+        )?;
+        let DecodedBlockMeta {
+            block_meta,
+            max_ts,
+            value_log_refs,
+            newest_write_time,
+            num_deletes,
+            num_entries,
+        } = BlockMeta::decode_block_meta(&raw_meta[..], format_version)?;
+        let (first_key, last_key) = Self::key_range(&block_meta, &range_tombstones);
         Ok(Self {
             file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta,
             block_meta_offset: block_meta_offset as usize,
             id,
             block_cache,
             bloom: Some(bloom_filter),
             max_ts,
+            newest_write_time,
+            num_deletes,
+            num_entries,
+            format_version,
+            value_log_refs,
+            range_tombstone_fragments: FragmentedRangeTombstones::new(&range_tombstones),
+            range_tombstones,
         })
     }
 
+    /// The key range of an SST covers both its entries and its range tombstones, so that reads and compactions
+    /// picking SSTs by key range see the tombstones deleting the keys they are interested in.
+    pub(crate) fn key_range(
+        block_meta: &[BlockMeta],
+        range_tombstones: &[RangeTombstone],
+    ) -> (KeyBytes, KeyBytes) {
+        let first_key = block_meta
+            .first()
+            .map(|meta| meta.first_key.clone())
+            .into_iter()
+            .chain(range_tombstones.iter().map(RangeTombstone::first_key))
+            .min()
+            .expect("SST has neither entries nor range tombstones");
+        let last_key = block_meta
+            .last()
+            .map(|meta| meta.last_key.clone())
+            .into_iter()
+            .chain(range_tombstones.iter().map(RangeTombstone::last_key))
+            .max()
+            .unwrap();
+        (first_key, last_key)
+    }
+
     /// Create a mock SST with only first key + last key metadata
     pub fn create_meta_only(
         id: usize,
@@ -204,9 +411,34 @@ impl SsTable {
             last_key,
             bloom: None,
             max_ts: 0,
+            newest_write_time: 0,
+            num_deletes: 0,
+            num_entries: 0,
+            format_version: SstFormatVersion::LATEST,
+            value_log_refs: BTreeMap::new(),
+            range_tombstones: Vec::new(),
+            range_tombstone_fragments: FragmentedRangeTombstones::default(),
         }
     }
 
+    /// Sets the newest write time of a mock SST.
+    pub fn with_newest_write_time(mut self, newest_write_time: u64) -> Self {
+        self.newest_write_time = newest_write_time;
+        self
+    }
+
+    /// Sets the number of deletions of a mock SST.
+    pub fn with_num_deletes(mut self, num_deletes: u64) -> Self {
+        self.num_deletes = num_deletes;
+        self
+    }
+
+    /// Sets the number of entries of a mock SST.
+    pub fn with_num_entries(mut self, num_entries: u64) -> Self {
+        self.num_entries = num_entries;
+        self
+    }
+
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let offset = self.block_meta[block_idx].offset;
@@ -214,16 +446,30 @@ impl SsTable {
             .block_meta
             .get(block_idx + 1)
             .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
         let block_data_with_chksum: Vec<u8> = self
             .file
             .read(offset as u64, (offset_end - offset) as u64)?;
-        let block_data = &block_data_with_chksum[..block_len];
-        let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
+        // V1 blocks are `data | checksum`, V2 stores the compression codec between the data and the checksum.
+        let (compression, block_len) = match self.format_version {
+            SstFormatVersion::V1 => (CompressionType::None, offset_end - offset - 4),
+            SstFormatVersion::V2 => {
+                let block_len = offset_end - offset - 5;
+                (
+                    CompressionType::from_tag(block_data_with_chksum[block_len])?,
+                    block_len,
+                )
+            }
+        };
+        let block_data = compression.decompress(&block_data_with_chksum[..block_len])?;
+        let checksum = (&block_data_with_chksum[offset_end - offset - 4..]).get_u32();
+        if checksum != crc32fast::hash(&block_data) {
             bail!("block checksum mismatched");
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        let block = match self.format_version {
+            SstFormatVersion::V1 => Block::decode_legacy(&block_data)?,
+            SstFormatVersion::V2 => Block::decode(&block_data),
+        };
+        Ok(Arc::new(block))
     }
 
     /// Read a block from disk, with block cache.
@@ -269,4 +515,42 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    /// The time of the newest write to this SST, in milliseconds since the UNIX epoch. `None` for the SSTs
+    /// written before the time was recorded.
+    pub fn newest_write_time(&self) -> Option<u64> {
+        (self.newest_write_time != 0).then_some(self.newest_write_time)
+    }
+
+    /// The number of point deletions in this SST, which is 0 for the SSTs written before it was recorded.
+    pub fn num_deletes(&self) -> u64 {
+        self.num_deletes
+    }
+
+    /// The number of entries in this SST, which is 0 for the SSTs written before it was recorded.
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    /// The ratio of point deletions to entries, or `None` for the SSTs written before the number of entries was
+    /// recorded.
+    pub fn tombstone_ratio(&self) -> Option<f64> {
+        (self.num_entries > 0).then(|| self.num_deletes as f64 / self.num_entries as f64)
+    }
+
+    pub fn format_version(&self) -> SstFormatVersion {
+        self.format_version
+    }
+
+    pub fn value_log_refs(&self) -> &BTreeMap<usize, u64> {
+        &self.value_log_refs
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    pub(crate) fn range_tombstone_fragments(&self) -> &FragmentedRangeTombstones {
+        &self.range_tombstone_fragments
+    }
 }
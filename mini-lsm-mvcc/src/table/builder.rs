@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -19,10 +20,14 @@ use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
+use super::{BlockMeta, CompressionType, FileObject, SST_FOOTER_MAGIC, SsTable, SstFormatVersion};
 use crate::block::BlockBuilder;
+use crate::iterators::EntryType;
 use crate::key::{KeySlice, KeyVec};
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::value_log::{ValueLogBuilder, ValuePointer};
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -34,11 +39,25 @@ pub struct SsTableBuilder {
     block_size: usize,
     key_hashes: Vec<u32>,
     max_ts: u64,
+    newest_write_time: u64,
+    num_deletes: u64,
+    num_entries: u64,
+    compression: CompressionType,
+    /// The value log for separated values, and the minimum size of a value to be separated.
+    value_log: Option<(ValueLogBuilder, usize)>,
+    value_log_refs: BTreeMap<usize, u64>,
+    range_tombstones: Vec<RangeTombstone>,
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_compression(block_size, CompressionType::None)
+    }
+
+    /// Create a builder based on target block size, compressing each data block with `compression`.
+    pub fn new_with_compression(block_size: usize, compression: CompressionType) -> Self {
         Self {
             data: Vec::new(),
             meta: Vec::new(),
@@ -48,11 +67,66 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            newest_write_time: 0,
+            num_deletes: 0,
+            num_entries: 0,
+            compression,
+            value_log: None,
+            value_log_refs: BTreeMap::new(),
+            range_tombstones: Vec::new(),
+            rate_limiter: None,
         }
     }
 
-    /// Adds a key-value pair to SSTable
+    /// Create a builder that writes values of at least `min_value_size` bytes to `value_log`.
+    pub fn new_with_value_log(
+        block_size: usize,
+        compression: CompressionType,
+        value_log: ValueLogBuilder,
+        min_value_size: usize,
+    ) -> Self {
+        let mut builder = Self::new_with_compression(block_size, compression);
+        builder.value_log = Some((value_log, min_value_size));
+        builder
+    }
+
+    /// Records the time of the newest write to the SST, in milliseconds since the UNIX epoch.
+    pub fn set_newest_write_time(&mut self, newest_write_time: u64) {
+        self.newest_write_time = newest_write_time;
+    }
+
+    /// Writes the SST, and the value log if any, through `rate_limiter` at `priority`.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>, priority: IoPriority) {
+        self.rate_limiter = Some((rate_limiter, priority));
+    }
+
+    /// Adds a key-value pair to SSTable. An empty value is written as a deletion.
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_with_type(key, value, EntryType::from_value(value))
+    }
+
+    /// Adds an entry of the given type to SSTable. Values of `EntryType::Put` go to the value log if they
+    /// are large enough.
+    pub fn add_with_type(&mut self, key: KeySlice, value: &[u8], entry_type: EntryType) {
+        if let (EntryType::Put, Some((value_log, min_value_size))) =
+            (entry_type, &mut self.value_log)
+            && !value.is_empty()
+            && value.len() >= *min_value_size
+        {
+            let pointer = value_log.add(key, value);
+            self.add_with_type(key, &pointer.encode(), EntryType::ValuePointer);
+            return;
+        }
+        if entry_type == EntryType::ValuePointer {
+            let pointer = ValuePointer::decode(value);
+            *self.value_log_refs.entry(pointer.log_id).or_default() += pointer.len;
+        }
+
+        if entry_type == EntryType::Delete {
+            self.num_deletes += 1;
+        }
+        self.num_entries += 1;
+
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -62,7 +136,7 @@ impl SsTableBuilder {
         }
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
 
-        if self.builder.add(key, value) {
+        if self.builder.add_with_type(key, value, entry_type) {
             self.last_key.set_from_slice(key);
             return;
         }
@@ -71,11 +145,24 @@ impl SsTableBuilder {
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_with_type(key, value, entry_type));
         self.first_key.set_from_slice(key);
         self.last_key.set_from_slice(key);
     }
 
+    /// Adds a range tombstone to the range tombstone block of the SSTable.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        if tombstone.ts > self.max_ts {
+            self.max_ts = tombstone.ts;
+        }
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Whether nothing has been added to the SSTable.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
+    }
+
     /// Get the estimated size of the SSTable.
     pub fn estimated_size(&self) -> usize {
         self.data.len()
@@ -90,40 +177,82 @@ impl SsTableBuilder {
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
         });
         let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
+        // Store the block uncompressed if the codec fails or does not save any space.
+        let (compression, block_data) = match self.compression.compress(&encoded_block) {
+            Ok(compressed) if compressed.len() < encoded_block.len() => {
+                (self.compression, compressed)
+            }
+            _ => (CompressionType::None, encoded_block.to_vec()),
+        };
+        self.data.extend(block_data);
+        self.data.put_u8(compression.tag());
         self.data.put_u32(checksum);
     }
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
+    /// The value log, if any values were separated, is written before the SST that references it.
     pub fn build(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
+        // An SST may hold nothing but range tombstones.
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
+        if let Some((value_log, _)) = self.value_log
+            && !value_log.is_empty()
+        {
+            value_log.build(self.rate_limiter.as_ref().map(|(r, p)| (r.as_ref(), *p)))?;
+        }
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
-        buf.put_u32(meta_offset as u32);
+        BlockMeta::encode_block_meta(
+            &self.meta,
+            self.max_ts,
+            &self.value_log_refs,
+            self.newest_write_time,
+            self.num_deletes,
+            self.num_entries,
+            &mut buf,
+        );
+        buf.put_u64(meta_offset as u64);
         let bloom = Bloom::build_from_key_hashes(
             &self.key_hashes,
             Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
         );
         let bloom_offset = buf.len();
         bloom.encode(&mut buf);
-        buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
+        buf.put_u64(bloom_offset as u64);
+        let range_tombstones_offset = buf.len();
+        RangeTombstone::encode_range_tombstones(&self.range_tombstones, &mut buf);
+        buf.put_u64(range_tombstones_offset as u64);
+        buf.put_u32(SstFormatVersion::LATEST as u32);
+        buf.put_u32(SST_FOOTER_MAGIC);
+        let file = FileObject::create_with_rate_limiter(
+            path.as_ref(),
+            buf,
+            self.rate_limiter.as_ref().map(|(r, p)| (r.as_ref(), *p)),
+        )?;
+        let (first_key, last_key) = SsTable::key_range(&self.meta, &self.range_tombstones);
         Ok(SsTable {
             id,
             file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta: self.meta,
             block_meta_offset: meta_offset,
             block_cache,
             bloom: Some(bloom),
             max_ts: self.max_ts,
+            newest_write_time: self.newest_write_time,
+            num_deletes: self.num_deletes,
+            num_entries: self.num_entries,
+            format_version: SstFormatVersion::LATEST,
+            value_log_refs: self.value_log_refs,
+            range_tombstone_fragments: FragmentedRangeTombstones::new(&self.range_tombstones),
+            range_tombstones: self.range_tombstones,
         })
     }
 
@@ -0,0 +1,66 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{Result, bail};
+
+/// The codec used to compress a data block. Each block on disk carries its own codec tag, so SSTs (and
+/// even blocks within one SST) written with different codecs can live in the same database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Lz4,
+    Snappy,
+    Zstd,
+}
+
+impl CompressionType {
+    /// The tag stored after each block.
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Snappy => 2,
+            CompressionType::Zstd => 3,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => CompressionType::None,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Snappy,
+            3 => CompressionType::Zstd,
+            _ => bail!("unknown compression type {}", tag),
+        })
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::compress_prepend_size(data),
+            CompressionType::Snappy => snap::raw::Encoder::new().compress_vec(data)?,
+            CompressionType::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)?,
+        })
+    }
+
+    pub(crate) fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::decompress_size_prepended(data)?,
+            CompressionType::Snappy => snap::raw::Decoder::new().decompress_vec(data)?,
+            CompressionType::Zstd => zstd::stream::decode_all(data)?,
+        })
+    }
+}
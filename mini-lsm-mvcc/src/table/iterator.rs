@@ -17,8 +17,8 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use super::SsTable;
-use crate::block::BlockIterator;
-use crate::iterators::StorageIterator;
+use crate::block::{Block, BlockIterator};
+use crate::iterators::{EntryType, StorageIterator};
 use crate::key::KeySlice;
 
 /// An iterator over the contents of an SSTable.
@@ -29,7 +29,18 @@ pub struct SsTableIterator {
 }
 
 impl SsTableIterator {
+    /// An iterator over an SST without any entries, which only holds range tombstones.
+    fn empty_block_iter() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block {
+            data: Vec::new(),
+            offsets: Vec::new(),
+        }))
+    }
+
     fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         Ok((
             0,
             BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
@@ -56,6 +67,9 @@ impl SsTableIterator {
     }
 
     fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         let mut blk_idx = table.find_block_idx(key);
         let mut blk_iter =
             BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
@@ -100,6 +114,10 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.key()
     }
 
+    fn entry_type(&self) -> EntryType {
+        self.blk_iter.entry_type()
+    }
+
     fn is_valid(&self) -> bool {
         self.blk_iter.is_valid()
     }
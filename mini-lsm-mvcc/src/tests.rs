@@ -12,7 +12,30 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod block_compression;
+mod change_feed;
+mod compact_range;
+mod compaction_filter;
+mod concurrent_compaction;
+mod empty_values;
+mod fifo_compaction;
+mod grandparent_overlap;
+mod group_commit;
 mod harness;
+mod large_entries;
+mod merge_operator;
+mod range_delete;
+mod rate_limiter;
+mod replication;
+mod sst_selection;
+mod subcompaction;
+mod tiered_compaction;
+mod time_window_compaction;
+mod tombstone_compaction;
+mod trivial_move;
+mod ttl;
+mod value_separation;
+mod wal_recovery;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -33,3 +56,4 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_stall;
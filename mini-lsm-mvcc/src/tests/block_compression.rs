@@ -0,0 +1,106 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{CompressionType, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+fn value_of(idx: usize) -> String {
+    format!(
+        "{{\"id\":{},\"name\":\"user_{:05}\",\"active\":true}}",
+        idx, idx
+    )
+}
+
+fn build_sst(compression: CompressionType, path: impl AsRef<std::path::Path>) -> SsTable {
+    let mut builder = SsTableBuilder::new_with_compression(4096, compression);
+    for idx in 0..1000 {
+        let key = format!("key_{:05}", idx);
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(key.as_bytes(), 1),
+            value_of(idx).as_bytes(),
+        );
+    }
+    builder.build_for_test(path).unwrap()
+}
+
+#[test]
+fn test_sst_compression_roundtrip() {
+    let dir = tempdir().unwrap();
+    let uncompressed = build_sst(CompressionType::None, dir.path().join("none.sst"));
+    for compression in [
+        CompressionType::Lz4,
+        CompressionType::Snappy,
+        CompressionType::Zstd,
+    ] {
+        let sst = build_sst(
+            compression,
+            dir.path().join(format!("{:?}.sst", compression)),
+        );
+        assert!(
+            sst.table_size() < uncompressed.table_size(),
+            "{:?} does not compress",
+            compression
+        );
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.into()).unwrap();
+        for idx in 0..1000 {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key().key_ref(), format!("key_{:05}", idx).as_bytes());
+            assert_eq!(iter.value(), value_of(idx).as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_switch_compression_on_reopen() {
+    let dir = tempdir().unwrap();
+    let codecs = [
+        CompressionType::Lz4,
+        CompressionType::None,
+        CompressionType::Snappy,
+        CompressionType::Zstd,
+    ];
+    for (round, compression) in codecs.iter().enumerate() {
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.compression = *compression;
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        for idx in 0..100 {
+            let key = format!("key_{}_{:05}", round, idx);
+            storage
+                .put(key.as_bytes(), value_of(idx).as_bytes())
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        // all SSTs written so far, each with a different codec, must stay readable
+        for prev_round in 0..=round {
+            for idx in 0..100 {
+                let key = format!("key_{}_{:05}", prev_round, idx);
+                assert_eq!(
+                    storage.get(key.as_bytes()).unwrap().unwrap(),
+                    value_of(idx).as_bytes()
+                );
+            }
+        }
+        storage.close().unwrap();
+    }
+}
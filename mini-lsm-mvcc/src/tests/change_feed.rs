@@ -0,0 +1,262 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    change_feed::{CommittedBatch, Update, WalArchiveOptions},
+    iterators::EntryType,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    mem_table::MemTable,
+    wal::WalRecoveryMode,
+};
+
+fn options(wal_archive: Option<WalArchiveOptions>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.wal_archive = wal_archive;
+    options
+}
+
+fn updates_since(storage: &MiniLsm, ts: u64) -> Vec<CommittedBatch> {
+    storage
+        .updates_since(ts)
+        .unwrap()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap()
+}
+
+fn commit_ts(batches: &[CommittedBatch]) -> Vec<u64> {
+    batches.iter().map(|batch| batch.commit_ts).collect()
+}
+
+fn put(key: &'static [u8], value: &'static [u8]) -> Update {
+    Update::Put {
+        key: Bytes::from_static(key),
+        value: Bytes::from_static(value),
+    }
+}
+
+fn archived_wals(dir: &Path) -> usize {
+    match std::fs::read_dir(dir.join("archive")) {
+        Ok(entries) => entries.count(),
+        Err(_) => 0,
+    }
+}
+
+#[test]
+fn test_updates_since() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(None)).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.delete(b"b").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(b"c".as_slice(), b"3".as_slice()),
+            WriteBatchRecord::DelRange(b"d".as_slice(), b"e".as_slice()),
+        ])
+        .unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"f", b"6");
+    txn.delete(b"g");
+    txn.commit().unwrap();
+    storage
+        .put_with_ttl(b"h", b"8", Duration::from_secs(60))
+        .unwrap();
+
+    let batches = updates_since(&storage, 0);
+    assert_eq!(commit_ts(&batches), vec![1, 2, 3, 4, 5]);
+    assert_eq!(batches[0].updates, vec![put(b"a", b"1")]);
+    assert_eq!(
+        batches[1].updates,
+        vec![Update::Delete {
+            key: Bytes::from_static(b"b")
+        }]
+    );
+    assert_eq!(
+        batches[2].updates,
+        vec![
+            put(b"c", b"3"),
+            Update::DeleteRange {
+                start: Bytes::from_static(b"d"),
+                end: Bytes::from_static(b"e"),
+            },
+        ]
+    );
+    assert_eq!(
+        batches[3].updates,
+        vec![
+            put(b"f", b"6"),
+            Update::Delete {
+                key: Bytes::from_static(b"g")
+            },
+        ]
+    );
+    assert!(matches!(
+        &batches[4].updates[..],
+        [Update::PutWithExpiry { key, value, .. }] if key == "h" && value == "8"
+    ));
+    assert_eq!(commit_ts(&updates_since(&storage, 3)), vec![4, 5]);
+    assert!(updates_since(&storage, 5).is_empty());
+
+    // the updates are still read from the WAL of a frozen memtable, but not once it is flushed
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"i", b"9").unwrap();
+    assert_eq!(commit_ts(&updates_since(&storage, 4)), vec![5, 6]);
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    assert_eq!(archived_wals(dir.path()), 0);
+    assert!(storage.updates_since(4).is_err());
+    assert_eq!(commit_ts(&updates_since(&storage, 5)), vec![6]);
+}
+
+#[test]
+fn test_update_consumer_keeps_wals() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(None)).unwrap();
+    storage.register_update_consumer("index", 0).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(archived_wals(dir.path()), 2);
+    assert_eq!(commit_ts(&updates_since(&storage, 0)), vec![1, 2]);
+
+    // the WALs are removed on the next flush once the consumer has read past them
+    storage.register_update_consumer("index", 1).unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(archived_wals(dir.path()), 2);
+    assert!(storage.updates_since(0).is_err());
+    assert_eq!(commit_ts(&updates_since(&storage, 1)), vec![2, 3]);
+    assert!(storage.register_update_consumer("index", 0).is_err());
+
+    // an open iterator keeps the WALs it reads
+    let iter = storage.updates_since(1).unwrap();
+    assert!(storage.unregister_update_consumer("index").unwrap());
+    assert!(!storage.unregister_update_consumer("index").unwrap());
+    storage.put(b"d", b"4").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(archived_wals(dir.path()), 3);
+    // the batches committed after the iterator is created are not returned
+    let batches = iter.collect::<anyhow::Result<Vec<_>>>().unwrap();
+    assert_eq!(commit_ts(&batches), vec![2, 3]);
+    storage.put(b"e", b"5").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(archived_wals(dir.path()), 0);
+    assert!(storage.updates_since(4).is_err());
+    assert!(updates_since(&storage, 5).is_empty());
+}
+
+#[test]
+fn test_memtable_max_ts() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let memtable = MemTable::create_with_wal(1, &path).unwrap();
+    assert_eq!(memtable.max_ts(), 0);
+    memtable
+        .write_batch(&[
+            (KeySlice::from_slice(b"a", 3), b"1", EntryType::Put),
+            (KeySlice::from_slice(b"b", 3), b"", EntryType::Delete),
+        ])
+        .unwrap();
+    memtable
+        .write_batch(&[(KeySlice::from_slice(b"c", 5), b"d", EntryType::RangeDelete)])
+        .unwrap();
+    memtable.put(KeySlice::from_slice(b"a", 4), b"2").unwrap();
+    assert_eq!(memtable.max_ts(), 5);
+    memtable.sync_wal().unwrap();
+    drop(memtable);
+
+    let (memtable, _) =
+        MemTable::recover_from_wal(1, &path, WalRecoveryMode::TolerateCorruptedTailRecords)
+            .unwrap();
+    assert_eq!(memtable.max_ts(), 5);
+}
+
+#[test]
+fn test_update_consumer_across_restarts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(None)).unwrap();
+    storage.register_update_consumer("index", 0).unwrap();
+    storage.register_update_consumer("audit", 0).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.register_update_consumer("index", 1).unwrap();
+    assert!(storage.unregister_update_consumer("audit").unwrap());
+    storage.close().unwrap();
+    drop(storage);
+
+    // the consumer is reloaded from the manifest and keeps the WALs after its ts
+    let storage = MiniLsm::open(&dir, options(None)).unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.force_flush().unwrap();
+    assert!(storage.updates_since(0).is_err());
+    assert_eq!(commit_ts(&updates_since(&storage, 1)), vec![2, 3]);
+    assert!(!storage.unregister_update_consumer("audit").unwrap());
+    assert!(storage.unregister_update_consumer("index").unwrap());
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options(None)).unwrap();
+    storage.put(b"d", b"4").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(archived_wals(dir.path()), 0);
+}
+
+#[test]
+fn test_wal_archive() {
+    let dir = tempdir().unwrap();
+    let archive = WalArchiveOptions {
+        ttl: None,
+        size_limit: Some(1 << 20),
+    };
+    let storage = MiniLsm::open(&dir, options(Some(archive.clone()))).unwrap();
+    for i in 0..3 {
+        storage.put(format!("key_{}", i).as_bytes(), b"v").unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.put(b"key_3", b"v").unwrap();
+    assert_eq!(archived_wals(dir.path()), 3);
+    storage.close().unwrap();
+    drop(storage);
+
+    // the archive is kept across restarts
+    let storage = MiniLsm::open(&dir, options(Some(archive))).unwrap();
+    assert_eq!(commit_ts(&updates_since(&storage, 0)), vec![1, 2, 3, 4]);
+    storage.close().unwrap();
+    drop(storage);
+
+    // the archived WALs over the limits are removed on the next flush
+    let storage = MiniLsm::open(
+        &dir,
+        options(Some(WalArchiveOptions {
+            ttl: Some(Duration::from_secs(3600)),
+            size_limit: Some(0),
+        })),
+    )
+    .unwrap();
+    storage.put(b"key_4", b"v").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(archived_wals(dir.path()), 0);
+    assert!(storage.updates_since(0).is_err());
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+        TieredCompactionOptions,
+    },
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::SsTableIterator,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+/// The keys in all SSTs, and whether any SST has range tombstones.
+fn sst_keys(storage: &MiniLsm) -> (Vec<Bytes>, bool) {
+    let state = storage.inner.state.read();
+    let mut keys = Vec::new();
+    let mut has_range_tombstones = false;
+    let sst_ids = state
+        .l0_sstables
+        .iter()
+        .chain(state.levels.iter().flat_map(|(_, ssts)| ssts));
+    for sst_id in sst_ids {
+        let sst = state.sstables[sst_id].clone();
+        has_range_tombstones |= !sst.range_tombstones().is_empty();
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        while iter.is_valid() {
+            keys.push(Bytes::copy_from_slice(iter.key().key_ref()));
+            iter.next().unwrap();
+        }
+    }
+    keys.sort();
+    (keys, has_range_tombstones)
+}
+
+fn test_compact_range(compaction_options: CompactionOptions) {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), b"v1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in 50..150 {
+        storage.put(&key_of(i), b"v2").unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.delete_range(&key_of(20), &key_of(80)).unwrap();
+    storage.delete(&key_of(100)).unwrap();
+    storage.force_flush().unwrap();
+
+    storage
+        .compact_range(Bound::Included(&key_of(20)), Bound::Included(&key_of(100)))
+        .unwrap();
+    // every version in the range but the latest one of each live key is dropped
+    let expected = (0..20)
+        .chain(80..100)
+        .chain(101..150)
+        .map(|i| Bytes::from(key_of(i)))
+        .collect::<Vec<_>>();
+    assert_eq!(sst_keys(&storage), (expected.clone(), false));
+
+    // nothing overlaps the range
+    storage
+        .compact_range(Bound::Excluded(&key_of(149)), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(sst_keys(&storage), (expected, false));
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(&key_of(10)).unwrap(), Some(Bytes::from("v1")));
+    assert_eq!(storage.get(&key_of(30)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(90)).unwrap(), Some(Bytes::from("v2")));
+    assert_eq!(storage.get(&key_of(100)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(120)).unwrap(), Some(Bytes::from("v2")));
+}
+
+#[test]
+fn test_compact_range_leveled() {
+    test_compact_range(CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 100,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    }));
+}
+
+#[test]
+fn test_compact_range_simple() {
+    test_compact_range(CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 1,
+        level0_file_num_compaction_trigger: 100,
+        max_levels: 3,
+    }));
+}
+
+#[test]
+fn test_compact_range_tiered() {
+    test_compact_range(CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 100,
+        max_size_amplification_percent: 100,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width: None,
+    }));
+}
@@ -0,0 +1,136 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionDecision, CompactionFilter, CompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    value_log::ValueSeparationOptions,
+};
+
+/// Removes the values whose expiry time, stored in their first 8 bytes, is before `now`.
+struct Expire {
+    now: u64,
+    levels: Mutex<Vec<usize>>,
+}
+
+impl CompactionFilter for Expire {
+    fn name(&self) -> &str {
+        "expire"
+    }
+
+    fn filter(&self, level: usize, _key: &[u8], value: &[u8], _ts: u64) -> CompactionDecision {
+        self.levels.lock().push(level);
+        let expiry = u64::from_be_bytes(value[..8].try_into().unwrap());
+        if expiry < self.now {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
+
+/// Rewrites the values encoded as `v1:<payload>` to `v2:<payload>`.
+struct Migrate;
+
+impl CompactionFilter for Migrate {
+    fn name(&self) -> &str {
+        "migrate"
+    }
+
+    fn filter(&self, _level: usize, _key: &[u8], value: &[u8], _ts: u64) -> CompactionDecision {
+        match value[8..].strip_prefix(b"v1:") {
+            Some(payload) => CompactionDecision::ChangeValue(encode(&value[..8], b"v2:", payload)),
+            None => CompactionDecision::Keep,
+        }
+    }
+}
+
+fn encode(expiry: &[u8], version: &[u8], payload: &[u8]) -> Bytes {
+    let mut value = Vec::new();
+    value.put_slice(expiry);
+    value.put_slice(version);
+    value.put_slice(payload);
+    value.into()
+}
+
+fn value_of(expiry: u64, version: &[u8], payload: &[u8]) -> Bytes {
+    encode(&expiry.to_be_bytes(), version, payload)
+}
+
+#[test]
+fn test_compaction_filters() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_separation = Some(ValueSeparationOptions {
+        min_value_size: 1024,
+        gc_garbage_ratio: 0.5,
+    });
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let large_payload = vec![b'x'; 4096];
+    storage.put(b"a", &value_of(5, b"v1:", b"a")).unwrap();
+    storage.put(b"b", &value_of(20, b"v1:", b"b")).unwrap();
+    storage.put(b"c", &value_of(20, b"v1:", b"c")).unwrap();
+    storage
+        .put(b"d", &value_of(20, b"v1:", &large_payload))
+        .unwrap();
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.put(b"c", &value_of(20, b"v1:", b"c2")).unwrap();
+    storage.put(b"e", &value_of(5, b"v1:", b"e")).unwrap();
+    storage.force_flush().unwrap();
+
+    let expire = Arc::new(Expire {
+        now: 10,
+        levels: Mutex::new(Vec::new()),
+    });
+    storage.add_compaction_filter(expire.clone());
+    storage.add_compaction_filter(Arc::new(Migrate));
+    storage.force_full_compaction().unwrap();
+    // the versions above the watermark are not filtered
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(
+        storage.get(b"b").unwrap().unwrap(),
+        value_of(20, b"v2:", b"b")
+    );
+    assert_eq!(
+        storage.get(b"c").unwrap().unwrap(),
+        value_of(20, b"v1:", b"c2")
+    );
+    assert_eq!(
+        storage.get(b"d").unwrap().unwrap(),
+        value_of(20, b"v2:", &large_payload)
+    );
+    assert_eq!(
+        storage.get(b"e").unwrap().unwrap(),
+        value_of(5, b"v1:", b"e")
+    );
+    // a full compaction writes to the first level
+    assert_eq!(*expire.levels.lock(), vec![1; 4]);
+
+    drop(snapshot);
+    assert!(storage.remove_compaction_filter("migrate"));
+    assert!(!storage.remove_compaction_filter("migrate"));
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        storage.get(b"c").unwrap().unwrap(),
+        value_of(20, b"v1:", b"c2")
+    );
+    assert_eq!(storage.get(b"e").unwrap(), None);
+}
@@ -0,0 +1,253 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionController, CompactionDecision, CompactionFilter, CompactionOptions,
+        CompactionTask, LeveledCompactionController, LeveledCompactionOptions,
+        LeveledCompactionTask, RunningCompactions, TieredCompactionController,
+        TieredCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+use super::harness::{check_compaction_ratio, compaction_bench};
+
+fn add_sst(state: &mut LsmStorageState, id: usize, size_mb: u64, first_key: &str, last_key: &str) {
+    let sst = SsTable::create_meta_only(
+        id,
+        size_mb << 20,
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::copy_from_slice(first_key.as_bytes())),
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::copy_from_slice(last_key.as_bytes())),
+    );
+    state.sstables.insert(id, Arc::new(sst));
+}
+
+#[test]
+fn test_non_conflicting_tasks() {
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: vec![2, 1],
+        levels: vec![(1, vec![]), (2, vec![3, 4]), (3, vec![5])],
+        sstables: Default::default(),
+        value_logs: Default::default(),
+        compaction_cursors: Default::default(),
+    };
+    add_sst(&mut state, 1, 1, "a", "m");
+    add_sst(&mut state, 2, 1, "n", "z");
+    add_sst(&mut state, 3, 5, "a", "m");
+    add_sst(&mut state, 4, 5, "n", "z");
+    add_sst(&mut state, 5, 8, "a", "z");
+    let controller =
+        CompactionController::Leveled(LeveledCompactionController::new(LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        }));
+    let mut running = RunningCompactions::default();
+
+    // L0 is the top priority
+    let l0_task = controller
+        .generate_compaction_task(&state, &running)
+        .unwrap();
+    assert!(matches!(
+        l0_task,
+        CompactionTask::TrivialMove {
+            upper_level: None,
+            lower_level: 1,
+            ..
+        }
+    ));
+    running.add(&l0_task);
+
+    // L2 is over its target size, and its task does not conflict with the L0 one
+    let l2_task = controller
+        .generate_compaction_task(&state, &running)
+        .unwrap();
+    let CompactionTask::Leveled(LeveledCompactionTask {
+        upper_level: Some(2),
+        upper_level_sst_ids,
+        lower_level: 3,
+        lower_level_sst_ids,
+        ..
+    }) = &l2_task
+    else {
+        panic!("unexpected task {:?}", l2_task);
+    };
+    assert_eq!(upper_level_sst_ids, &vec![3]);
+    assert_eq!(lower_level_sst_ids, &vec![5]);
+    running.add(&l2_task);
+    assert!(
+        controller
+            .generate_compaction_task(&state, &running)
+            .is_none()
+    );
+
+    running.remove(&l0_task);
+    assert!(matches!(
+        controller.generate_compaction_task(&state, &running),
+        Some(CompactionTask::TrivialMove {
+            upper_level: None,
+            ..
+        })
+    ));
+
+    // a tiered task waits for the running tasks
+    let mut state = state.clone();
+    state.l0_sstables.clear();
+    state.levels = vec![(3, vec![3]), (4, vec![4]), (5, vec![5])];
+    let controller =
+        CompactionController::Tiered(TieredCompactionController::new(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        }));
+    assert!(
+        controller
+            .generate_compaction_task(&state, &running)
+            .is_none()
+    );
+    running.remove(&l2_task);
+    assert!(running.is_empty());
+    assert!(
+        controller
+            .generate_compaction_task(&state, &running)
+            .is_some()
+    );
+}
+
+#[test]
+fn test_integration_with_background_threads() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.max_background_compactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+
+    compaction_bench(storage.clone());
+    check_compaction_ratio(storage.clone());
+}
+
+/// Holds the compactions writing to `level` until `release` is dropped.
+struct BlockingFilter {
+    level: usize,
+    started: Sender<()>,
+    release: Receiver<()>,
+}
+
+impl CompactionFilter for BlockingFilter {
+    fn name(&self) -> &str {
+        "blocking"
+    }
+
+    fn filter(&self, level: usize, _key: &[u8], _value: &[u8], _ts: u64) -> CompactionDecision {
+        if level == self.level {
+            self.started.send(()).ok();
+            self.release.recv().ok();
+        }
+        CompactionDecision::Keep
+    }
+}
+
+#[test]
+fn test_value_log_gc_does_not_block_compactions() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 3,
+        },
+    ));
+    assert!(options.value_separation.is_none());
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for _ in 0..4 {
+        for i in 0..10 {
+            storage
+                .put(format!("key_{:03}", i).as_bytes(), b"v")
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    // two SSTs in L0 are compacted into L3, the base level, and the one in L1 into L2
+    {
+        let _state_lock = storage.state_lock.lock();
+        let mut snapshot = storage.state.read().as_ref().clone();
+        let ssts = std::mem::take(&mut snapshot.l0_sstables);
+        snapshot.l0_sstables = ssts[..2].to_vec();
+        snapshot.levels[0].1 = vec![ssts[2]];
+        snapshot.levels[2].1 = vec![ssts[3]];
+        *storage.state.write() = Arc::new(snapshot);
+    }
+    let (started_tx, started_rx) = crossbeam_channel::unbounded();
+    let (release_tx, release_rx) = crossbeam_channel::unbounded();
+    storage.add_compaction_filter(Arc::new(BlockingFilter {
+        level: 3,
+        started: started_tx,
+        release: release_rx,
+    }));
+
+    let l0_compaction = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.trigger_compaction())
+    };
+    started_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    // the GC runs on the compaction thread alongside the tasks
+    let gc = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.trigger_value_log_gc())
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+    let l1_compaction = {
+        let storage = storage.clone();
+        std::thread::spawn(move || done_tx.send(storage.trigger_compaction()).unwrap())
+    };
+    let result = done_rx.recv_timeout(Duration::from_secs(10));
+    drop(release_tx);
+    result
+        .expect("the L1 compaction waited for the L0 one")
+        .unwrap();
+    l0_compaction.join().unwrap().unwrap();
+    gc.join().unwrap().unwrap();
+    l1_compaction.join().unwrap();
+    let state = storage.state.read();
+    assert!(state.l0_sstables.is_empty());
+    assert!(state.levels[0].1.is_empty());
+    assert_eq!(state.levels[1].1.len(), 1);
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::BufMut;
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{EntryType, StorageIterator},
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    wal::Wal,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Keys divisible by 3 have an empty value, keys with a remainder of 1 a non-empty one, and the rest are deleted.
+fn value_of(idx: usize) -> Option<Vec<u8>> {
+    match idx % 3 {
+        0 => Some(Vec::new()),
+        1 => Some(format!("value_{:05}", idx).into_bytes()),
+        _ => None,
+    }
+}
+
+fn check_keys(storage: &MiniLsm, num_keys: usize) {
+    for idx in 0..num_keys {
+        let value = storage.get(&key_of(idx)).unwrap();
+        assert_eq!(
+            value.map(|value| value.to_vec()),
+            value_of(idx),
+            "key {}",
+            idx
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..num_keys {
+        if let Some(value) = value_of(idx) {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value);
+            iter.next().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_empty_values() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..300 {
+        storage.put(&key_of(idx), b"old_value").unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in 0..300 {
+        match value_of(idx) {
+            Some(value) => storage.put(&key_of(idx), &value).unwrap(),
+            None => storage.delete(&key_of(idx)).unwrap(),
+        }
+    }
+    check_keys(&storage, 300);
+    // the memtable is recovered from the WAL
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage, 300);
+    storage.force_flush().unwrap();
+    check_keys(&storage, 300);
+    // compacting to the bottom level drops the deletions, but not the empty values
+    storage.force_full_compaction().unwrap();
+    check_keys(&storage, 300);
+}
+
+#[test]
+fn test_empty_values_in_batch_and_txn() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(b"a".as_slice(), b"".as_slice()),
+            WriteBatchRecord::Put(b"b", b"1"),
+            WriteBatchRecord::Put(b"c", b"2"),
+        ])
+        .unwrap();
+    let txn = storage.new_txn().unwrap();
+    assert_eq!(txn.get(b"a").unwrap().unwrap(), b"".as_slice());
+    txn.put(b"b", b"");
+    txn.delete(b"c");
+    txn.put(b"d", b"");
+    assert_eq!(txn.get(b"b").unwrap().unwrap(), b"".as_slice());
+    assert_eq!(txn.get(b"c").unwrap(), None);
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for key in [b"a", b"b", b"d"] {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value(), b"");
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    txn.commit().unwrap();
+    for key in [b"a", b"b", b"d"] {
+        assert_eq!(storage.get(key).unwrap().unwrap(), b"".as_slice());
+    }
+    assert_eq!(storage.get(b"c").unwrap(), None);
+}
+
+#[test]
+fn test_legacy_wal_empty_value_is_deletion() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let mut buf = Vec::new();
+    // a V1 WAL, where a deletion is a record of an empty value
+    for (key, ts, value) in [(b"a", 1, b"1".as_slice()), (b"b", 2, b"")] {
+        let mut batch = Vec::new();
+        batch.put_u16(1);
+        batch.put_slice(key);
+        batch.put_u64(ts);
+        batch.put_u16(value.len() as u16);
+        batch.put_slice(value);
+        buf.put_u32(batch.len() as u32);
+        buf.put_slice(&batch);
+        buf.put_u32(crc32fast::hash(&batch));
+    }
+    std::fs::write(&path, buf).unwrap();
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &SkipMap::new()).unwrap();
+    let entry_type = |key: &'static [u8], ts| {
+        map.get(&KeyBytes::from_bytes_with_ts(key.into(), ts))
+            .unwrap()
+            .value()
+            .0
+    };
+    assert_eq!(entry_type(b"a", 1), EntryType::Put);
+    assert_eq!(entry_type(b"b", 2), EntryType::Delete);
+}
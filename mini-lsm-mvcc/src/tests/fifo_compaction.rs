@@ -0,0 +1,190 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, FifoCompactionController, FifoCompactionOptions},
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+    ttl::{Clock, ManualClock},
+};
+
+fn add_sst(state: &mut LsmStorageState, id: usize, size: u64, newest_write_time: u64) {
+    let sst = SsTable::create_meta_only(
+        id,
+        size,
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"a")),
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"z")),
+    )
+    .with_newest_write_time(newest_write_time);
+    state.sstables.insert(id, Arc::new(sst));
+}
+
+#[test]
+fn test_fifo_tasks() {
+    let clock = Arc::new(ManualClock::new(10_000));
+    let controller = FifoCompactionController::new(
+        FifoCompactionOptions {
+            max_table_files_size: 90,
+            ttl: Some(Duration::from_secs(5)),
+            intra_l0_min_merge_width: Some(2),
+            intra_l0_max_merged_size: 30,
+        },
+        clock.clone(),
+    );
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: Vec::new(),
+        sstables: Default::default(),
+        value_logs: Default::default(),
+        compaction_cursors: Default::default(),
+    };
+    add_sst(&mut state, 5, 10, 9_000);
+    add_sst(&mut state, 4, 10, 8_000);
+    add_sst(&mut state, 3, 40, 7_000);
+    add_sst(&mut state, 2, 40, 6_000);
+    add_sst(&mut state, 1, 40, 5_000);
+    state.l0_sstables = vec![5, 4, 3, 2, 1];
+
+    // the oldest SSTs are deleted until the total size is within the limit
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.deleted_sst_ids, vec![1, 2]);
+    assert!(task.merged_sst_ids.is_empty());
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[]);
+    assert_eq!(removed, vec![1, 2]);
+    assert_eq!(state.l0_sstables, vec![5, 4, 3]);
+
+    // the newest small SSTs are merged, and the output takes their place below the SSTs flushed meanwhile
+    let mut state = state;
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.deleted_sst_ids.is_empty());
+    assert_eq!(task.merged_sst_ids, vec![5, 4]);
+    add_sst(&mut state, 7, 20, 9_600);
+    state.l0_sstables.insert(0, 7);
+    add_sst(&mut state, 6, 20, 9_000);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[6]);
+    assert_eq!(removed, vec![5, 4]);
+    assert_eq!(state.l0_sstables, vec![7, 6, 3]);
+    assert!(controller.generate_compaction_task(&state).is_none());
+
+    // the SSTs whose newest write is older than the TTL are deleted
+    clock.advance(Duration::from_millis(4_500));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.deleted_sst_ids, vec![3, 6]);
+}
+
+fn put_and_flush(storage: &MiniLsm, batch: usize) {
+    for i in 0..100 {
+        storage
+            .put(
+                format!("{}-{:03}", batch, i).as_bytes(),
+                format!("value{:0100}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+}
+
+fn assert_batch(storage: &MiniLsm, batch: usize, exists: bool) {
+    for i in 0..100 {
+        let value = storage
+            .get(format!("{}-{:03}", batch, i).as_bytes())
+            .unwrap();
+        assert_eq!(value.is_some(), exists, "batch {} key {}", batch, i);
+    }
+}
+
+#[test]
+fn test_integration() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let mut options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(FifoCompactionOptions {
+            // each batch takes about 13KB
+            max_table_files_size: 30_000,
+            ttl: Some(Duration::from_secs(10)),
+            intra_l0_min_merge_width: None,
+            intra_l0_max_merged_size: 0,
+        }));
+    options.clock = clock.clone();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for batch in 0..3 {
+        put_and_flush(&storage, batch);
+    }
+    storage.inner.trigger_compaction().unwrap();
+    assert_batch(&storage, 0, false);
+    assert_batch(&storage, 1, true);
+    assert_batch(&storage, 2, true);
+    for id in &storage.inner.state.read().l0_sstables {
+        let sst = &storage.inner.state.read().sstables[id];
+        assert_eq!(sst.newest_write_time(), Some(clock.now()));
+    }
+
+    clock.advance(Duration::from_secs(10));
+    put_and_flush(&storage, 3);
+    storage.inner.trigger_compaction().unwrap();
+    assert_batch(&storage, 1, false);
+    assert_batch(&storage, 2, false);
+    assert_batch(&storage, 3, true);
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    assert_batch(&storage, 2, false);
+    assert_batch(&storage, 3, true);
+}
+
+#[test]
+fn test_intra_l0_merge() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(FifoCompactionOptions {
+            max_table_files_size: 1 << 20,
+            ttl: None,
+            intra_l0_min_merge_width: Some(3),
+            intra_l0_max_merged_size: 1 << 20,
+        }));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for batch in 0..3 {
+        put_and_flush(&storage, batch);
+    }
+    storage.delete(b"0-000").unwrap();
+    storage.force_flush().unwrap();
+    storage.inner.trigger_compaction().unwrap();
+    let state = storage.inner.state.read().clone();
+    assert_eq!(state.l0_sstables.len(), 1);
+    assert!(
+        state.sstables[&state.l0_sstables[0]]
+            .newest_write_time()
+            .is_some()
+    );
+    assert_eq!(storage.get(b"0-000").unwrap(), None);
+    assert_eq!(
+        &storage.get(b"0-001").unwrap().unwrap()[..],
+        format!("value{:0100}", 1).as_bytes()
+    );
+    assert_batch(&storage, 1, true);
+    assert_batch(&storage, 2, true);
+}
@@ -0,0 +1,129 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::harness::flush_all;
+
+const GRANDPARENT_SST_SIZE: usize = 4096;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:04}", idx).into_bytes()
+}
+
+fn options(
+    level0_file_num_compaction_trigger: usize,
+    target_sst_size: usize,
+    max_grandparent_overlap_bytes: Option<u64>,
+) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 0,
+            level0_file_num_compaction_trigger,
+            max_levels: 2,
+        },
+    ));
+    options.target_sst_size = target_sst_size;
+    options.max_grandparent_overlap_bytes = max_grandparent_overlap_bytes;
+    options
+}
+
+/// Compacts two L0 SSTs of 1000 keys into L1 above an L2 of small SSTs, and returns the number of bytes of L2 each
+/// SST of L1 overlaps, and the size of the largest SST of L2.
+fn compact_above_small_ssts(max_grandparent_overlap_bytes: Option<u64>) -> (Vec<u64>, u64) {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(100, GRANDPARENT_SST_SIZE, None)).unwrap();
+    for i in 0..1000 {
+        storage.put(&key_of(i), &[b'1'; 100]).unwrap();
+    }
+    flush_all(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // two overlapping L0 SSTs are compacted into L1, and the outputs are only cut by their overlap with L2
+    let storage = MiniLsm::open(&dir, options(2, 1 << 20, max_grandparent_overlap_bytes)).unwrap();
+    for value in [b"2", b"3"] {
+        for i in 0..1000 {
+            storage.put(&key_of(i), value).unwrap();
+        }
+        flush_all(&storage);
+    }
+    while !storage.inner.state.read().l0_sstables.is_empty() {
+        storage.inner.trigger_compaction().unwrap();
+    }
+    for i in [0, 500, 999] {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(Bytes::from("3")));
+    }
+
+    let state = storage.inner.state.read();
+    let grandparents = state.levels[1]
+        .1
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .collect::<Vec<_>>();
+    let overlaps = state.levels[0]
+        .1
+        .iter()
+        .map(|id| {
+            let sst = &state.sstables[id];
+            grandparents
+                .iter()
+                .filter(|grandparent| {
+                    grandparent.first_key().key_ref() <= sst.last_key().key_ref()
+                        && sst.first_key().key_ref() <= grandparent.last_key().key_ref()
+                })
+                .map(|grandparent| grandparent.table_size())
+                .sum()
+        })
+        .collect();
+    let max_grandparent_size = grandparents
+        .iter()
+        .map(|grandparent| grandparent.table_size())
+        .max()
+        .unwrap();
+    (overlaps, max_grandparent_size)
+}
+
+#[test]
+fn test_grandparent_overlap_disabled() {
+    let (overlaps, _) = compact_above_small_ssts(None);
+    assert_eq!(overlaps.len(), 1);
+}
+
+#[test]
+fn test_grandparent_overlap_split() {
+    let max_overlapped_bytes = 4 * GRANDPARENT_SST_SIZE as u64;
+    let (overlaps, max_grandparent_size) = compact_above_small_ssts(Some(max_overlapped_bytes));
+    assert!(overlaps.len() > 1, "{:?}", overlaps);
+    // an output SST passes at most the budget of grandparent SSTs, and partially overlaps one on each end
+    for overlap in overlaps {
+        assert!(
+            overlap <= max_overlapped_bytes + 3 * max_grandparent_size,
+            "{} > {}",
+            overlap,
+            max_overlapped_bytes + 3 * max_grandparent_size
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    group_commit::{GroupCommitStats, WalSyncPolicy},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+fn options(wal_sync: WalSyncPolicy) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.wal_sync = wal_sync;
+    options
+}
+
+fn key_of(thread: usize, idx: usize) -> Vec<u8> {
+    format!("key_{}_{:03}", thread, idx).into_bytes()
+}
+
+#[test]
+fn test_group_commit_concurrent_writes() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, options(WalSyncPolicy::EveryCommit)).unwrap());
+    let start_ts = storage.mvcc().latest_commit_ts();
+    let handles = (0..8)
+        .map(|thread| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                (0..50)
+                    .map(|i| {
+                        storage
+                            .write_batch_inner(&[
+                                WriteBatchRecord::Put(key_of(thread, i), b"v".to_vec()),
+                                WriteBatchRecord::Del(key_of(thread, i + 1)),
+                            ])
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut commit_ts = handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>();
+
+    // each write gets its own ts, as if they were committed one by one
+    commit_ts.sort();
+    assert_eq!(
+        commit_ts,
+        (start_ts + 1..=start_ts + 400).collect::<Vec<_>>()
+    );
+    assert_eq!(storage.mvcc().latest_commit_ts(), start_ts + 400);
+    let stats = storage.group_commit_stats();
+    assert_eq!(stats.writes, 400);
+    assert!(stats.groups <= 400);
+    // every group is synced once, however many writes it has
+    assert_eq!(stats.wal_syncs, stats.groups);
+
+    // the writes of a thread are committed in order, so the deletion of the next key is overwritten by its put
+    drop(storage);
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, options(WalSyncPolicy::EveryCommit)).unwrap());
+    for thread in 0..8 {
+        for i in 0..50 {
+            assert_eq!(
+                storage.get(&key_of(thread, i)).unwrap(),
+                Some(Bytes::from_static(b"v"))
+            );
+        }
+        assert_eq!(storage.get(&key_of(thread, 50)).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_wal_sync_never() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(&dir, options(WalSyncPolicy::Never)).unwrap());
+    for i in 0..10 {
+        storage.put(&key_of(0, i), b"v").unwrap();
+    }
+    assert_eq!(
+        storage.group_commit_stats(),
+        GroupCommitStats {
+            groups: 10,
+            writes: 10,
+            wal_syncs: 0,
+        }
+    );
+}
+
+#[test]
+fn test_wal_sync_interval() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            options(WalSyncPolicy::Interval(Duration::from_secs(3600))),
+        )
+        .unwrap(),
+    );
+    for i in 0..10 {
+        storage.put(&key_of(0, i), b"v").unwrap();
+    }
+    // the interval has not passed since the storage was opened
+    assert_eq!(storage.group_commit_stats().wal_syncs, 0);
+
+    // the flush thread syncs the last write once the interval passes
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        options(WalSyncPolicy::Interval(Duration::from_millis(10))),
+    )
+    .unwrap();
+    storage.put(b"key", b"v").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    let stats = storage.group_commit_stats();
+    assert!(stats.wal_syncs >= 1, "{:?}", stats);
+}
+
+#[test]
+fn test_max_write_group_size() {
+    let dir = tempdir().unwrap();
+    let mut options = options(WalSyncPolicy::EveryCommit);
+    options.max_write_group_size = 0;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let handles = (0..4)
+        .map(|thread| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    storage.put(&key_of(thread, i), b"v").unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // a group takes a single write once it is over the size
+    let stats = storage.group_commit_stats();
+    assert_eq!(stats.writes, 200);
+    assert_eq!(stats.groups, 200);
+}
+
+#[test]
+fn test_committed_write_with_failed_freeze() {
+    let dir = tempdir().unwrap();
+    let mut options = options(WalSyncPolicy::EveryCommit);
+    options.target_sst_size = 1;
+    let storage = Arc::new(LsmStorageInner::open(dir.path().join("db"), options).unwrap());
+    let start_ts = storage.mvcc().latest_commit_ts();
+    // the WAL of the next memtable cannot be created
+    std::fs::remove_dir_all(dir.path().join("db")).unwrap();
+    let err = storage.put(b"a", b"1").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains(&format!("committed at ts {}", start_ts + 1)),
+        "{:#}",
+        err
+    );
+    assert_eq!(storage.mvcc().latest_commit_ts(), start_ts + 1);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::{BufMut, Bytes};
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use crate::{
+    block::Block,
+    compact::CompactionOptions,
+    iterators::{EntryType, StorageIterator},
+    key::{KeyBytes, KeySlice},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{FileObject, SsTable, SsTableIterator, SstFormatVersion, bloom::Bloom},
+    wal::Wal,
+};
+
+fn large_value(idx: usize, len: usize) -> Vec<u8> {
+    format!("{:08}", idx).repeat(len / 8).into_bytes()
+}
+
+#[test]
+fn test_large_keys_and_values() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let sizes = [100 << 10, 1 << 20, 70 << 10];
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for (idx, size) in sizes.iter().enumerate() {
+        let key = large_value(idx, 80 << 10);
+        storage.put(&key, &large_value(idx, *size)).unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    for (idx, size) in sizes.iter().enumerate() {
+        let key = large_value(idx, 80 << 10);
+        assert_eq!(storage.get(&key).unwrap().unwrap(), large_value(idx, *size));
+    }
+}
+
+#[test]
+fn test_large_values_in_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .put(&large_value(0, 80 << 10), &large_value(1, 200 << 10))
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(&large_value(0, 80 << 10)).unwrap().unwrap(),
+        large_value(1, 200 << 10)
+    );
+}
+
+/// Write an SST in the original format by hand: `u16` lengths and offsets inside blocks, blocks followed only by
+/// their checksum, `u32` block meta and bloom filter offsets, and no footer.
+fn write_legacy_sst(path: &std::path::Path, entries: &[(&[u8], u64, &[u8])]) {
+    let mut buf = Vec::new();
+    let mut meta = Vec::new();
+    // one block per entry
+    for (key, ts, value) in entries {
+        let offset = buf.len();
+        let mut block = Vec::new();
+        block.put_u16(0);
+        block.put_u16(key.len() as u16);
+        block.put_slice(key);
+        block.put_u64(*ts);
+        block.put_u16(value.len() as u16);
+        block.put_slice(value);
+        block.put_u16(0); // offset of the only entry
+        block.put_u16(1); // number of entries
+        let checksum = crc32fast::hash(&block);
+        buf.extend(block);
+        buf.put_u32(checksum);
+        meta.push((offset, key, *ts));
+    }
+    let meta_offset = buf.len();
+    buf.put_u32(meta.len() as u32);
+    for (offset, key, ts) in &meta {
+        buf.put_u32(*offset as u32);
+        for _ in 0..2 {
+            buf.put_u16(key.len() as u16);
+            buf.put_slice(key);
+            buf.put_u64(*ts);
+        }
+    }
+    buf.put_u64(entries.iter().map(|(_, ts, _)| *ts).max().unwrap());
+    buf.put_u32(crc32fast::hash(&buf[meta_offset + 4..]));
+    buf.put_u32(meta_offset as u32);
+    let hashes = entries
+        .iter()
+        .map(|(key, _, _)| farmhash::fingerprint32(key))
+        .collect::<Vec<_>>();
+    let bloom_offset = buf.len();
+    Bloom::build_from_key_hashes(&hashes, 10).encode(&mut buf);
+    buf.put_u32(bloom_offset as u32);
+    std::fs::write(path, buf).unwrap();
+}
+
+#[test]
+fn test_open_legacy_sst() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let entries: [(&[u8], u64, &[u8]); 3] =
+        [(b"a", 3, b"value_a"), (b"b", 2, b"value_b"), (b"c", 1, b"")];
+    write_legacy_sst(&path, &entries);
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.format_version(), SstFormatVersion::V1);
+    assert_eq!(sst.max_ts(), 3);
+    assert_eq!(sst.num_of_blocks(), 3);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.into()).unwrap();
+    for (key, ts, value) in entries {
+        assert!(iter.is_valid());
+        assert_eq!(
+            iter.key(),
+            KeySlice::for_testing_from_slice_with_ts(key, ts)
+        );
+        assert_eq!(iter.value(), value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_corrupted_legacy_block() {
+    let mut block = Vec::new();
+    block.put_u16(0);
+    block.put_u16(1);
+    block.put_slice(b"a");
+    block.put_u64(1);
+    block.put_u16(1);
+    block.put_slice(b"1");
+    block.put_u16(0);
+    assert!(Block::decode_legacy(&block).is_err());
+    block.put_u16(1);
+    assert_eq!(Block::decode_legacy(&block).unwrap().offsets, vec![0]);
+    // the value is cut off
+    let len = block.len();
+    block.remove(len - 5);
+    assert!(Block::decode_legacy(&block).is_err());
+}
+
+#[test]
+fn test_recover_legacy_wal() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let mut buf = Vec::new();
+    for (key, ts, value) in [(b"a", 1, b"1"), (b"b", 2, b"2")] {
+        let mut batch = Vec::new();
+        batch.put_u16(1);
+        batch.put_slice(key);
+        batch.put_u64(ts);
+        batch.put_u16(1);
+        batch.put_slice(value);
+        buf.put_u32(batch.len() as u32);
+        buf.put_slice(&batch);
+        buf.put_u32(crc32fast::hash(&batch));
+    }
+    std::fs::write(&path, buf).unwrap();
+    let map = SkipMap::new();
+    let wal = Wal::recover(&path, &map, &SkipMap::new()).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+        map.get(&KeyBytes::from_bytes_with_ts(Bytes::from_static(b"b"), 2))
+            .unwrap()
+            .value(),
+        &(EntryType::Put, Bytes::from_static(b"2"))
+    );
+    // records appended to a legacy WAL keep the legacy encoding
+    wal.put(KeySlice::from_slice(b"c", 3), b"3").unwrap();
+    assert!(
+        wal.put(KeySlice::from_slice(b"d", 4), &vec![0; 70 << 10])
+            .is_err()
+    );
+    wal.sync().unwrap();
+    drop(wal);
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &SkipMap::new()).unwrap();
+    assert_eq!(map.len(), 3);
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{EntryType, StorageIterator},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    merge_operator::MergeOperator,
+    table::SsTableIterator,
+};
+
+use super::harness::flush_all;
+
+/// Appends the operands to a comma-separated list, so that the order they are applied in is visible.
+struct Append;
+
+impl MergeOperator for Append {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Bytes> {
+        Ok(existing
+            .into_iter()
+            .chain(operands.iter().copied())
+            .collect::<Vec<_>>()
+            .join(&b","[..])
+            .into())
+    }
+
+    fn partial_merge(&self, _key: &[u8], older: &[u8], newer: &[u8]) -> Result<Bytes> {
+        Ok([older, newer].join(&b","[..]).into())
+    }
+}
+
+fn options_with_append() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.merge_operator = Some(Arc::new(Append));
+    options
+}
+
+fn check_values(storage: &MiniLsm, expected: &[(&[u8], &[u8])]) {
+    for (key, value) in expected {
+        assert_eq!(storage.get(key).unwrap().unwrap(), value, "key {:?}", key);
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), *key);
+        assert_eq!(iter.value(), *value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+/// All entries of all SSTs as `(key, entry type, value)`.
+fn sst_entries(storage: &MiniLsm) -> Vec<(Vec<u8>, EntryType, Vec<u8>)> {
+    let state = storage.inner.state.read();
+    let mut entries = Vec::new();
+    for sst in state.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            entries.push((
+                iter.key().key_ref().to_vec(),
+                iter.entry_type(),
+                iter.value().to_vec(),
+            ));
+            iter.next().unwrap();
+        }
+    }
+    entries
+}
+
+#[test]
+fn test_merge() {
+    let dir = tempdir().unwrap();
+    let mut options = options_with_append();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    storage.merge(b"a", b"1").unwrap();
+    storage.merge(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.merge(b"a", b"2").unwrap();
+    storage.merge(b"b", b"2").unwrap();
+    storage.put(b"c", b"0").unwrap();
+    storage.delete(b"c").unwrap();
+    storage.merge(b"c", b"1").unwrap();
+    storage.merge(b"d", b"1").unwrap();
+    storage.delete_range(b"d", b"e").unwrap();
+    storage.merge(b"d", b"2").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Merge(b"e".as_slice(), b"1".as_slice()),
+            WriteBatchRecord::Merge(b"e", b"2"),
+            WriteBatchRecord::Put(b"f", b"0"),
+            WriteBatchRecord::Merge(b"f", b"1"),
+        ])
+        .unwrap();
+    let expected: [(&[u8], &[u8]); 6] = [
+        (b"a", b"0,1,2"),
+        (b"b", b"1,2"),
+        (b"c", b"1"),
+        (b"d", b"2"),
+        (b"e", b"1,2"),
+        (b"f", b"0,1"),
+    ];
+    check_values(&storage, &expected);
+    // the operands in the memtable are recovered from the WAL
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_values(&storage, &expected);
+    flush_all(&storage);
+    storage.force_full_compaction().unwrap();
+    check_values(&storage, &expected);
+}
+
+#[test]
+fn test_merge_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options_with_append()).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    storage.merge(b"a", b"1").unwrap();
+    storage.merge(b"b", b"1").unwrap();
+    storage.merge(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.merge(b"a", b"2").unwrap();
+    storage.merge(b"a", b"3").unwrap();
+    storage.merge(b"b", b"3").unwrap();
+    flush_all(&storage);
+    // the operands above the watermark are kept for the snapshot, the ones below it are folded
+    storage.force_full_compaction().unwrap();
+    let entry = |key: &[u8], entry_type, value: &[u8]| (key.to_vec(), entry_type, value.to_vec());
+    assert_eq!(
+        sst_entries(&storage),
+        vec![
+            entry(b"a", EntryType::Merge, b"3"),
+            entry(b"a", EntryType::Merge, b"2"),
+            entry(b"a", EntryType::Put, b"0,1"),
+            entry(b"b", EntryType::Merge, b"3"),
+            entry(b"b", EntryType::Put, b"1,2"),
+        ]
+    );
+    check_values(&storage, &[(b"a", b"0,1,2,3"), (b"b", b"1,2,3")]);
+    assert_eq!(snapshot.get(b"a").unwrap().unwrap(), b"0,1".as_slice());
+    assert_eq!(snapshot.get(b"b").unwrap().unwrap(), b"1,2".as_slice());
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        sst_entries(&storage),
+        vec![
+            entry(b"a", EntryType::Put, b"0,1,2,3"),
+            entry(b"b", EntryType::Put, b"1,2,3"),
+        ]
+    );
+}
+
+#[test]
+fn test_merge_in_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = options_with_append();
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"d", b"0").unwrap();
+    // merges do not read the key, so concurrent merges do not conflict
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.merge(b"a", b"1").unwrap();
+    txn1.merge(b"a", b"2").unwrap();
+    txn2.merge(b"a", b"3").unwrap();
+    txn1.commit().unwrap();
+    txn2.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), b"1,2,3".as_slice());
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"0");
+    txn.merge(b"b", b"1").unwrap();
+    txn.delete(b"c");
+    txn.merge(b"c", b"1").unwrap();
+    txn.merge(b"d", b"1").unwrap();
+    assert_eq!(txn.get(b"b").unwrap().unwrap(), b"0,1".as_slice());
+    assert_eq!(txn.get(b"d").unwrap().unwrap(), b"0,1".as_slice());
+    let expected: [(&[u8], &[u8]); 4] = [
+        (b"a", b"1,2,3"),
+        (b"b", b"0,1"),
+        (b"c", b"1"),
+        (b"d", b"0,1"),
+    ];
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value(), value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    txn.commit().unwrap();
+    check_values(&storage, &expected);
+
+    // merging requires a merge operator
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.merge(b"a", b"1").is_err());
+}
@@ -0,0 +1,286 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    range_tombstone::{FragmentedRangeTombstones, RangeTombstone},
+    table::SsTableIterator,
+};
+
+use super::harness::flush_all;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+/// Checks that exactly the keys in `expected` are visible, both through `get` and `scan`.
+fn check_keys(storage: &MiniLsm, num_keys: usize, expected: impl Fn(usize) -> bool) {
+    for idx in 0..num_keys {
+        let value = storage.get(&key_of(idx)).unwrap();
+        assert_eq!(value.is_some(), expected(idx), "key {}", idx);
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in (0..num_keys).filter(|idx| expected(*idx)) {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_delete_range() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(20), &key_of(50)).unwrap();
+    storage.put(&key_of(30), &value_of(30)).unwrap();
+    let expected = |idx| !(20..50).contains(&idx) || idx == 30;
+    check_keys(&storage, 100, expected);
+    // the range tombstone is not visible to an older snapshot
+    assert_eq!(snapshot.get(&key_of(20)).unwrap().unwrap(), value_of(20));
+    let mut iter = snapshot
+        .scan(Bound::Included(&key_of(20)), Bound::Excluded(&key_of(50)))
+        .unwrap();
+    for idx in 20..50 {
+        assert_eq!(iter.key(), key_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    drop(iter);
+    drop(snapshot);
+
+    // recover the range tombstone from the WAL
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage, 100, expected);
+    // and from an SST
+    storage.force_flush().unwrap();
+    check_keys(&storage, 100, expected);
+}
+
+#[test]
+fn test_delete_range_in_batch_and_txn() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    // a range tombstone in a batch deletes the earlier writes of the batch, but not the later ones
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(key_of(10), value_of(10)),
+            WriteBatchRecord::DelRange(key_of(2), key_of(11)),
+            WriteBatchRecord::Put(key_of(5), value_of(5)),
+        ])
+        .unwrap();
+    check_keys(&storage, 11, |idx| !(2..11).contains(&idx) || idx == 5);
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(0), b"overwritten");
+    txn.delete_range(&key_of(0), &key_of(2));
+    txn.put(&key_of(1), &value_of(1));
+    assert_eq!(txn.get(&key_of(0)).unwrap(), None);
+    assert_eq!(txn.get(&key_of(1)).unwrap().unwrap(), value_of(1));
+    assert_eq!(txn.get(&key_of(5)).unwrap().unwrap(), value_of(5));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in [1, 5] {
+        assert_eq!(iter.key(), key_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    drop(iter);
+    // not visible outside of the transaction before it commits
+    assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), value_of(0));
+    txn.commit().unwrap();
+    check_keys(&storage, 11, |idx| idx == 1 || idx == 5);
+}
+
+#[test]
+fn test_delete_ranges_with_same_start() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    // the ranges of a batch or a transaction share a ts, and the longer one is kept
+    storage
+        .write_batch(&[
+            WriteBatchRecord::DelRange(key_of(0), key_of(8)),
+            WriteBatchRecord::DelRange(key_of(0), key_of(3)),
+        ])
+        .unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.delete_range(&key_of(10), &key_of(15));
+    txn.delete_range(&key_of(10), &key_of(12));
+    txn.commit().unwrap();
+    let expected = |idx| !(0..8).contains(&idx) && !(10..15).contains(&idx);
+    check_keys(&storage, 20, expected);
+
+    // from the WAL
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage, 20, expected);
+
+    // from the SST flushed from the memtable
+    flush_all(&storage);
+    check_keys(&storage, 20, expected);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_delete_range_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 4096;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    flush_all(&storage);
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(100), &key_of(900)).unwrap();
+    // an SST holding nothing but the range tombstone
+    flush_all(&storage);
+    let expected = |idx| !(100..900).contains(&idx);
+    check_keys(&storage, 1000, expected);
+
+    // the snapshot still reads the deleted keys, so compaction keeps them and splits the range tombstone
+    // across the output SSTs
+    storage.force_full_compaction().unwrap();
+    check_keys(&storage, 1000, expected);
+    assert_eq!(snapshot.get(&key_of(500)).unwrap().unwrap(), value_of(500));
+    {
+        let state = storage.inner.state.read();
+        assert!(state.levels[0].1.len() > 1);
+        let num_tombstones = state.levels[0]
+            .1
+            .iter()
+            .map(|id| state.sstables[id].range_tombstones().len())
+            .sum::<usize>();
+        assert!(num_tombstones > 1);
+    }
+    drop(snapshot);
+
+    // below the watermark, the covered data and the range tombstone are dropped at the bottom level
+    storage.force_full_compaction().unwrap();
+    check_keys(&storage, 1000, expected);
+    let state = storage.inner.state.read();
+    let mut num_entries = 0;
+    for id in &state.levels[0].1 {
+        let sst = state.sstables[id].clone();
+        assert!(sst.range_tombstones().is_empty());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        while iter.is_valid() {
+            num_entries += 1;
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(num_entries, 200);
+}
+
+#[test]
+fn test_fragmented_range_tombstones() {
+    let tombstone = |start: &'static [u8], end: &'static [u8], ts| {
+        RangeTombstone::new(Bytes::from_static(start), Bytes::from_static(end), ts)
+    };
+    let tombstones = vec![
+        tombstone(b"b", b"f", 5),
+        tombstone(b"d", b"h", 8),
+        tombstone(b"d", b"e", 3),
+        tombstone(b"m", b"p", 2),
+        tombstone(b"x", b"x", 9),
+    ];
+    let fragments = FragmentedRangeTombstones::new(&tombstones);
+    let split = fragments
+        .overlapping(Bound::Unbounded, Bound::Unbounded)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        split,
+        vec![
+            tombstone(b"b", b"d", 5),
+            tombstone(b"d", b"e", 8),
+            tombstone(b"d", b"e", 5),
+            tombstone(b"d", b"e", 3),
+            tombstone(b"e", b"f", 8),
+            tombstone(b"e", b"f", 5),
+            tombstone(b"f", b"h", 8),
+            tombstone(b"m", b"p", 2),
+        ]
+    );
+    let overlapping = fragments
+        .overlapping(Bound::Included(b"f"), Bound::Excluded(b"m"))
+        .collect::<Vec<_>>();
+    assert_eq!(overlapping, vec![tombstone(b"f", b"h", 8)]);
+
+    // the lookups by binary search and by cursor agree with the tombstones for every key, version and reader
+    let keys = [
+        &b"a"[..],
+        b"b",
+        b"c",
+        b"d",
+        b"d1",
+        b"e",
+        b"f",
+        b"g",
+        b"h",
+        b"i",
+        b"m",
+        b"o",
+        b"p",
+        b"x",
+        b"z",
+    ];
+    for read_ts in 0..10 {
+        for ts in 0..10 {
+            let mut cursor = 0;
+            for key in keys {
+                let expected = tombstones
+                    .iter()
+                    .any(|tombstone| tombstone.ts <= read_ts && tombstone.covers(key, ts));
+                assert_eq!(
+                    fragments.deletes_from_cursor(&mut 0, key, ts, read_ts),
+                    expected
+                );
+                assert_eq!(
+                    fragments.deletes_from_cursor(&mut cursor, key, ts, read_ts),
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    rate_limiter::{IoPriority, RateLimiter},
+    table::FileObject,
+};
+
+#[test]
+fn test_rate_limit_adjusted_at_runtime() {
+    let dir = tempdir().unwrap();
+    // 100KB per refill period, which the bucket starts with
+    let rate_limiter = RateLimiter::new(1 << 20);
+    let start = Instant::now();
+    let file = FileObject::create_with_rate_limiter(
+        &dir.path().join("1.sst"),
+        vec![1; 300 << 10],
+        Some((&rate_limiter, IoPriority::Low)),
+    )
+    .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(file.size(), 300 << 10);
+    assert_eq!(rate_limiter.total_bytes(IoPriority::Low), 300 << 10);
+
+    rate_limiter.set_bytes_per_second(0);
+    assert_eq!(rate_limiter.bytes_per_second(), 0);
+    let start = Instant::now();
+    FileObject::create_with_rate_limiter(
+        &dir.path().join("2.sst"),
+        vec![1; 16 << 20],
+        Some((&rate_limiter, IoPriority::Low)),
+    )
+    .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        rate_limiter.total_bytes(IoPriority::Low),
+        (300 << 10) + (16 << 20)
+    );
+}
+
+#[test]
+fn test_high_priority_first() {
+    // 10KB per refill period
+    let rate_limiter = Arc::new(RateLimiter::new(100 << 10));
+    let low = {
+        let rate_limiter = rate_limiter.clone();
+        std::thread::spawn(move || {
+            let mut remaining = 100 << 10;
+            while remaining > 0 {
+                remaining -= rate_limiter.request(remaining, IoPriority::Low);
+            }
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    let low_before = rate_limiter.total_bytes(IoPriority::Low);
+    let mut remaining = 30 << 10;
+    while remaining > 0 {
+        remaining -= rate_limiter.request(remaining, IoPriority::High);
+    }
+    // the low priority writes wait for the high priority ones, except for a request already granted
+    assert!(rate_limiter.total_bytes(IoPriority::Low) - low_before <= 10 << 10);
+    assert!(!low.is_finished());
+    low.join().unwrap();
+    assert_eq!(rate_limiter.total_bytes(IoPriority::High), 30 << 10);
+    assert_eq!(rate_limiter.total_bytes(IoPriority::Low), 100 << 10);
+}
+
+#[test]
+fn test_flush_and_compaction_rate_limited() {
+    let dir = tempdir().unwrap();
+    let rate_limiter = Arc::new(RateLimiter::new(16 << 20));
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.rate_limiter = Some(rate_limiter.clone());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..2 {
+        for j in 0..100 {
+            storage
+                .put(
+                    format!("key{:03}", j).as_bytes(),
+                    format!("value{}", i).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let flushed = rate_limiter.total_bytes(IoPriority::High);
+    assert!(flushed > 0);
+    assert_eq!(rate_limiter.total_bytes(IoPriority::Low), 0);
+
+    storage.force_full_compaction().unwrap();
+    assert!(rate_limiter.total_bytes(IoPriority::Low) > 0);
+    assert_eq!(rate_limiter.total_bytes(IoPriority::High), flushed);
+    for j in 0..100 {
+        assert_eq!(
+            &storage
+                .get(format!("key{:03}", j).as_bytes())
+                .unwrap()
+                .unwrap()[..],
+            b"value1"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    mvcc::txn::TxnIterator,
+    replication::{Follower, WalShipper},
+};
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn collect(mut iter: TxnIterator) -> Vec<(Bytes, Bytes)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+fn assert_replicated(leader: &MiniLsm, follower: &Follower) {
+    assert_eq!(
+        follower.applied_ts(),
+        leader.inner.mvcc().latest_commit_ts()
+    );
+    assert_eq!(
+        collect(follower.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        collect(leader.scan(Bound::Unbounded, Bound::Unbounded).unwrap())
+    );
+}
+
+#[test]
+fn test_follower_bootstrap_and_catch_up() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    for i in 0..10 {
+        leader.put(&key_of(i), b"flushed").unwrap();
+    }
+    leader.force_flush().unwrap();
+    for i in 10..20 {
+        leader.put(&key_of(i), b"in memtable").unwrap();
+    }
+
+    let checkpoint = dir.path().join("follower");
+    let mut shipper = WalShipper::with_checkpoint(leader.clone(), "follower", &checkpoint).unwrap();
+    assert_eq!(shipper.shipped_ts(), 20);
+    leader.put(&key_of(0), b"updated").unwrap();
+    leader.delete(&key_of(11)).unwrap();
+    leader.delete_range(&key_of(15), &key_of(18)).unwrap();
+    let txn = leader.new_txn().unwrap();
+    txn.put(&key_of(20), b"txn");
+    txn.delete(&key_of(1));
+    txn.commit().unwrap();
+    leader
+        .write_batch(&[
+            WriteBatchRecord::Put(key_of(21), b"batch".to_vec()),
+            WriteBatchRecord::Put(key_of(22), b"batch".to_vec()),
+        ])
+        .unwrap();
+
+    let follower = Follower::open(&checkpoint, options(), Duration::from_secs(3600)).unwrap();
+    assert_eq!(follower.applied_ts(), 20);
+    // the follower does not serve reads until it hears from the leader
+    assert!(follower.get(&key_of(0)).is_err());
+    let mut stream = Vec::new();
+    assert_eq!(shipper.ship(&mut stream).unwrap(), 5);
+    follower.replicate_from(&stream[..]).unwrap();
+    assert_eq!(
+        follower.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"updated"))
+    );
+    assert_eq!(follower.get(&key_of(11)).unwrap(), None);
+    assert_eq!(follower.get(&key_of(16)).unwrap(), None);
+    assert_replicated(&leader, &follower);
+
+    // a message applied again is skipped
+    follower.replicate_from(&stream[..]).unwrap();
+    assert_replicated(&leader, &follower);
+}
+
+#[test]
+fn test_follower_over_socket() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    let checkpoint = dir.path().join("follower");
+    let mut shipper = WalShipper::with_checkpoint(leader.clone(), "follower", &checkpoint).unwrap();
+    let follower =
+        Arc::new(Follower::open(&checkpoint, options(), Duration::from_secs(3600)).unwrap());
+
+    let (mut sender, receiver) = UnixStream::pair().unwrap();
+    let handle = {
+        let follower = follower.clone();
+        std::thread::spawn(move || follower.replicate_from(receiver))
+    };
+    for round in 0..5 {
+        for i in 0..100 {
+            leader
+                .put(&key_of(i), format!("value_{}", round).as_bytes())
+                .unwrap();
+        }
+        // the WALs the shipper has shipped are removed once flushed
+        leader.force_flush().unwrap();
+        shipper.ship(&mut sender).unwrap();
+    }
+    drop(sender);
+    handle.join().unwrap().unwrap();
+    assert_replicated(&leader, &follower);
+    assert!(leader.updates_since(0).is_err());
+
+    // a follower that restarts resumes from the batches it has applied
+    follower.close().unwrap();
+    drop(follower);
+    drop(shipper);
+    leader.delete(&key_of(0)).unwrap();
+    let follower = Follower::open(&checkpoint, options(), Duration::from_secs(3600)).unwrap();
+    let mut shipper = WalShipper::new(leader.clone(), "follower", follower.applied_ts()).unwrap();
+    let mut stream = Vec::new();
+    assert_eq!(shipper.ship(&mut stream).unwrap(), 1);
+    follower.replicate_from(&stream[..]).unwrap();
+    assert_eq!(follower.get(&key_of(0)).unwrap(), None);
+    assert_replicated(&leader, &follower);
+}
+
+#[test]
+fn test_follower_bounded_staleness() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    let checkpoint = dir.path().join("follower");
+    let mut shipper = WalShipper::with_checkpoint(leader.clone(), "follower", &checkpoint).unwrap();
+    let follower = Follower::open(&checkpoint, options(), Duration::from_millis(200)).unwrap();
+
+    leader.put(b"a", b"1").unwrap();
+    let mut stream = Vec::new();
+    shipper.ship(&mut stream).unwrap();
+    follower.replicate_from(&stream[..]).unwrap();
+    assert_eq!(follower.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+
+    // a heartbeat keeps an idle follower readable
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(follower.get(b"a").is_err());
+    let mut stream = Vec::new();
+    assert_eq!(shipper.ship(&mut stream).unwrap(), 0);
+    follower.replicate_from(&stream[..]).unwrap();
+    assert_eq!(follower.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+}
+
+#[test]
+fn test_checkpoint_with_concurrent_writes() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    let handle = {
+        let leader = leader.clone();
+        std::thread::spawn(move || {
+            for i in 0..300 {
+                leader.put(&key_of(i), b"v").unwrap();
+                if i % 20 == 0 {
+                    leader.force_flush().unwrap();
+                }
+            }
+        })
+    };
+    while leader.inner.mvcc().latest_commit_ts() < 100 {
+        std::thread::yield_now();
+    }
+    let checkpoint = dir.path().join("checkpoint");
+    let checkpoint_ts = leader.create_checkpoint(&checkpoint).unwrap();
+    handle.join().unwrap();
+
+    // the checkpoint has the writes up to its ts and none after, as the files are copied up to their sizes
+    let storage = MiniLsm::open(&checkpoint, options()).unwrap();
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), checkpoint_ts);
+    let keys = collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap())
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    let expected = (0..checkpoint_ts as usize)
+        .map(|i| Bytes::from(key_of(i)))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_follower_max_batch_size() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    let checkpoint = dir.path().join("follower");
+    let mut shipper = WalShipper::with_checkpoint(leader.clone(), "follower", &checkpoint).unwrap();
+    let follower = Follower::open(&checkpoint, options(), Duration::from_secs(3600))
+        .unwrap()
+        .with_max_batch_size(64);
+
+    leader.put(b"a", b"1").unwrap();
+    let mut stream = Vec::new();
+    shipper.ship(&mut stream).unwrap();
+    follower.replicate_from(&stream[..]).unwrap();
+    assert_eq!(follower.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+
+    // a message over the limit is rejected before it is read
+    leader.put(b"b", &[b'x'; 128]).unwrap();
+    let mut stream = Vec::new();
+    shipper.ship(&mut stream).unwrap();
+    assert!(follower.replicate_from(&stream[..]).is_err());
+    assert_eq!(follower.get(b"b").unwrap(), None);
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{check_compaction_ratio, compaction_bench};
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
+        SstSelectionPolicy,
+    },
+    iterators::EntryType,
+    key::{KeyBytes, KeySlice},
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::{FileObject, SsTable, SsTableBuilder},
+};
+
+fn add_sst(
+    state: &mut LsmStorageState,
+    id: usize,
+    first_key: &'static [u8],
+    last_key: &'static [u8],
+) {
+    let sst = SsTable::create_meta_only(
+        id,
+        1 << 20,
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(first_key)),
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(last_key)),
+    )
+    .with_num_deletes(id as u64 % 5);
+    state.sstables.insert(id, Arc::new(sst));
+}
+
+fn selected_sst(controller: &LeveledCompactionController, state: &LsmStorageState) -> usize {
+    let task = controller.generate_compaction_task(state).unwrap();
+    assert_eq!(task.upper_level, Some(1));
+    task.upper_level_sst_ids[0]
+}
+
+#[test]
+fn test_sst_selection_policies() {
+    let options = LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    };
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: Vec::new(),
+        sstables: Default::default(),
+        value_logs: Default::default(),
+        compaction_cursors: Default::default(),
+    };
+    // L1 is 3MB over a target of 2MB. SST 9 overlaps two SSTs of L2, SST 6 one, and SST 7 none; SST 9 has the
+    // most deletions.
+    add_sst(&mut state, 9, b"a", b"b");
+    add_sst(&mut state, 6, b"c", b"d");
+    add_sst(&mut state, 7, b"e", b"f");
+    add_sst(&mut state, 1, b"a", b"a5");
+    add_sst(&mut state, 2, b"a6", b"b");
+    add_sst(&mut state, 3, b"c", b"d");
+    add_sst(&mut state, 5, b"x", b"z");
+    state.levels = vec![(1, vec![9, 6, 7]), (2, vec![1, 2, 3, 5])];
+
+    let controller =
+        |policy| LeveledCompactionController::new(options.clone()).with_sst_selection(policy);
+    assert_eq!(
+        selected_sst(&controller(SstSelectionPolicy::OldestFirst), &state),
+        6
+    );
+    assert_eq!(
+        selected_sst(&controller(SstSelectionPolicy::MinOverlappingRatio), &state),
+        7
+    );
+    assert_eq!(
+        selected_sst(&controller(SstSelectionPolicy::MostTombstones), &state),
+        9
+    );
+
+    // round-robin starts from the first SST, and continues after the last key compacted from the level
+    let round_robin = controller(SstSelectionPolicy::RoundRobin);
+    assert_eq!(selected_sst(&round_robin, &state), 9);
+    let cursor = round_robin.compaction_cursor(&state, Some(1), &[9]);
+    assert_eq!(cursor, Some((1, Bytes::from_static(b"b"))));
+    state.compaction_cursors.insert(1, Bytes::from_static(b"b"));
+    assert_eq!(selected_sst(&round_robin, &state), 6);
+    state.compaction_cursors.insert(1, Bytes::from_static(b"f"));
+    assert_eq!(selected_sst(&round_robin, &state), 9);
+    assert_eq!(round_robin.compaction_cursor(&state, None, &[9]), None);
+    assert_eq!(
+        controller(SstSelectionPolicy::OldestFirst).compaction_cursor(&state, Some(1), &[9]),
+        None
+    );
+}
+
+#[test]
+fn test_num_deletes() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        let key = format!("key_{:03}", i);
+        let entry_type = if i % 4 == 0 {
+            EntryType::Delete
+        } else {
+            EntryType::Put
+        };
+        builder.add_with_type(
+            KeySlice::for_testing_from_slice_no_ts(key.as_bytes()),
+            b"value",
+            entry_type,
+        );
+    }
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert_eq!(sst.num_deletes(), 25);
+    let sst = SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.num_deletes(), 25);
+}
+
+#[test]
+fn test_integration_round_robin() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.sst_selection = SstSelectionPolicy::RoundRobin;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    compaction_bench(storage.clone());
+    check_compaction_ratio(storage.clone());
+    let cursors = storage.inner.state.read().compaction_cursors.clone();
+    assert!(!cursors.is_empty());
+
+    // the cursors are recovered from the manifest
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().compaction_cursors, cursors);
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, CompactionTask},
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:04}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, version: usize) -> Bytes {
+    Bytes::from(format!("value_{:04}_v{}", idx, version))
+}
+
+/// Writes the keys to L0 SSTs, flushing the memtable before it is large enough to be frozen by a write, so that
+/// no immutable memtable is left to the flush thread.
+fn put_and_flush(storage: &MiniLsm, keys: Vec<usize>, version: usize) {
+    for chunk in keys.chunks(50) {
+        for &i in chunk {
+            storage.put(&key_of(i), &value_of(i, version)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+}
+
+#[test]
+fn test_subcompactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.target_sst_size = 4096;
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    put_and_flush(&storage, (0..1000).collect(), 1);
+    put_and_flush(&storage, (0..1000).step_by(2).collect(), 2);
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(200), &key_of(700)).unwrap();
+    storage.force_flush().unwrap();
+
+    let boundaries = {
+        let state = storage.inner.state.read().clone();
+        let task = CompactionTask::ForceFullCompaction {
+            l0_sstables: state.l0_sstables.clone(),
+            l1_sstables: state.levels[0].1.clone(),
+        };
+        storage.inner.subcompaction_boundaries(&state, &task)
+    };
+    assert_eq!(boundaries.len(), 3);
+    assert!(boundaries.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // the range tombstone is above the watermark, so it is split across the subcompactions
+    storage.force_full_compaction().unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        let l1 = &state.levels[0].1;
+        assert!(l1.len() > 1);
+        for pair in l1.windows(2) {
+            let (left, right) = (&state.sstables[&pair[0]], &state.sstables[&pair[1]]);
+            // the key range of an SST ends at the exclusive end of its range tombstones
+            assert!(left.last_key().key_ref() <= right.first_key().key_ref());
+        }
+    }
+    let expected = |i: usize| match i {
+        200..700 => None,
+        i if i % 2 == 0 => Some(value_of(i, 2)),
+        i => Some(value_of(i, 1)),
+    };
+    for i in 0..1000 {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), expected(i));
+        assert_eq!(
+            snapshot.get(&key_of(i)).unwrap(),
+            Some(value_of(i, if i % 2 == 0 { 2 } else { 1 }))
+        );
+    }
+
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for i in (0..200).chain(700..1000) {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(Some(Bytes::copy_from_slice(iter.value())), expected(i));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert!(storage.inner.state.read().levels[0].1.iter().all(|id| {
+        storage.inner.state.read().sstables[id]
+            .range_tombstones()
+            .is_empty()
+    }));
+}
@@ -0,0 +1,192 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, TieredCompactionController, TieredCompactionOptions},
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+use super::harness::compaction_bench;
+
+/// Builds a state of tiers, newest first, from the sizes of their SSTs.
+fn state_of(tiers: &[&[u64]]) -> LsmStorageState {
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: Vec::new(),
+        sstables: Default::default(),
+        value_logs: Default::default(),
+        compaction_cursors: Default::default(),
+    };
+    let mut next_sst_id = 1;
+    for sizes in tiers {
+        let mut sst_ids = Vec::new();
+        for size in *sizes {
+            let sst = SsTable::create_meta_only(
+                next_sst_id,
+                *size,
+                KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"a")),
+                KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"z")),
+            );
+            state.sstables.insert(next_sst_id, Arc::new(sst));
+            sst_ids.push(next_sst_id);
+            next_sst_id += 1;
+        }
+        state.levels.push((sst_ids[0], sst_ids));
+    }
+    state
+}
+
+fn tier_ids(state: &LsmStorageState, tiers: &[(usize, Vec<usize>)]) -> Vec<usize> {
+    tiers
+        .iter()
+        .map(|(tier_id, _)| {
+            state
+                .levels
+                .iter()
+                .position(|(id, _)| id == tier_id)
+                .unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn test_space_amplification_in_bytes() {
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width: None,
+    });
+    // the upper tiers have fewer SSTs than the bottom tier, but hold 4x the bytes
+    let state = state_of(&[&[100], &[100], &[10, 10, 10, 10, 10]]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.bottom_tier_included);
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1, 2]);
+
+    // the upper tiers have more SSTs than the bottom tier, but hold fewer bytes
+    let state = state_of(&[&[10, 10], &[10, 10, 10], &[1000]]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(!task.bottom_tier_included);
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1]);
+}
+
+#[test]
+fn test_size_ratio_in_bytes() {
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 4,
+        max_size_amplification_percent: 1000,
+        size_ratio: 50,
+        min_merge_width: 2,
+        max_merge_width: None,
+    });
+    // tier 2 has fewer SSTs than the tiers above it, but more than 1.5x their bytes
+    let state = state_of(&[&[10, 10], &[25], &[100], &[1000]]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(!task.bottom_tier_included);
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1]);
+}
+
+#[test]
+fn test_max_merge_bytes() {
+    let options = TieredCompactionOptions {
+        num_tiers: 4,
+        max_size_amplification_percent: 1000,
+        size_ratio: 1000,
+        min_merge_width: 2,
+        max_merge_width: None,
+    };
+    let controller =
+        TieredCompactionController::new(options.clone()).with_max_merge_bytes(Some(250));
+    let state = state_of(&[&[100], &[100], &[100], &[1000]]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(!task.bottom_tier_included);
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1]);
+
+    // at least two tiers are merged
+    let controller =
+        TieredCompactionController::new(options.clone()).with_max_merge_bytes(Some(10));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1]);
+
+    // the bottom tier is included if all tiers fit
+    let controller = TieredCompactionController::new(options.clone());
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.bottom_tier_included);
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1, 2, 3]);
+
+    // the number of tiers is still capped by `max_merge_width`
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        max_merge_width: Some(3),
+        ..options
+    })
+    .with_max_merge_bytes(Some(1000));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(!task.bottom_tier_included);
+    assert_eq!(tier_ids(&state, &task.tiers), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_integration_in_bytes() {
+    let dir = tempdir().unwrap();
+    let options = TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width: None,
+    };
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(options.clone())),
+    )
+    .unwrap();
+    compaction_bench(storage.clone());
+
+    // the tiers left are stable under the triggers that compare their sizes in bytes
+    let state = storage.inner.state.read().clone();
+    assert!(state.levels.len() <= options.num_tiers);
+    let tier_sizes = state
+        .levels
+        .iter()
+        .map(|(_, sst_ids)| {
+            sst_ids
+                .iter()
+                .map(|id| state.sstables[id].table_size())
+                .sum::<u64>()
+        })
+        .collect::<Vec<_>>();
+    let (bottom_tier_size, upper_tier_sizes) = tier_sizes.split_last().unwrap();
+    assert!(
+        upper_tier_sizes.iter().sum::<u64>() * 100
+            < *bottom_tier_size * options.max_size_amplification_percent as u64,
+        "violation of space amp: {:?}",
+        tier_sizes
+    );
+    assert!(
+        TieredCompactionController::new(options)
+            .generate_compaction_task(&state)
+            .is_none()
+    );
+}
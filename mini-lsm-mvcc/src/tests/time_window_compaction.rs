@@ -0,0 +1,186 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        BigEndianTimePrefix, CompactionOptions, TimeWindowCompactionController,
+        TimeWindowCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+fn key_of(time: u64, suffix: &str) -> Bytes {
+    let mut key = BytesMut::new();
+    key.put_u64(time);
+    key.put_slice(suffix.as_bytes());
+    key.freeze()
+}
+
+fn add_sst(state: &mut LsmStorageState, id: usize, size: u64, first_time: u64, last_time: u64) {
+    let sst = SsTable::create_meta_only(
+        id,
+        size,
+        KeyBytes::for_testing_from_bytes_no_ts(key_of(first_time, "")),
+        KeyBytes::for_testing_from_bytes_no_ts(key_of(last_time, "")),
+    );
+    state.sstables.insert(id, Arc::new(sst));
+    state.levels.insert(0, (id, vec![id]));
+}
+
+fn time_window_options(window_size: u64, min_threshold: usize) -> TimeWindowCompactionOptions {
+    TimeWindowCompactionOptions {
+        time_extractor: Arc::new(BigEndianTimePrefix),
+        window_size,
+        min_threshold,
+        max_threshold: 4,
+    }
+}
+
+#[test]
+fn test_time_window_tasks() {
+    let controller = TimeWindowCompactionController::new(time_window_options(100, 2));
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: Vec::new(),
+        sstables: Default::default(),
+        value_logs: Default::default(),
+        compaction_cursors: Default::default(),
+    };
+    add_sst(&mut state, 1, 10, 0, 49);
+    add_sst(&mut state, 2, 10, 50, 99);
+    add_sst(&mut state, 3, 10, 100, 119);
+    add_sst(&mut state, 4, 10, 120, 139);
+    add_sst(&mut state, 5, 100, 130, 159);
+
+    // the SSTs of similar sizes in the newest window are merged first
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.window, 1);
+    assert_eq!(task.sst_ids, vec![3, 4]);
+    assert!(!task.covers_key_range);
+    add_sst(&mut state, 6, 10, 160, 179);
+    let mut state = state;
+    state.sstables.insert(
+        7,
+        Arc::new(SsTable::create_meta_only(
+            7,
+            20,
+            KeyBytes::for_testing_from_bytes_no_ts(key_of(100, "")),
+            KeyBytes::for_testing_from_bytes_no_ts(key_of(139, "")),
+        )),
+    );
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[7]);
+    assert_eq!(removed, vec![3, 4]);
+    let ids = state.levels.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![6, 5, 7, 2, 1]);
+
+    // a closed window is merged into one SST, which drops the deletions as no other SST overlaps it
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.window, 0);
+    assert_eq!(task.sst_ids, vec![2, 1]);
+    assert!(task.covers_key_range);
+    let mut state = state;
+    state.sstables.insert(
+        8,
+        Arc::new(SsTable::create_meta_only(
+            8,
+            20,
+            KeyBytes::for_testing_from_bytes_no_ts(key_of(0, "")),
+            KeyBytes::for_testing_from_bytes_no_ts(key_of(99, "")),
+        )),
+    );
+    let (state, _) = controller.apply_compaction_result(&state, &task, &[8]);
+    let ids = state.levels.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![6, 5, 7, 8]);
+
+    // a single SST in a closed window, and SSTs of different sizes in the newest window, are not compacted
+    assert!(controller.generate_compaction_task(&state).is_none());
+}
+
+fn compact_until(storage: &MiniLsm, num_ssts: usize) {
+    for _ in 0..100 {
+        storage.inner.trigger_compaction().unwrap();
+        if storage.inner.state.read().levels.len() == num_ssts {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("compaction does not converge");
+}
+
+#[test]
+fn test_integration() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::TimeWindow(
+        time_window_options(10, 2),
+    ));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // 8 flushes of 5 seconds each, which cover 4 windows of 10 seconds
+    for flush in 0..8 {
+        for time in flush * 5..(flush + 1) * 5 {
+            for i in 0..10 {
+                storage
+                    .put(
+                        &key_of(time, &format!("{:02}", i)),
+                        format!("value{}", time).as_bytes(),
+                    )
+                    .unwrap();
+            }
+        }
+        if flush == 3 {
+            storage.delete(&key_of(0, "00")).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    compact_until(&storage, 4);
+
+    let controller = TimeWindowCompactionController::new(time_window_options(10, 2));
+    let check = |storage: &MiniLsm| {
+        let state = storage.inner.state.read().clone();
+        let mut windows = Vec::new();
+        for (_, sst_ids) in &state.levels {
+            let sst = &state.sstables[&sst_ids[0]];
+            let window = controller.window_of(sst.first_key().key_ref());
+            assert_eq!(window, controller.window_of(sst.last_key().key_ref()));
+            windows.push(window);
+        }
+        windows.sort();
+        assert_eq!(windows, vec![0, 1, 2, 3]);
+        assert_eq!(storage.get(&key_of(0, "00")).unwrap(), None);
+        for time in 0..40 {
+            let suffix = if time == 0 { "01" } else { "00" };
+            assert_eq!(
+                &storage.get(&key_of(time, suffix)).unwrap().unwrap()[..],
+                format!("value{}", time).as_bytes()
+            );
+        }
+    };
+    check(&storage);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels.len(), 4);
+    check(&storage);
+}
@@ -0,0 +1,223 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, SimpleLeveledCompactionOptions, TombstoneCompactionOptions,
+        TombstoneTracker,
+    },
+    iterators::{EntryType, StorageIterator},
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{FileObject, SsTable, SsTableBuilder},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+/// Opens a storage whose simple leveled compaction only runs range and tombstone tasks.
+fn open_storage(
+    dir: &tempfile::TempDir,
+    tombstone_compaction: TombstoneCompactionOptions,
+) -> Arc<MiniLsm> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 0,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 3,
+        },
+    ));
+    options.tombstone_compaction = Some(tombstone_compaction);
+    let storage = MiniLsm::open(dir, options).unwrap();
+    // the keys start at the bottom level
+    for i in 0..100 {
+        storage.put(&key_of(i), b"v1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    storage
+}
+
+/// The number of SSTs in L0 and each level, and the number of deletions in all SSTs.
+fn sst_stats(storage: &MiniLsm) -> (Vec<usize>, u64) {
+    let state = storage.inner.state.read();
+    let num_ssts = std::iter::once(state.l0_sstables.len())
+        .chain(state.levels.iter().map(|(_, ssts)| ssts.len()))
+        .collect();
+    let num_deletes = state.sstables.values().map(|sst| sst.num_deletes()).sum();
+    (num_ssts, num_deletes)
+}
+
+fn scan_all(storage: &MiniLsm) -> Vec<Bytes> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_num_entries() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        let entry_type = if i % 4 == 0 {
+            EntryType::Delete
+        } else {
+            EntryType::Put
+        };
+        builder.add_with_type(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(i)),
+            b"value",
+            entry_type,
+        );
+    }
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert_eq!(sst.num_entries(), 100);
+    assert_eq!(sst.tombstone_ratio(), Some(0.25));
+    let sst = SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.num_entries(), 100);
+    assert_eq!(sst.tombstone_ratio(), Some(0.25));
+}
+
+#[test]
+fn test_tombstone_ratio_trigger() {
+    let dir = tempdir().unwrap();
+    let storage = open_storage(
+        &dir,
+        TombstoneCompactionOptions {
+            tombstone_ratio_threshold: 0.5,
+            min_entries: 10,
+            scan_skipped_deletes_trigger: None,
+        },
+    );
+    for i in 0..40 {
+        storage.put(&key_of(i), b"v2").unwrap();
+    }
+    storage.force_flush().unwrap();
+    // 40 puts and no deletions
+    storage.inner.trigger_compaction().unwrap();
+    assert_eq!(sst_stats(&storage), (vec![1, 0, 0, 1], 0));
+
+    for i in 40..100 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    for i in 0..40 {
+        storage.put(&key_of(i), b"v3").unwrap();
+    }
+    storage.force_flush().unwrap();
+    // the SST of 60 deletions and 40 puts is compacted level by level, and its deletions are dropped at the
+    // bottom level
+    for _ in 0..3 {
+        storage.inner.trigger_compaction().unwrap();
+    }
+    assert_eq!(sst_stats(&storage), (vec![0, 0, 0, 1], 0));
+    assert_eq!(storage.get(&key_of(10)).unwrap(), Some(Bytes::from("v3")));
+    assert_eq!(storage.get(&key_of(50)).unwrap(), None);
+}
+
+#[test]
+fn test_scan_skipped_deletes_trigger() {
+    let dir = tempdir().unwrap();
+    let storage = open_storage(
+        &dir,
+        TombstoneCompactionOptions {
+            tombstone_ratio_threshold: 1.0,
+            min_entries: 10,
+            scan_skipped_deletes_trigger: Some(20),
+        },
+    );
+    for i in 10..20 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in 50..80 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.inner.trigger_compaction().unwrap();
+    assert_eq!(sst_stats(&storage), (vec![2, 0, 0, 1], 40));
+
+    // a scan that skips 10 deletions in a row marks nothing
+    let mut iter = storage
+        .scan(Bound::Unbounded, Bound::Excluded(&key_of(40)))
+        .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    storage.inner.trigger_compaction().unwrap();
+    assert_eq!(sst_stats(&storage), (vec![2, 0, 0, 1], 40));
+
+    // a scan that skips 30 deletions in a row marks the SSTs under them, whose outputs are marked by the next
+    // scans until the deletions are dropped at the bottom level
+    let expected = (0..10)
+        .chain(20..50)
+        .chain(80..100)
+        .map(|i| Bytes::from(key_of(i)))
+        .collect::<Vec<_>>();
+    for _ in 0..3 {
+        assert_eq!(scan_all(&storage), expected);
+        storage.inner.trigger_compaction().unwrap();
+    }
+    assert_eq!(sst_stats(&storage), (vec![0, 0, 0, 1], 0));
+    assert_eq!(scan_all(&storage), expected);
+}
+
+#[test]
+fn test_skipped_ranges_are_merged() {
+    let tracker = TombstoneTracker::new(1);
+    for _ in 0..100 {
+        tracker.record_skipped_range(b"key_010", b"key_020");
+    }
+    assert_eq!(tracker.num_skipped_ranges(), 1);
+    tracker.record_skipped_range(b"key_030", b"key_040");
+    assert_eq!(tracker.num_skipped_ranges(), 2);
+    // bridges the two ranges
+    tracker.record_skipped_range(b"key_015", b"key_035");
+    assert_eq!(tracker.num_skipped_ranges(), 1);
+
+    // the disjoint ranges beyond the limit are dropped, but the overlapping ones still merge
+    for idx in 0..2000 {
+        let key = format!("other_{:04}", idx);
+        tracker.record_skipped_range(key.as_bytes(), key.as_bytes());
+    }
+    assert_eq!(tracker.num_skipped_ranges(), 1024);
+    tracker.record_skipped_range(b"key_000", b"key_050");
+    assert_eq!(tracker.num_skipped_ranges(), 1024);
+}
+
+#[test]
+fn test_no_tracker_without_tombstone_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.tombstone_compaction = Some(TombstoneCompactionOptions {
+        tombstone_ratio_threshold: 0.5,
+        min_entries: 1,
+        scan_skipped_deletes_trigger: Some(1),
+    });
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.inner.tombstone_tracker.is_none());
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+/// Writes the keys in `[begin, end)` to a new L0 SST, and returns its id.
+fn flush_keys(storage: &MiniLsm, begin: usize, end: usize, value: &[u8]) -> usize {
+    for i in begin..end {
+        storage.put(&key_of(i), value).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.inner.state.read().l0_sstables[0]
+}
+
+fn compact_until_done(storage: &MiniLsm) {
+    for _ in 0..5 {
+        storage.inner.trigger_compaction().unwrap();
+    }
+}
+
+fn levels(storage: &MiniLsm) -> (Vec<usize>, Vec<Vec<usize>>) {
+    let state = storage.inner.state.read();
+    let levels = state.levels.iter().map(|(_, ssts)| ssts.clone()).collect();
+    (state.l0_sstables.clone(), levels)
+}
+
+fn test_trivial_move(compaction_options: CompactionOptions) {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let sst1 = flush_keys(&storage, 0, 50, b"v1");
+    let sst2 = flush_keys(&storage, 50, 100, b"v1");
+    compact_until_done(&storage);
+    // the SSTs of sequential writes do not overlap, so they are moved to the bottom level as they are
+    let expected = (vec![], vec![vec![], vec![], vec![sst1, sst2]]);
+    assert_eq!(levels(&storage), expected);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(levels(&storage), expected);
+    for i in 0..100 {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(Bytes::from("v1")));
+    }
+}
+
+#[test]
+fn test_trivial_move_leveled() {
+    test_trivial_move(CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    }));
+}
+
+#[test]
+fn test_trivial_move_simple() {
+    test_trivial_move(CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    }));
+}
+
+#[test]
+fn test_no_trivial_move_on_overlap() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let sst1 = flush_keys(&storage, 0, 50, b"v1");
+    let sst2 = flush_keys(&storage, 50, 100, b"v1");
+    compact_until_done(&storage);
+    let sst3 = flush_keys(&storage, 0, 10, b"v2");
+    let sst4 = flush_keys(&storage, 20, 30, b"v2");
+    compact_until_done(&storage);
+    // the L0 SSTs overlap `sst1`, so they are rewritten along with it
+    let (l0, levels) = levels(&storage);
+    assert!(l0.is_empty());
+    assert!(levels[2].contains(&sst2));
+    for sst_id in [sst1, sst3, sst4] {
+        assert!(!levels[2].contains(&sst_id));
+    }
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(&key_of(5)).unwrap(), Some(Bytes::from("v2")));
+    assert_eq!(storage.get(&key_of(40)).unwrap(), Some(Bytes::from("v1")));
+    assert_eq!(storage.get(&key_of(25)).unwrap(), Some(Bytes::from("v2")));
+    assert_eq!(storage.get(&key_of(75)).unwrap(), Some(Bytes::from("v1")));
+}
@@ -0,0 +1,187 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ops::Bound, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{EntryType, StorageIterator},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::SsTableIterator,
+    tests::harness::check_lsm_iter_result_by_key,
+    ttl::ManualClock,
+};
+
+fn options_with_clock(clock: Arc<ManualClock>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.clock = clock;
+    options
+}
+
+#[test]
+fn test_ttl() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let storage = MiniLsm::open(&dir, options_with_clock(clock.clone())).unwrap();
+    storage
+        .put_with_ttl(b"a", b"1", Duration::from_secs(10))
+        .unwrap();
+    storage
+        .put_with_ttl(b"b", b"2", Duration::from_secs(20))
+        .unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.put(b"d", b"4").unwrap();
+    // an expiring version shadows the older ones once it expires
+    storage
+        .put_with_ttl(b"d", b"5", Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("b"), Bytes::from("2")),
+            (Bytes::from("c"), Bytes::from("3")),
+            (Bytes::from("d"), Bytes::from("5")),
+        ],
+    );
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.get(b"d").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("b"), Bytes::from("2")),
+            (Bytes::from("c"), Bytes::from("3")),
+        ],
+    );
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn test_ttl_in_txn() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let storage = MiniLsm::open(&dir, options_with_clock(clock.clone())).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put_with_ttl(b"a", b"2", Duration::from_secs(5));
+    txn.put_with_ttl(b"b", b"3", Duration::from_secs(10));
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from("2")));
+    check_lsm_iter_result_by_key(
+        &mut txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("2")),
+            (Bytes::from("b"), Bytes::from("3")),
+        ],
+    );
+
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(txn.get(b"a").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(Bytes::from("b"), Bytes::from("3"))],
+    );
+    txn.commit().unwrap();
+    // the expiry time is fixed when the txn writes the key, not when it commits
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("3")));
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn test_ttl_recovery() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let mut options = options_with_clock(clock.clone());
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .put_with_ttl(b"a", b"1", Duration::from_secs(10))
+        .unwrap();
+    storage.force_flush().unwrap();
+    storage
+        .put_with_ttl(b"b", b"2", Duration::from_secs(20))
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_ttl_compaction() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let storage = MiniLsm::open(&dir, options_with_clock(clock.clone())).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    storage.force_flush().unwrap();
+    storage
+        .put_with_ttl(b"a", b"1", Duration::from_secs(10))
+        .unwrap();
+    storage
+        .put_with_ttl(b"b", b"2", Duration::from_secs(20))
+        .unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.force_flush().unwrap();
+
+    clock.advance(Duration::from_secs(10));
+    storage.force_full_compaction().unwrap();
+    let mut entries = Vec::new();
+    {
+        let state = storage.inner.state.read();
+        for sst_id in &state.levels[0].1 {
+            let sst = state.sstables[sst_id].clone();
+            let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+            while iter.is_valid() {
+                entries.push((
+                    Bytes::copy_from_slice(iter.key().key_ref()),
+                    iter.entry_type(),
+                ));
+                iter.next().unwrap();
+            }
+        }
+    }
+    // the expired version of `a` is dropped along with the version it shadows
+    assert_eq!(
+        entries,
+        vec![
+            (Bytes::from("b"), EntryType::ExpiringPut),
+            (Bytes::from("c"), EntryType::Put),
+        ]
+    );
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+
+    clock.advance(Duration::from_secs(10));
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(Bytes::from("c"), Bytes::from("3"))],
+    );
+}
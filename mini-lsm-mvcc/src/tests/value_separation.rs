@@ -0,0 +1,175 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::path::Path;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{EntryType, StorageIterator},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::SsTableIterator,
+    value_log::ValueSeparationOptions,
+};
+
+fn value_separation_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_separation = Some(ValueSeparationOptions {
+        min_value_size: 1024,
+        gc_garbage_ratio: 0.5,
+    });
+    options
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    // odd keys get small values that stay in the SST
+    let len = if idx.is_multiple_of(2) { 4096 } else { 16 };
+    format!("{:05}_{:05}", idx, round)
+        .repeat(len / 11 + 1)
+        .into_bytes()
+}
+
+fn num_value_logs(path: &Path) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "vlog")
+        })
+        .count()
+}
+
+fn check_values(storage: &MiniLsm, num_keys: usize, round_of: impl Fn(usize) -> usize) {
+    for idx in 0..num_keys {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap().unwrap(),
+            value_of(idx, round_of(idx))
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..num_keys {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, round_of(idx)));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_value_separation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_separation_options()).unwrap();
+    for idx in 0..=100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.delete(&key_of(100)).unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(num_value_logs(dir.path()), 1);
+
+    // large values are stored as pointers, small values and tombstones inline
+    {
+        let state = storage.inner.state.read();
+        let sst = state.sstables[&state.l0_sstables[0]].clone();
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        while iter.is_valid() {
+            let idx = std::str::from_utf8(&iter.key().key_ref()[4..])
+                .unwrap()
+                .parse::<usize>()
+                .unwrap();
+            match iter.entry_type() {
+                EntryType::Delete => assert_eq!(idx, 100),
+                EntryType::ValuePointer => {
+                    assert!(idx.is_multiple_of(2));
+                    assert!(iter.value().len() < 16);
+                }
+                entry_type => {
+                    assert_eq!(entry_type, EntryType::Put);
+                    assert!(!idx.is_multiple_of(2));
+                }
+            }
+            iter.next().unwrap();
+        }
+    }
+
+    let txn = storage.new_txn().unwrap();
+    assert_eq!(txn.get(&key_of(2)).unwrap().unwrap(), value_of(2, 0));
+    assert_eq!(txn.get(&key_of(100)).unwrap(), None);
+    check_values(&storage, 100, |_| 0);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, value_separation_options()).unwrap();
+    check_values(&storage, 100, |_| 0);
+}
+
+#[test]
+fn test_value_log_removed_after_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_separation_options()).unwrap();
+    for round in 0..3 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    assert_eq!(num_value_logs(dir.path()), 3);
+    storage.force_full_compaction().unwrap();
+    // only the value log of the latest round is still referenced
+    assert_eq!(num_value_logs(dir.path()), 1);
+    assert_eq!(storage.inner.state.read().value_logs.len(), 1);
+    check_values(&storage, 100, |_| 2);
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_separation_options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let first_log = *storage.inner.state.read().value_logs.keys().next().unwrap();
+    // overwrite 60% of the large values
+    for idx in 0..60 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.inner.state.read().value_logs.len(), 2);
+    let round_of = |idx| if idx < 60 { 1 } else { 0 };
+    check_values(&storage, 100, round_of);
+
+    storage.force_value_log_gc().unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.value_logs.len(), 2);
+        assert!(!state.value_logs.contains_key(&first_log));
+    }
+    assert_eq!(num_value_logs(dir.path()), 2);
+    check_values(&storage, 100, round_of);
+    // nothing left to collect
+    storage.force_value_log_gc().unwrap();
+    assert_eq!(storage.inner.state.read().value_logs.len(), 2);
+}
@@ -0,0 +1,251 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::EntryType,
+    key::{KeyBytes, KeySlice},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+    wal::{Wal, WalRecoveryMode, WalRecoveryReport},
+};
+
+/// The size of the blocks of a WAL.
+const BLOCK_SIZE: u64 = 32 << 10;
+
+/// Writes `n` batches of one put of `value_len` bytes each, and returns the length of the WAL after each batch.
+fn write_wal(path: &Path, n: usize, value_len: usize) -> Vec<u64> {
+    let wal = Wal::create(path).unwrap();
+    (0..n)
+        .map(|i| {
+            let key = format!("key_{}", i);
+            wal.put(
+                KeySlice::for_testing_from_slice_with_ts(key.as_bytes(), i as u64 + 1),
+                &vec![i as u8; value_len],
+            )
+            .unwrap();
+            wal.sync().unwrap();
+            std::fs::metadata(path).unwrap().len()
+        })
+        .collect()
+}
+
+fn recover(path: &Path, mode: WalRecoveryMode) -> anyhow::Result<(Vec<Bytes>, WalRecoveryReport)> {
+    let skiplist = SkipMap::<KeyBytes, (EntryType, Bytes)>::new();
+    let (_, report) = Wal::recover_with_mode(path, &skiplist, &SkipMap::new(), mode)?;
+    let keys = skiplist
+        .iter()
+        .map(|entry| Bytes::copy_from_slice(entry.key().key_ref()))
+        .collect();
+    Ok((keys, report))
+}
+
+fn keys(ids: &[usize]) -> Vec<Bytes> {
+    ids.iter()
+        .map(|i| Bytes::from(format!("key_{}", i)))
+        .collect()
+}
+
+fn flip_byte(path: &Path, offset: u64) {
+    let mut data = std::fs::read(path).unwrap();
+    data[offset as usize] ^= 0xff;
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn test_wal_recovery_torn_tail() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00000.wal");
+    let lens = write_wal(&path, 3, 8);
+    // a crash in the middle of the last batch
+    let torn_len = lens[2] - 3;
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(torn_len)
+        .unwrap();
+
+    assert!(recover(&path, WalRecoveryMode::AbsoluteConsistency).is_err());
+    let (recovered, report) =
+        recover(&path, WalRecoveryMode::TolerateCorruptedTailRecords).unwrap();
+    assert_eq!(recovered, keys(&[0, 1]));
+    assert_eq!(report.batches, 2);
+    assert_eq!(report.records, 2);
+    assert_eq!(report.truncated_bytes, torn_len - lens[1]);
+    assert!(report.corruption.is_some());
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), lens[1]);
+
+    // the batches written after the recovery follow the recovered ones
+    let skiplist = SkipMap::new();
+    let wal = Wal::recover(&path, &skiplist, &SkipMap::new()).unwrap();
+    wal.put(
+        KeySlice::for_testing_from_slice_with_ts(b"key_3", 4),
+        b"value_3",
+    )
+    .unwrap();
+    wal.sync().unwrap();
+    drop(wal);
+    let (recovered, report) = recover(&path, WalRecoveryMode::AbsoluteConsistency).unwrap();
+    assert_eq!(recovered, keys(&[0, 1, 3]));
+    assert_eq!(report.batches, 3);
+    assert_eq!(report.truncated_bytes, 0);
+    assert_eq!(report.corruption, None);
+}
+
+#[test]
+fn test_wal_recovery_corrupted_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00000.wal");
+    let lens = write_wal(&path, 8, 10_000);
+    assert!(lens[7] > 2 * BLOCK_SIZE);
+    // corrupt the value of the second batch, in the first block
+    flip_byte(&path, lens[1] - 6);
+
+    assert!(recover(&path, WalRecoveryMode::AbsoluteConsistency).is_err());
+    assert!(recover(&path, WalRecoveryMode::TolerateCorruptedTailRecords).is_err());
+
+    // the rest of the first block is skipped, along with the batch that starts in it and ends in the next block
+    let intact = (0..8)
+        .filter(|&i| i == 0 || lens[i - 1] >= BLOCK_SIZE)
+        .collect::<Vec<_>>();
+    assert!(intact.len() < 7);
+    let (recovered, report) = recover(&path, WalRecoveryMode::SkipAnyCorruptedRecords).unwrap();
+    assert_eq!(recovered, keys(&intact));
+    assert_eq!(report.batches, intact.len() as u64);
+    assert_eq!(report.skipped_batches, 1);
+    assert_eq!(report.truncated_bytes, 0);
+    assert!(report.corruption.is_some());
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), lens[7]);
+
+    let (recovered, report) = recover(&path, WalRecoveryMode::PointInTimeRecovery).unwrap();
+    assert_eq!(recovered, keys(&[0]));
+    assert_eq!(report.batches, 1);
+    assert_eq!(report.skipped_batches, 0);
+    assert_eq!(report.truncated_bytes, lens[7] - lens[0]);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), lens[0]);
+}
+
+#[test]
+fn test_wal_batch_spans_blocks() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00000.wal");
+    let lens = write_wal(&path, 3, 100 << 10);
+    assert!(lens[0] > 3 * BLOCK_SIZE);
+    let (recovered, report) = recover(&path, WalRecoveryMode::AbsoluteConsistency).unwrap();
+    assert_eq!(recovered, keys(&[0, 1, 2]));
+    assert_eq!(report.batches, 3);
+
+    // a crash after the first blocks of the last batch are written
+    let torn_len = lens[1] + 2 * BLOCK_SIZE;
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(torn_len)
+        .unwrap();
+    let skiplist = SkipMap::new();
+    let wal = Wal::recover(&path, &skiplist, &SkipMap::new()).unwrap();
+    assert_eq!(skiplist.len(), 2);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), lens[1]);
+    wal.put(
+        KeySlice::for_testing_from_slice_with_ts(b"key_3", 4),
+        &vec![3; 100 << 10],
+    )
+    .unwrap();
+    wal.sync().unwrap();
+    drop(wal);
+
+    let skiplist = SkipMap::new();
+    Wal::recover(&path, &skiplist, &SkipMap::new()).unwrap();
+    let value = skiplist
+        .get(&KeyBytes::from_bytes_with_ts(
+            Bytes::from_static(b"key_3"),
+            4,
+        ))
+        .unwrap();
+    assert_eq!(value.value().1, vec![3; 100 << 10]);
+}
+
+fn options(wal_recovery_mode: WalRecoveryMode) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.wal_recovery_mode = wal_recovery_mode;
+    options
+}
+
+/// Writes `a` to a memtable that is frozen, and `b` to the next one, and returns the WAL of the first one.
+fn write_two_wals(dir: &Path) -> PathBuf {
+    let storage = MiniLsm::open(dir, options(WalRecoveryMode::default())).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let first_wal = storage
+        .inner
+        .path_of_wal(storage.inner.state.read().memtable.id());
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.close().unwrap();
+    first_wal
+}
+
+#[test]
+fn test_recovery_summary() {
+    let dir = tempdir().unwrap();
+    let first_wal = write_two_wals(dir.path());
+    let first_wal_len = std::fs::metadata(&first_wal).unwrap().len();
+    // corrupt the checksum of the only batch in the first WAL
+    flip_byte(&first_wal, first_wal_len - 1);
+
+    let storage = MiniLsm::open(&dir, options(WalRecoveryMode::default())).unwrap();
+    let summary = storage.recovery_summary().clone();
+    assert_eq!(summary.ssts_opened, 0);
+    let reports = summary.wals.values().collect::<Vec<_>>();
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].batches, 0);
+    assert_eq!(reports[0].truncated_bytes, first_wal_len - 8);
+    assert!(reports[0].corruption.is_some());
+    assert_eq!(reports[1].batches, 1);
+    assert_eq!(reports[1].corruption, None);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from_static(b"2")));
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_recovery_summary_point_in_time() {
+    let dir = tempdir().unwrap();
+    let first_wal = write_two_wals(dir.path());
+    let first_wal_len = std::fs::metadata(&first_wal).unwrap().len();
+    flip_byte(&first_wal, first_wal_len - 1);
+
+    // the WALs after the corruption are dropped, even though they are intact
+    let storage = Arc::new(
+        LsmStorageInner::open(&dir, options(WalRecoveryMode::PointInTimeRecovery)).unwrap(),
+    );
+    let reports = storage.recovery_summary.wals.values().collect::<Vec<_>>();
+    assert_eq!(reports[0].batches, 0);
+    assert!(reports[0].corruption.is_some());
+    assert_eq!(reports[1].batches, 0);
+    assert!(reports[1].truncated_bytes > 0);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
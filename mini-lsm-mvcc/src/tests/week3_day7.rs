@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use bytes::Bytes;
 use tempfile::tempdir;
 
 use crate::{
-    compact::CompactionOptions,
-    lsm_storage::{CompactionFilter, LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    compact::{CompactionOptions, PrefixCompactionFilter},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
 };
 
 use super::harness::{check_iter_result_by_key, construct_merge_iterator_over_storage};
@@ -50,7 +52,9 @@ fn test_task3_mvcc_compaction() {
         ])
         .unwrap();
     storage.force_flush().unwrap();
-    storage.add_compaction_filter(CompactionFilter::Prefix(Bytes::from("table2_")));
+    storage.add_compaction_filter(Arc::new(PrefixCompactionFilter::new(Bytes::from(
+        "table2_",
+    ))));
     storage.force_full_compaction().unwrap();
 
     let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
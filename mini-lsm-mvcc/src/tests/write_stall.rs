@@ -0,0 +1,144 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+    write_stall::{WriteStallCause, WriteStallCondition, WriteStallOptions},
+};
+
+fn write_stall_options() -> WriteStallOptions {
+    WriteStallOptions {
+        level0_slowdown_writes_trigger: 2,
+        level0_stop_writes_trigger: 3,
+        imm_memtables_slowdown_writes_trigger: 2,
+        imm_memtables_stop_writes_trigger: 3,
+        slowdown_delay: Duration::from_millis(1),
+    }
+}
+
+fn open_with_write_stall(dir: &std::path::Path) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.num_memtable_limit = 1;
+    options.write_stall = Some(write_stall_options());
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+#[test]
+fn test_invalid_write_stall_options() {
+    let dir = tempdir().unwrap();
+    let open = |num_memtable_limit, write_stall| {
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.num_memtable_limit = num_memtable_limit;
+        options.write_stall = Some(write_stall);
+        LsmStorageInner::open(dir.path(), options).map(|_| ())
+    };
+    assert!(open(1, write_stall_options()).is_ok());
+    // the immutable memtables of a regular flush would stall the writes
+    assert!(open(2, write_stall_options()).is_err());
+    // a trigger of 0 stalls every write
+    let options = WriteStallOptions {
+        level0_slowdown_writes_trigger: 0,
+        ..write_stall_options()
+    };
+    assert!(open(1, options).is_err());
+    // the stop trigger is below the slowdown trigger
+    let options = WriteStallOptions {
+        imm_memtables_stop_writes_trigger: 1,
+        ..write_stall_options()
+    };
+    assert!(open(1, options).is_err());
+}
+
+#[test]
+fn test_imm_memtables_stall() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_write_stall(dir.path());
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    assert_eq!(storage.write_stall_condition(), WriteStallCondition::Normal);
+    assert_eq!(storage.write_stall_stats(), Default::default());
+
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    assert_eq!(
+        storage.write_stall_condition(),
+        WriteStallCondition::Delayed(WriteStallCause::ImmMemtables)
+    );
+    storage.put(b"b", b"2").unwrap();
+    let stats = storage.write_stall_stats();
+    assert_eq!(stats.delayed_writes, 1);
+    assert_eq!(stats.delayed_duration, Duration::from_millis(1));
+
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    assert_eq!(
+        storage.write_stall_condition(),
+        WriteStallCondition::Stopped(WriteStallCause::ImmMemtables)
+    );
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"c", b"3").unwrap())
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    assert_eq!(storage.get(b"c").unwrap(), None);
+
+    // the flush brings the memtables below the hard threshold
+    storage.force_flush_next_imm_memtable().unwrap();
+    writer.join().unwrap();
+    assert_eq!(&storage.get(b"c").unwrap().unwrap()[..], b"3");
+    let stats = storage.write_stall_stats();
+    assert_eq!(stats.stopped_writes, 1);
+    assert!(stats.stopped_duration >= Duration::from_millis(100));
+    assert_eq!(stats.delayed_writes, 1);
+}
+
+#[test]
+fn test_l0_files_stall() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_write_stall(dir.path());
+    for i in 0..3 {
+        storage.put(format!("{}", i).as_bytes(), b"v").unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+        let expected = match i {
+            0 => WriteStallCondition::Normal,
+            1 => WriteStallCondition::Delayed(WriteStallCause::L0Files),
+            _ => WriteStallCondition::Stopped(WriteStallCause::L0Files),
+        };
+        assert_eq!(storage.write_stall_condition(), expected);
+    }
+
+    // the memtables are reported when both reach the hard threshold
+    for _ in 0..3 {
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+    }
+    assert_eq!(
+        storage.write_stall_condition(),
+        WriteStallCondition::Stopped(WriteStallCause::ImmMemtables)
+    );
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-key TTL. `put_with_ttl` writes an `EntryType::ExpiringPut` entry, whose value is prefixed by its expiry
+//! time (u64, milliseconds since the UNIX epoch). Once the expiry time has passed, the entry reads as a deletion,
+//! and compaction replaces it with one.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut};
+
+use crate::iterators::EntryType;
+
+/// The source of the current time for expiry.
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the UNIX epoch.
+    fn now(&self) -> u64;
+}
+
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Clock({})", self.now())
+    }
+}
+
+/// The system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+/// A clock that only moves when told to, for deterministic expiry.
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// The expiry time of a value written at `now` with `ttl`.
+pub(crate) fn expiry_of(now: u64, ttl: Duration) -> u64 {
+    now.saturating_add(ttl.as_millis() as u64)
+}
+
+/// Encode the value of an `EntryType::ExpiringPut` entry.
+pub(crate) fn encode_expiring_value(expiry: u64, value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(std::mem::size_of::<u64>() + value.len());
+    buf.put_u64(expiry);
+    buf.put_slice(value);
+    buf
+}
+
+/// Decode the value of an `EntryType::ExpiringPut` entry into its expiry time and the user value.
+pub(crate) fn decode_expiring_value(mut value: &[u8]) -> (u64, &[u8]) {
+    let expiry = value.get_u64();
+    (expiry, value)
+}
+
+/// Whether an entry is an `EntryType::ExpiringPut` whose expiry time has passed at `now`.
+pub(crate) fn is_expired(entry_type: EntryType, value: &[u8], now: u64) -> bool {
+    entry_type == EntryType::ExpiringPut && decode_expiring_value(value).0 <= now
+}
+
+/// The user value of an entry, without the expiry time of an `EntryType::ExpiringPut`.
+pub(crate) fn user_value(entry_type: EntryType, value: &[u8]) -> &[u8] {
+    match entry_type {
+        EntryType::ExpiringPut => decode_expiring_value(value).1,
+        _ => value,
+    }
+}
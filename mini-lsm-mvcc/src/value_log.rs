@@ -0,0 +1,380 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key-value separation (WiscKey). When an SST is built, values above a size threshold are appended to a value
+//! log file instead, and the SST only stores a `ValuePointer` to them. Compaction copies the pointers, not the
+//! values. A value log is removed once no SST references it, and value log GC rewrites the SSTs still
+//! referencing a mostly-dead value log so that its live values move to a new one.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow, bail, ensure};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::iterators::{EntryType, StorageIterator};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::table::{FileObject, SsTable, SsTableIterator};
+use crate::varint::{get_varint, put_varint, varint_len};
+
+#[derive(Debug, Clone)]
+pub struct ValueSeparationOptions {
+    // Values of at least this many bytes are written to a value log instead of the SST
+    pub min_value_size: usize,
+    // Rewrite a value log once this fraction of its bytes is no longer referenced by any SST
+    pub gc_garbage_ratio: f64,
+}
+
+/// Points to a record in a value log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValuePointer {
+    /// Id of the value log.
+    pub log_id: usize,
+    /// Offset of the record in the value log.
+    pub offset: u64,
+    /// Length of the record, including the key and the checksum.
+    pub len: u64,
+}
+
+impl ValuePointer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            varint_len(self.log_id as u64) + varint_len(self.offset) + varint_len(self.len),
+        );
+        put_varint(&mut buf, self.log_id as u64);
+        put_varint(&mut buf, self.offset);
+        put_varint(&mut buf, self.len);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Self {
+        Self {
+            log_id: get_varint(&mut buf) as usize,
+            offset: get_varint(&mut buf),
+            len: get_varint(&mut buf),
+        }
+    }
+}
+
+/// Builds a value log. Each record is `key_len (varint) | key | ts (u64) | value_len (varint) | value |
+/// checksum (u32)`; the key is kept so that a pointer to the wrong record is detected.
+pub struct ValueLogBuilder {
+    id: usize,
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl ValueLogBuilder {
+    pub fn new(id: usize, path: impl AsRef<Path>) -> Self {
+        Self {
+            id,
+            path: path.as_ref().to_path_buf(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Appends a value and returns the pointer to it.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> ValuePointer {
+        let offset = self.data.len();
+        put_varint(&mut self.data, key.key_len() as u64);
+        self.data.put_slice(key.key_ref());
+        self.data.put_u64(key.ts());
+        put_varint(&mut self.data, value.len() as u64);
+        self.data.put_slice(value);
+        let checksum = crc32fast::hash(&self.data[offset..]);
+        self.data.put_u32(checksum);
+        ValuePointer {
+            log_id: self.id,
+            offset: offset as u64,
+            len: (self.data.len() - offset) as u64,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Writes the value log to disk.
+    pub fn build(self, rate_limiter: Option<(&RateLimiter, IoPriority)>) -> Result<ValueLog> {
+        Ok(ValueLog {
+            id: self.id,
+            file: FileObject::create_with_rate_limiter(&self.path, self.data, rate_limiter)?,
+        })
+    }
+}
+
+/// A value log file.
+pub struct ValueLog {
+    id: usize,
+    file: FileObject,
+}
+
+impl ValueLog {
+    pub fn open(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            id,
+            file: FileObject::open(path.as_ref())?,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Reads the value `pointer` points to, which must have been written for `key`.
+    pub fn read(&self, pointer: &ValuePointer, key: KeySlice) -> Result<Bytes> {
+        assert_eq!(pointer.log_id, self.id);
+        let data = self.file.read(pointer.offset, pointer.len)?;
+        let checksum_offset = data.len() - std::mem::size_of::<u32>();
+        if (&data[checksum_offset..]).get_u32() != crc32fast::hash(&data[..checksum_offset]) {
+            bail!("value log record checksum mismatched");
+        }
+        let mut buf = &data[..checksum_offset];
+        let key_len = get_varint(&mut buf) as usize;
+        if &buf[..key_len] != key.key_ref() {
+            bail!("value pointer does not point to a record of its key");
+        }
+        buf.advance(key_len);
+        if buf.get_u64() != key.ts() {
+            bail!("value pointer does not point to a record of its key");
+        }
+        let value_len = get_varint(&mut buf) as usize;
+        let value_offset = checksum_offset - buf.len();
+        Ok(Bytes::from(data).slice(value_offset..value_offset + value_len))
+    }
+}
+
+impl LsmStorageState {
+    /// Reads the value that the `ValuePointer` entry `pointer` of `key` points to.
+    pub(crate) fn read_value_pointer(&self, pointer: &[u8], key: KeySlice) -> Result<Bytes> {
+        let pointer = ValuePointer::decode(pointer);
+        let value_log = self
+            .value_logs
+            .get(&pointer.log_id)
+            .ok_or_else(|| anyhow!("value log {} not found", pointer.log_id))?;
+        value_log.read(&pointer, key)
+    }
+
+    /// Starts tracking the value logs referenced by `sst`.
+    pub(crate) fn open_value_logs(&mut self, sst: &SsTable, path: impl AsRef<Path>) -> Result<()> {
+        for log_id in sst.value_log_refs().keys() {
+            if !self.value_logs.contains_key(log_id) {
+                let value_log = ValueLog::open(
+                    *log_id,
+                    LsmStorageInner::path_of_vlog_static(&path, *log_id),
+                )?;
+                self.value_logs.insert(*log_id, Arc::new(value_log));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops tracking the value logs that no SST references anymore. The caller removes the files after the
+    /// new state is persisted.
+    pub(crate) fn remove_unreferenced_value_logs(&mut self) -> Vec<Arc<ValueLog>> {
+        let live_bytes = self.value_log_live_bytes();
+        let unreferenced = self
+            .value_logs
+            .keys()
+            .filter(|log_id| !live_bytes.contains_key(log_id))
+            .copied()
+            .collect::<Vec<_>>();
+        unreferenced
+            .into_iter()
+            .map(|log_id| self.value_logs.remove(&log_id).unwrap())
+            .collect()
+    }
+
+    /// Bytes of each value log referenced by the SSTs.
+    fn value_log_live_bytes(&self) -> HashMap<usize, u64> {
+        let mut live_bytes = HashMap::new();
+        for sst in self.sstables.values() {
+            for (log_id, bytes) in sst.value_log_refs() {
+                *live_bytes.entry(*log_id).or_default() += bytes;
+            }
+        }
+        live_bytes
+    }
+
+    /// Replaces SST `old` with `new` in L0 or the levels, returns false if `old` is not there.
+    pub(crate) fn replace_sst_id(&mut self, old: usize, new: usize) -> bool {
+        for id in self
+            .l0_sstables
+            .iter_mut()
+            .chain(self.levels.iter_mut().flat_map(|(_, files)| files))
+        {
+            if *id == old {
+                *id = new;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl LsmStorageInner {
+    /// Picks the value log with the most garbage above the GC ratio, and returns it with its garbage ratio and
+    /// the SSTs referencing it.
+    fn generate_value_log_gc_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<(Arc<ValueLog>, f64, Vec<usize>)> {
+        let options = self.options.value_separation.as_ref()?;
+        let live_bytes = snapshot.value_log_live_bytes();
+        let (value_log, garbage_ratio) = snapshot
+            .value_logs
+            .values()
+            .map(|value_log| {
+                let live = live_bytes.get(&value_log.id()).copied().unwrap_or_default();
+                let garbage_ratio = 1.0 - live as f64 / value_log.size() as f64;
+                (value_log, garbage_ratio)
+            })
+            .filter(|(_, garbage_ratio)| *garbage_ratio >= options.gc_garbage_ratio)
+            .max_by(|(_, x), (_, y)| x.total_cmp(y))?;
+        let sst_ids = snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, files)| files))
+            .filter(|id| {
+                snapshot.sstables[*id]
+                    .value_log_refs()
+                    .contains_key(&value_log.id())
+            })
+            .copied()
+            .collect();
+        Some((value_log.clone(), garbage_ratio, sst_ids))
+    }
+
+    /// Rewrites `sst` with the values it stores in `value_log` moved to a new value log.
+    fn rewrite_sst_for_value_log_gc(
+        &self,
+        sst: Arc<SsTable>,
+        value_log: &ValueLog,
+    ) -> Result<Arc<SsTable>> {
+        let mut builder =
+            self.new_sst_builder(IoPriority::Low, sst.newest_write_time().unwrap_or(0));
+        for tombstone in sst.range_tombstones() {
+            builder.add_range_tombstone(tombstone.clone());
+        }
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst)?;
+        while iter.is_valid() {
+            let pointer = match iter.entry_type() {
+                EntryType::ValuePointer => Some(ValuePointer::decode(iter.value())),
+                _ => None,
+            };
+            match pointer {
+                Some(pointer) if pointer.log_id == value_log.id() => {
+                    let value = value_log.read(&pointer, iter.key())?;
+                    builder.add_with_type(iter.key(), &value, EntryType::Put);
+                }
+                _ => builder.add_with_type(iter.key(), iter.value(), iter.entry_type()),
+            }
+            iter.next()?;
+        }
+        let sst_id = self.next_sst_id();
+        Ok(Arc::new(builder.build(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(sst_id),
+        )?))
+    }
+
+    pub(crate) fn trigger_value_log_gc(&self) -> Result<()> {
+        if self.options.value_separation.is_none() {
+            return Ok(());
+        }
+        // Waiting for the compaction lock stalls the compaction tasks, so it is only taken when there is a value
+        // log to collect.
+        if self
+            .generate_value_log_gc_task(&self.state.read())
+            .is_none()
+        {
+            return Ok(());
+        }
+        // The GC rewrites SSTs that compaction tasks may be reading, so it waits for them to finish.
+        let _compaction_lock = self.compaction_lock.write();
+        let (value_log, ssts) = {
+            // the compactions finished in the meantime may have changed the task
+            let state = self.state.read();
+            let Some((value_log, garbage_ratio, sst_ids)) = self.generate_value_log_gc_task(&state)
+            else {
+                return Ok(());
+            };
+            println!(
+                "value log gc: {}.vlog has {:.1}% garbage",
+                value_log.id(),
+                garbage_ratio * 100.0
+            );
+            let ssts = sst_ids
+                .iter()
+                .map(|sst_id| state.sstables[sst_id].clone())
+                .collect::<Vec<_>>();
+            (value_log, ssts)
+        };
+        let mut rewritten = Vec::with_capacity(ssts.len());
+        for sst in ssts {
+            let sst_id = sst.sst_id();
+            rewritten.push((sst_id, self.rewrite_sst_for_value_log_gc(sst, &value_log)?));
+        }
+        let replaced = rewritten
+            .iter()
+            .map(|(old, new)| (*old, new.sst_id()))
+            .collect::<Vec<_>>();
+        let (ssts_to_remove, value_logs_to_remove) = {
+            let state_lock = self.state_lock.lock();
+            let mut snapshot = self.state.read().as_ref().clone();
+            let mut ssts_to_remove = Vec::with_capacity(rewritten.len());
+            for (old, new) in rewritten {
+                ensure!(
+                    snapshot.replace_sst_id(old, new.sst_id()),
+                    "{}.sst was removed during value log gc",
+                    old
+                );
+                let Some(sst) = snapshot.sstables.remove(&old) else {
+                    bail!("{}.sst was removed during value log gc", old);
+                };
+                ssts_to_remove.push(sst);
+                snapshot.open_value_logs(&new, &self.path)?;
+                snapshot.sstables.insert(new.sst_id(), new);
+            }
+            let value_logs_to_remove = snapshot.remove_unreferenced_value_logs();
+            *self.state.write() = Arc::new(snapshot);
+            self.sync_dir()?;
+            self.manifest()
+                .add_record(&state_lock, ManifestRecord::ValueLogGc(replaced.clone()))?;
+            (ssts_to_remove, value_logs_to_remove)
+        };
+        println!(
+            "value log gc finished: rewrote SSTs {:?}, {} value logs removed",
+            replaced,
+            value_logs_to_remove.len()
+        );
+        for sst in ssts_to_remove {
+            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+        }
+        for value_log in value_logs_to_remove {
+            std::fs::remove_file(self.path_of_vlog(value_log.id()))?;
+        }
+        self.sync_dir()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! LEB128 variable-length integers, used to encode lengths in the on-disk formats.
+
+use bytes::{Buf, BufMut};
+
+/// Get the number of bytes `value` takes when encoded as a varint.
+pub fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Encode `value` as a varint.
+pub fn put_varint(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Decode a varint and advance the buffer. Like `Buf::get_u16` and friends, this panics if the buffer ends
+/// in the middle of the varint.
+pub fn get_varint(buf: &mut impl Buf) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
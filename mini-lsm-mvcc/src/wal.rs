@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::fs::{File, OpenOptions};
 use std::hash::Hasher;
 use std::io::{BufWriter, Read, Write};
@@ -23,27 +24,343 @@ use bytes::{Buf, BufMut, Bytes};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
+use crate::iterators::EntryType;
 use crate::key::{KeyBytes, KeySlice};
+use crate::mem_table::insert_range_tombstone;
+use crate::varint::{get_varint, put_varint};
+
+/// Marks the header of a WAL written in a versioned format ("MWAL").
+pub(crate) const WAL_HEADER_MAGIC: u32 = 0x4d57_414c;
+
+/// Revisions of the WAL file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+enum WalFormatVersion {
+    /// The original format without a file header. Each write batch is framed as
+    /// `batch_size (u32) | records | checksum (u32)`, the records have `u16` key and value lengths and no entry
+    /// type, and a deletion is a record of an empty value.
+    V1 = 1,
+    /// A `magic (u32) | version (u32)` file header, varint key and value lengths, and an `EntryType` byte after
+    /// the timestamp of each record. The write batches are split into fragments in blocks of `WAL_BLOCK_SIZE`
+    /// bytes (= LevelDB's log format), so that a batch is not limited by a `u32` size and recovery can skip to
+    /// the next block after a corruption.
+    V2 = 2,
+}
+
+/// The size of the blocks of a V2 WAL, whose first block starts with the file header. A fragment never crosses
+/// the end of a block, and a block with less than `FRAGMENT_HEADER_SIZE` bytes left is padded with zeros.
+const WAL_BLOCK_SIZE: usize = 32 << 10;
+
+/// A fragment is `checksum (u32) | length (u16) | type (u8) | payload`, where the checksum covers the type and
+/// the payload.
+const FRAGMENT_HEADER_SIZE: usize = 7;
+
+/// The part of a write batch held by a fragment of a V2 WAL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum FragmentType {
+    /// The whole batch.
+    Full = 1,
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+impl FragmentType {
+    fn from_u8(fragment_type: u8) -> Option<Self> {
+        match fragment_type {
+            1 => Some(Self::Full),
+            2 => Some(Self::First),
+            3 => Some(Self::Middle),
+            4 => Some(Self::Last),
+            _ => None,
+        }
+    }
+}
+
+/// How a WAL with corrupted or incomplete write batches is recovered (= RocksDB's WAL recovery modes). A crash
+/// during a write leaves an incomplete batch at the end of the WAL.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Drop a corrupted or incomplete batch at the end of a WAL, and fail on a corrupted batch before the end.
+    #[default]
+    TolerateCorruptedTailRecords,
+    /// Fail on any corrupted or incomplete batch.
+    AbsoluteConsistency,
+    /// Recover the batches before the first corrupted one, and drop the rest of the WAL and the WALs after it.
+    PointInTimeRecovery,
+    /// Skip the corrupted batches, and drop an incomplete batch at the end of a WAL.
+    SkipAnyCorruptedRecords,
+}
+
+/// What the recovery of a WAL replayed and dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WalRecoveryReport {
+    pub batches: u64,
+    pub records: u64,
+    /// The corrupted parts of the WAL skipped by `WalRecoveryMode::SkipAnyCorruptedRecords`. In a V2 WAL, the
+    /// rest of the block is skipped with a corrupted fragment.
+    pub skipped_batches: u64,
+    /// The bytes truncated from the end of the WAL.
+    pub truncated_bytes: u64,
+    /// The first corruption found, if any.
+    pub corruption: Option<String>,
+}
+
+/// A record of a WAL, as `(key, entry type, value)`.
+pub(crate) type WalRecord = (KeyBytes, EntryType, Bytes);
+
+/// What is wrong with a write batch in a V1 WAL.
+enum BatchCorruption {
+    /// The batch is cut off by the end of the WAL.
+    Incomplete,
+    /// The checksum does not match the batch, which takes `frame_len` bytes with its size and checksum.
+    ChecksumMismatch { frame_len: usize },
+}
+
+/// A write batch read from a WAL, or a corrupted part of it.
+enum WalRead<'a> {
+    Batch(Cow<'a, [u8]>),
+    /// A corrupted or incomplete batch starting at `offset`. The reader resumes at `resume`, or has reached the
+    /// end of the WAL if it is `None`.
+    Corruption {
+        offset: usize,
+        reason: &'static str,
+        resume: Option<usize>,
+    },
+}
+
+/// Reads the write batches of a WAL after its header.
+struct WalReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    version: WalFormatVersion,
+    /// Set when a V2 WAL skips to the next block after a corruption, until the fragments of the batch cut by the
+    /// skip are passed.
+    resyncing: bool,
+}
+
+impl<'a> WalReader<'a> {
+    fn next_framed_batch(&mut self) -> WalRead<'a> {
+        let offset = self.offset;
+        let buf = &self.buf[offset..];
+        match Wal::check_batch(buf) {
+            Ok(batch_size) => {
+                self.offset += batch_size + 8;
+                WalRead::Batch(Cow::Borrowed(&buf[4..4 + batch_size]))
+            }
+            Err(BatchCorruption::Incomplete) => self.incomplete(offset),
+            Err(BatchCorruption::ChecksumMismatch { frame_len }) => {
+                self.offset += frame_len;
+                WalRead::Corruption {
+                    offset,
+                    reason: "checksum mismatch",
+                    resume: Some(self.offset),
+                }
+            }
+        }
+    }
+
+    /// Reads the fragments of the next write batch in a V2 WAL.
+    fn next_fragmented_batch(&mut self) -> Option<WalRead<'a>> {
+        // the offset of the first fragment and the payloads read so far of a fragmented batch
+        let mut batch: Option<(usize, Vec<u8>)> = None;
+        while self.offset < self.buf.len() {
+            let offset = self.offset;
+            let batch_offset = batch
+                .as_ref()
+                .map_or(offset, |(batch_offset, _)| *batch_offset);
+            let block_left = WAL_BLOCK_SIZE - offset % WAL_BLOCK_SIZE;
+            if block_left < FRAGMENT_HEADER_SIZE {
+                self.offset += block_left;
+                continue;
+            }
+            let buf = &self.buf[offset..];
+            if buf.len() < FRAGMENT_HEADER_SIZE {
+                return Some(self.incomplete(batch_offset));
+            }
+            let mut header = &buf[..FRAGMENT_HEADER_SIZE];
+            let checksum = header.get_u32();
+            let len = header.get_u16() as usize;
+            let fragment_len = FRAGMENT_HEADER_SIZE + len;
+            if fragment_len > block_left {
+                return Some(
+                    self.skip_block(batch_offset, "fragment crosses the end of its block"),
+                );
+            }
+            if fragment_len > buf.len() {
+                return Some(self.incomplete(batch_offset));
+            }
+            if crc32fast::hash(&buf[FRAGMENT_HEADER_SIZE - 1..fragment_len]) != checksum {
+                return Some(self.skip_block(batch_offset, "checksum mismatch"));
+            }
+            let Some(fragment_type) = FragmentType::from_u8(header.get_u8()) else {
+                return Some(self.skip_block(batch_offset, "unknown fragment type"));
+            };
+            let payload = &buf[FRAGMENT_HEADER_SIZE..fragment_len];
+            self.offset += fragment_len;
+            match (fragment_type, batch.as_mut()) {
+                (FragmentType::Full, None) => {
+                    self.resyncing = false;
+                    return Some(WalRead::Batch(Cow::Borrowed(payload)));
+                }
+                (FragmentType::First, None) => {
+                    self.resyncing = false;
+                    batch = Some((offset, payload.to_vec()));
+                }
+                (FragmentType::Middle, Some((_, data))) => data.extend_from_slice(payload),
+                (FragmentType::Last, Some((_, data))) => {
+                    data.extend_from_slice(payload);
+                    return Some(WalRead::Batch(Cow::Owned(std::mem::take(data))));
+                }
+                (FragmentType::Middle, None) if self.resyncing => {}
+                (FragmentType::Last, None) if self.resyncing => self.resyncing = false,
+                (FragmentType::Middle | FragmentType::Last, None) => {
+                    return Some(WalRead::Corruption {
+                        offset,
+                        reason: "fragment without the start of its batch",
+                        resume: Some(self.offset),
+                    });
+                }
+                (FragmentType::Full | FragmentType::First, Some(_)) => {
+                    // read the fragment again as the start of the next batch
+                    self.offset = offset;
+                    return Some(WalRead::Corruption {
+                        offset: batch_offset,
+                        reason: "batch without its last fragment",
+                        resume: Some(offset),
+                    });
+                }
+            }
+        }
+        batch.map(|(batch_offset, _)| self.incomplete(batch_offset))
+    }
+
+    fn incomplete(&mut self, offset: usize) -> WalRead<'a> {
+        self.offset = self.buf.len();
+        WalRead::Corruption {
+            offset,
+            reason: "incomplete write batch",
+            resume: None,
+        }
+    }
+
+    /// Skips the rest of the block after a corrupted fragment, whose length cannot be trusted.
+    fn skip_block(&mut self, offset: usize, reason: &'static str) -> WalRead<'a> {
+        self.offset = (self.offset / WAL_BLOCK_SIZE + 1) * WAL_BLOCK_SIZE;
+        self.resyncing = true;
+        WalRead::Corruption {
+            offset,
+            reason,
+            resume: Some(self.offset),
+        }
+    }
+}
+
+impl<'a> Iterator for WalReader<'a> {
+    type Item = WalRead<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        match self.version {
+            WalFormatVersion::V2 => self.next_fragmented_batch(),
+            _ => Some(self.next_framed_batch()),
+        }
+    }
+}
+
+struct WalWriter {
+    file: BufWriter<File>,
+    /// The offset in the current block of a V2 WAL.
+    block_offset: usize,
+}
+
+impl WalWriter {
+    /// Writes `batch` as fragments in the blocks of a V2 WAL.
+    fn write_fragments(&mut self, mut batch: &[u8]) -> Result<()> {
+        let mut first = true;
+        loop {
+            let block_left = WAL_BLOCK_SIZE - self.block_offset;
+            if block_left < FRAGMENT_HEADER_SIZE {
+                self.file
+                    .write_all(&[0; FRAGMENT_HEADER_SIZE][..block_left])?;
+                self.block_offset = 0;
+                continue;
+            }
+            let len = batch.len().min(block_left - FRAGMENT_HEADER_SIZE);
+            let last = len == batch.len();
+            let fragment_type = match (first, last) {
+                (true, true) => FragmentType::Full,
+                (true, false) => FragmentType::First,
+                (false, false) => FragmentType::Middle,
+                (false, true) => FragmentType::Last,
+            };
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&[fragment_type as u8]);
+            hasher.update(&batch[..len]);
+            self.file.write_all(&hasher.finalize().to_be_bytes())?;
+            self.file.write_all(&(len as u16).to_be_bytes())?;
+            self.file.write_all(&[fragment_type as u8])?;
+            self.file.write_all(&batch[..len])?;
+            self.block_offset = (self.block_offset + FRAGMENT_HEADER_SIZE + len) % WAL_BLOCK_SIZE;
+            batch = &batch[len..];
+            first = false;
+            if last {
+                return Ok(());
+            }
+        }
+    }
+}
 
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    writer: Arc<Mutex<WalWriter>>,
+    /// The format of the file. New records are always written in the format the file started with.
+    version: WalFormatVersion,
 }
 
 impl Wal {
     pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = BufWriter::new(
+            OpenOptions::new()
+                .read(true)
+                .create_new(true)
+                .write(true)
+                .open(path)
+                .context("failed to create WAL")?,
+        );
+        file.write_all(&WAL_HEADER_MAGIC.to_be_bytes())?;
+        file.write_all(&(WalFormatVersion::V2 as u32).to_be_bytes())?;
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+            writer: Arc::new(Mutex::new(WalWriter {
+                file,
+                block_offset: 8,
+            })),
+            version: WalFormatVersion::V2,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Replay the WAL into `skiplist`, and the range tombstones in it into `range_tombstones` as
+    /// `(start, ts) -> end`. A corrupted or incomplete record at the end of the WAL is dropped.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, (EntryType, Bytes)>,
+        range_tombstones: &SkipMap<KeyBytes, Bytes>,
+    ) -> Result<Self> {
+        let (wal, _) =
+            Self::recover_with_mode(path, skiplist, range_tombstones, WalRecoveryMode::default())?;
+        Ok(wal)
+    }
+
+    /// Replay the WAL like `recover`, handling the corrupted and incomplete records according to `mode`. The
+    /// records dropped from the end are truncated from the file, so that new records follow the recovered ones.
+    pub fn recover_with_mode(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, (EntryType, Bytes)>,
+        range_tombstones: &SkipMap<KeyBytes, Bytes>,
+        mode: WalRecoveryMode,
+    ) -> Result<(Self, WalRecoveryReport)> {
         let path = path.as_ref();
         let mut file = OpenOptions::new()
             .read(true)
@@ -52,77 +369,292 @@ impl Wal {
             .context("failed to recover from WAL")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let batch_size = rbuf.get_u32() as usize;
-            if rbuf.remaining() < batch_size {
-                bail!("incomplete WAL");
+        let (version, header_len) = Self::read_header(&buf)?;
+        let reader = WalReader {
+            buf: &buf,
+            offset: header_len,
+            version,
+            resyncing: false,
+        };
+        let mut report = WalRecoveryReport::default();
+        let mut file_len = buf.len();
+        for read in reader {
+            let batch = match read {
+                WalRead::Batch(batch) => batch,
+                WalRead::Corruption {
+                    offset,
+                    reason,
+                    resume,
+                } => {
+                    let is_tail = resume.is_none_or(|resume| resume >= buf.len());
+                    let corruption = format!("{} at offset {}", reason, offset);
+                    match mode {
+                        WalRecoveryMode::AbsoluteConsistency => bail!(corruption),
+                        WalRecoveryMode::TolerateCorruptedTailRecords if !is_tail => {
+                            bail!("{} before the end of the WAL", corruption)
+                        }
+                        WalRecoveryMode::SkipAnyCorruptedRecords if !is_tail => {
+                            report.skipped_batches += 1;
+                            report.corruption.get_or_insert(corruption);
+                            continue;
+                        }
+                        _ => {
+                            report.truncated_bytes = (buf.len() - offset) as u64;
+                            report.corruption.get_or_insert(corruption);
+                            file.set_len(offset as u64)?;
+                            file.sync_all()?;
+                            file_len = offset;
+                            break;
+                        }
+                    }
+                }
+            };
+            let records = Self::decode_batch(&batch, version);
+            report.batches += 1;
+            report.records += records.len() as u64;
+            for (key, entry_type, value) in records {
+                match entry_type {
+                    EntryType::RangeDelete => {
+                        insert_range_tombstone(range_tombstones, key, value);
+                    }
+                    _ => {
+                        skiplist.insert(key, (entry_type, value));
+                    }
+                }
             }
-            let mut batch_buf = &rbuf[..batch_size];
-            let mut kv_pairs = Vec::new();
-            let mut hasher = crc32fast::Hasher::new();
-            // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
-            // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
-            let single_checksum = crc32fast::hash(batch_buf);
-            while batch_buf.has_remaining() {
-                let key_len = batch_buf.get_u16() as usize;
-                hasher.write(&(key_len as u16).to_be_bytes());
-                let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
-                hasher.write(&key);
-                batch_buf.advance(key_len);
-                let ts = batch_buf.get_u64();
-                hasher.write(&ts.to_be_bytes());
-                let value_len = batch_buf.get_u16() as usize;
-                hasher.write(&(value_len as u16).to_be_bytes());
-                let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
-                hasher.write(&value);
-                kv_pairs.push((key, ts, value));
-                batch_buf.advance(value_len);
+        }
+        let wal = Self {
+            writer: Arc::new(Mutex::new(WalWriter {
+                file: BufWriter::new(file),
+                block_offset: file_len % WAL_BLOCK_SIZE,
+            })),
+            version,
+        };
+        Ok((wal, report))
+    }
+
+    /// Decode the records of the WAL in `buf` in the order they were written. An incomplete or corrupted write
+    /// batch at the end of the WAL, which may be one being written, ends the records.
+    pub(crate) fn read_records(buf: &[u8]) -> Result<Vec<WalRecord>> {
+        let (version, header_len) = Self::read_header(buf)?;
+        let reader = WalReader {
+            buf,
+            offset: header_len,
+            version,
+            resyncing: false,
+        };
+        let mut records = Vec::new();
+        for read in reader {
+            match read {
+                WalRead::Batch(batch) => records.extend(Self::decode_batch(&batch, version)),
+                WalRead::Corruption {
+                    offset,
+                    reason,
+                    resume,
+                } => {
+                    if resume.is_some_and(|resume| resume < buf.len()) {
+                        bail!("{} at offset {} before the end of the WAL", reason, offset);
+                    }
+                    break;
+                }
             }
-            rbuf.advance(batch_size);
-            let expected_checksum = rbuf.get_u32();
-            let component_checksum = hasher.finalize();
-            assert_eq!(component_checksum, single_checksum);
-            if single_checksum != expected_checksum {
-                bail!("checksum mismatch");
+        }
+        Ok(records)
+    }
+
+    /// Read the format version of a WAL, and the length of its header.
+    fn read_header(buf: &[u8]) -> Result<(WalFormatVersion, usize)> {
+        if buf.len() < 8 || (&buf[..4]).get_u32() != WAL_HEADER_MAGIC {
+            return Ok((WalFormatVersion::V1, 0));
+        }
+        let version = match (&buf[4..8]).get_u32() {
+            2 => WalFormatVersion::V2,
+            version => bail!("unsupported WAL format version {}", version),
+        };
+        Ok((version, 8))
+    }
+
+    /// Decode the records of a write batch.
+    fn decode_batch(mut batch: &[u8], version: WalFormatVersion) -> Vec<WalRecord> {
+        let mut kv_pairs = Vec::new();
+        let mut hasher = crc32fast::Hasher::new();
+        // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
+        // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
+        let single_checksum = crc32fast::hash(batch);
+        while batch.has_remaining() {
+            let key_len = Self::get_len(&mut batch, &mut hasher, version);
+            let key = Bytes::copy_from_slice(&batch[..key_len]);
+            hasher.write(&key);
+            batch.advance(key_len);
+            let ts = batch.get_u64();
+            hasher.write(&ts.to_be_bytes());
+            let mut entry_type = if version >= WalFormatVersion::V2 {
+                let entry_type = batch.get_u8();
+                hasher.write_u8(entry_type);
+                EntryType::from_u8(entry_type)
+            } else {
+                EntryType::Put
+            };
+            let value_len = Self::get_len(&mut batch, &mut hasher, version);
+            if version == WalFormatVersion::V1 && value_len == 0 {
+                entry_type = EntryType::Delete;
             }
-            for (key, ts, value) in kv_pairs {
-                skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            let value = Bytes::copy_from_slice(&batch[..value_len]);
+            hasher.write(&value);
+            kv_pairs.push((key, ts, entry_type, value));
+            batch.advance(value_len);
+        }
+        let component_checksum = hasher.finalize();
+        assert_eq!(component_checksum, single_checksum);
+        kv_pairs
+            .into_iter()
+            .map(|(key, ts, entry_type, value)| {
+                (KeyBytes::from_bytes_with_ts(key, ts), entry_type, value)
+            })
+            .collect()
+    }
+
+    /// Checks the framing and the checksum of the write batch at the start of `buf` in a V1 WAL, and
+    /// returns the size of the batch.
+    fn check_batch(buf: &[u8]) -> Result<usize, BatchCorruption> {
+        if buf.len() < 4 {
+            return Err(BatchCorruption::Incomplete);
+        }
+        let batch_size = (&buf[..4]).get_u32() as usize;
+        if buf.len() - 4 < batch_size + 4 {
+            return Err(BatchCorruption::Incomplete);
+        }
+        let batch = &buf[4..4 + batch_size];
+        let checksum = (&buf[4 + batch_size..]).get_u32();
+        if crc32fast::hash(batch) != checksum {
+            return Err(BatchCorruption::ChecksumMismatch {
+                frame_len: batch_size + 8,
+            });
+        }
+        Ok(batch_size)
+    }
+
+    /// Decode a key or value length and feed its encoded bytes to the checksum.
+    fn get_len(
+        buf: &mut &[u8],
+        hasher: &mut crc32fast::Hasher,
+        version: WalFormatVersion,
+    ) -> usize {
+        let begin = *buf;
+        let len = match version {
+            WalFormatVersion::V1 => buf.get_u16() as usize,
+            _ => get_varint(buf) as usize,
+        };
+        hasher.write(&begin[..begin.len() - buf.len()]);
+        len
+    }
+
+    fn put_len(buf: &mut Vec<u8>, len: usize, version: WalFormatVersion) -> Result<()> {
+        match version {
+            WalFormatVersion::V1 => {
+                if len > u16::MAX as usize {
+                    bail!(
+                        "key or value of {} bytes cannot be written to a legacy WAL",
+                        len
+                    );
+                }
+                buf.put_u16(len as u16);
             }
+            _ => put_varint(buf, len as u64),
         }
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
-        })
+        Ok(())
     }
 
-    /// Implement this in week 3, day 5.
-    pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
-        let mut file = self.file.lock();
+    /// Encode the records of a write batch in the format of `version`.
+    fn encode_batch(
+        data: &[(KeySlice, &[u8], EntryType)],
+        version: WalFormatVersion,
+    ) -> Result<Vec<u8>> {
         let mut buf = Vec::<u8>::new();
-        for (key, value) in data {
-            buf.put_u16(key.key_len() as u16);
+        for (key, value, entry_type) in data {
+            Self::put_len(&mut buf, key.key_len(), version)?;
             buf.put_slice(key.key_ref());
             buf.put_u64(key.ts());
-            buf.put_u16(value.len() as u16);
+            // In V1, a deletion is written as a `Put` of an empty value, and there are no other entry types.
+            match (version, *entry_type) {
+                (WalFormatVersion::V2, entry_type) => buf.put_u8(entry_type as u8),
+                (WalFormatVersion::V1, EntryType::Delete) => {}
+                (WalFormatVersion::V1, EntryType::Put) if value.is_empty() => {
+                    bail!("empty values cannot be written to a legacy WAL")
+                }
+                (WalFormatVersion::V1, EntryType::Put) => {}
+                (WalFormatVersion::V1, entry_type) => {
+                    bail!("{:?} entries cannot be written to a legacy WAL", entry_type)
+                }
+            }
+            Self::put_len(&mut buf, value.len(), version)?;
             buf.put_slice(value);
         }
+        Ok(buf)
+    }
+
+    /// Encode a write batch as `batch_size (u32) | records | checksum (u32)` with the records in the latest
+    /// format, which is how the batches are shipped to a replica.
+    pub(crate) fn encode_batch_frame(data: &[(KeySlice, &[u8], EntryType)]) -> Result<Vec<u8>> {
+        let batch = Self::encode_batch(data, WalFormatVersion::V2)?;
+        if batch.len() > u32::MAX as usize {
+            bail!(
+                "write batch of {} bytes exceeds the frame limit of {} bytes",
+                batch.len(),
+                u32::MAX
+            );
+        }
+        let mut buf = Vec::with_capacity(batch.len() + 8);
+        buf.put_u32(batch.len() as u32);
+        buf.put_slice(&batch);
+        buf.put_u32(crc32fast::hash(&batch));
+        Ok(buf)
+    }
+
+    /// Decode a write batch encoded by `encode_batch_frame`.
+    pub(crate) fn decode_batch_frame(buf: &[u8]) -> Result<Vec<WalRecord>> {
+        match Self::check_batch(buf) {
+            Ok(batch_size) if batch_size + 8 == buf.len() => Ok(Self::decode_batch(
+                &buf[4..4 + batch_size],
+                WalFormatVersion::V2,
+            )),
+            Ok(_) | Err(BatchCorruption::Incomplete) => bail!("malformed write batch frame"),
+            Err(BatchCorruption::ChecksumMismatch { .. }) => bail!("checksum mismatch"),
+        }
+    }
+
+    /// Implement this in week 3, day 5.
+    pub fn put_batch(&self, data: &[(KeySlice, &[u8], EntryType)]) -> Result<()> {
+        let buf = Self::encode_batch(data, self.version)?;
+        let mut writer = self.writer.lock();
+        if self.version == WalFormatVersion::V2 {
+            return writer.write_fragments(&buf);
+        }
+        if buf.len() > u32::MAX as usize {
+            bail!(
+                "write batch of {} bytes exceeds the record limit of a legacy WAL of {} bytes",
+                buf.len(),
+                u32::MAX
+            );
+        }
         // write batch_size header (u32)
-        file.write_all(&(buf.len() as u32).to_be_bytes())?;
+        writer.file.write_all(&(buf.len() as u32).to_be_bytes())?;
         // write key-value pairs body
-        file.write_all(&buf)?;
+        writer.file.write_all(&buf)?;
         // write checksum (u32)
-        file.write_all(&crc32fast::hash(&buf).to_be_bytes())?;
+        writer
+            .file
+            .write_all(&crc32fast::hash(&buf).to_be_bytes())?;
         Ok(())
     }
-
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        self.put_batch(&[(key, value)])
+        self.put_batch(&[(key, value, EntryType::Put)])
     }
 
     pub fn sync(&self) -> Result<()> {
-        let mut file = self.file.lock();
-        file.flush()?;
-        file.get_mut().sync_all()?;
+        let mut writer = self.writer.lock();
+        writer.file.flush()?;
+        writer.file.get_mut().sync_all()?;
         Ok(())
     }
 }
@@ -0,0 +1,178 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Backpressure on writes. When flushes or compactions fall behind, the number of immutable memtables or L0
+//! SSTs grows. Past a soft threshold each write is delayed, and past a hard threshold writes block until the
+//! background threads bring it back down.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, ensure};
+use parking_lot::{Condvar, Mutex};
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+
+/// How long a blocked write waits before checking the state again if it is not woken up by a flush or compaction.
+const STOP_RECHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone)]
+pub struct WriteStallOptions {
+    // Delay writes once L0 has this many SSTs
+    pub level0_slowdown_writes_trigger: usize,
+    // Block writes once L0 has this many SSTs
+    pub level0_stop_writes_trigger: usize,
+    // Delay writes once this many immutable memtables are waiting for a flush, which must be above
+    // `num_memtable_limit`
+    pub imm_memtables_slowdown_writes_trigger: usize,
+    // Block writes once this many immutable memtables are waiting for a flush
+    pub imm_memtables_stop_writes_trigger: usize,
+    // How long each write is delayed past a soft threshold
+    pub slowdown_delay: Duration,
+}
+
+impl WriteStallOptions {
+    /// Checks that the triggers are only reached when flushes or compactions fall behind. A trigger of 0 stalls
+    /// every write, and up to `num_memtable_limit` immutable memtables are normal while they wait for a flush.
+    pub(crate) fn validate(&self, num_memtable_limit: usize) -> Result<()> {
+        ensure!(
+            self.level0_slowdown_writes_trigger > 0,
+            "level0_slowdown_writes_trigger must not be 0"
+        );
+        ensure!(
+            self.level0_stop_writes_trigger >= self.level0_slowdown_writes_trigger,
+            "level0_stop_writes_trigger ({}) must not be below level0_slowdown_writes_trigger ({})",
+            self.level0_stop_writes_trigger,
+            self.level0_slowdown_writes_trigger
+        );
+        ensure!(
+            self.imm_memtables_slowdown_writes_trigger > num_memtable_limit,
+            "imm_memtables_slowdown_writes_trigger ({}) must be above num_memtable_limit ({})",
+            self.imm_memtables_slowdown_writes_trigger,
+            num_memtable_limit
+        );
+        ensure!(
+            self.imm_memtables_stop_writes_trigger >= self.imm_memtables_slowdown_writes_trigger,
+            "imm_memtables_stop_writes_trigger ({}) must not be below imm_memtables_slowdown_writes_trigger ({})",
+            self.imm_memtables_stop_writes_trigger,
+            self.imm_memtables_slowdown_writes_trigger
+        );
+        Ok(())
+    }
+}
+
+/// What a write stall is caused by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStallCause {
+    L0Files,
+    ImmMemtables,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    Normal,
+    /// Writes are delayed past a soft threshold.
+    Delayed(WriteStallCause),
+    /// Writes block past a hard threshold.
+    Stopped(WriteStallCause),
+}
+
+impl WriteStallCondition {
+    /// The condition of a state. Writes stop if any hard threshold is reached, and are delayed otherwise if any
+    /// soft threshold is.
+    pub fn of(state: &LsmStorageState, options: &WriteStallOptions) -> Self {
+        let l0 = state.l0_sstables.len();
+        let imm = state.imm_memtables.len();
+        if imm >= options.imm_memtables_stop_writes_trigger {
+            Self::Stopped(WriteStallCause::ImmMemtables)
+        } else if l0 >= options.level0_stop_writes_trigger {
+            Self::Stopped(WriteStallCause::L0Files)
+        } else if imm >= options.imm_memtables_slowdown_writes_trigger {
+            Self::Delayed(WriteStallCause::ImmMemtables)
+        } else if l0 >= options.level0_slowdown_writes_trigger {
+            Self::Delayed(WriteStallCause::L0Files)
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+/// The writes stalled so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    pub delayed_writes: u64,
+    pub delayed_duration: Duration,
+    pub stopped_writes: u64,
+    pub stopped_duration: Duration,
+}
+
+/// Tracks the stalled writes and wakes up the blocked ones.
+#[derive(Debug, Default)]
+pub(crate) struct WriteController {
+    stats: Mutex<WriteStallStats>,
+    cvar: Condvar,
+}
+
+impl WriteController {
+    /// Wakes up the blocked writes after a flush or compaction changed the state.
+    pub(crate) fn notify(&self) {
+        self.cvar.notify_all();
+    }
+
+    pub(crate) fn stats(&self) -> WriteStallStats {
+        self.stats.lock().clone()
+    }
+}
+
+impl LsmStorageInner {
+    pub fn write_stall_condition(&self) -> WriteStallCondition {
+        match &self.options.write_stall {
+            Some(options) => WriteStallCondition::of(&self.state.read(), options),
+            None => WriteStallCondition::Normal,
+        }
+    }
+
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        self.write_controller.stats()
+    }
+
+    /// Delays or blocks a write according to the current condition, before the write takes the MVCC write lock.
+    pub(crate) fn stall_write(&self) {
+        let Some(options) = &self.options.write_stall else {
+            return;
+        };
+        match self.write_stall_condition() {
+            WriteStallCondition::Normal => {}
+            WriteStallCondition::Delayed(_) => {
+                std::thread::sleep(options.slowdown_delay);
+                let mut stats = self.write_controller.stats.lock();
+                stats.delayed_writes += 1;
+                stats.delayed_duration += options.slowdown_delay;
+            }
+            WriteStallCondition::Stopped(_) => {
+                let start = Instant::now();
+                let mut stats = self.write_controller.stats.lock();
+                while matches!(
+                    self.write_stall_condition(),
+                    WriteStallCondition::Stopped(_)
+                ) {
+                    self.write_controller
+                        .cvar
+                        .wait_for(&mut stats, STOP_RECHECK_INTERVAL);
+                }
+                stats.stopped_writes += 1;
+                stats.stopped_duration += start.elapsed();
+            }
+        }
+    }
+}
@@ -26,7 +26,6 @@ use mini_lsm_wrapper::compact::{
 };
 use mini_lsm_wrapper::key::KeyBytes;
 use mini_lsm_wrapper::lsm_storage::LsmStorageState;
-use mini_lsm_wrapper::mem_table::MemTable;
 use mini_lsm_wrapper::table::SsTable;
 
 #[derive(Parser, Debug)]
@@ -122,15 +121,8 @@ impl Default for MockStorage {
 
 impl MockStorage {
     pub fn new() -> Self {
-        let snapshot = LsmStorageState {
-            memtable: Arc::new(MemTable::create(0)),
-            imm_memtables: Vec::new(),
-            l0_sstables: Vec::new(),
-            levels: Vec::new(),
-            sstables: Default::default(),
-        };
         Self {
-            snapshot,
+            snapshot: LsmStorageState::empty(),
             next_sst_id: 1,
             file_list: Default::default(),
             total_flushes: 0,
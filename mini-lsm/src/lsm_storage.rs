@@ -63,6 +63,17 @@ pub enum WriteBatchRecord<T: AsRef<[u8]>> {
 }
 
 impl LsmStorageState {
+    /// Creates a state without memtable data, SSTs or levels, which the compaction simulator fills in.
+    pub fn empty() -> Self {
+        Self {
+            memtable: Arc::new(MemTable::create(0)),
+            imm_memtables: Vec::new(),
+            l0_sstables: Vec::new(),
+            levels: Vec::new(),
+            sstables: Default::default(),
+        }
+    }
+
     fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
@@ -74,11 +85,8 @@ impl LsmStorageState {
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
-            memtable: Arc::new(MemTable::create(0)),
-            imm_memtables: Vec::new(),
-            l0_sstables: Vec::new(),
             levels,
-            sstables: Default::default(),
+            ..Self::empty()
         }
     }
 }
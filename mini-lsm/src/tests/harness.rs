@@ -234,6 +234,16 @@ pub fn sync(storage: &LsmStorageInner) {
     storage.force_flush_next_imm_memtable().unwrap();
 }
 
+/// Flushes the memtable and all immutable memtables to SSTs.
+pub fn flush_all(storage: &MiniLsm) {
+    while {
+        let state = storage.inner.state.read();
+        !state.memtable.is_empty() || !state.imm_memtables.is_empty()
+    } {
+        storage.force_flush().unwrap();
+    }
+}
+
 pub fn compaction_bench(storage: Arc<MiniLsm>) {
     let mut key_map = BTreeMap::<usize, usize>::new();
     let gen_key = |i| format!("{:010}", i); // 10B
@@ -325,7 +335,6 @@ pub fn check_compaction_ratio(storage: Arc<MiniLsm>) {
         .num_active_iterators();
     let num_memtables = storage.inner.state.read().imm_memtables.len() + 1;
     match compaction_options {
-        CompactionOptions::NoCompaction => unreachable!(),
         CompactionOptions::Simple(SimpleLeveledCompactionOptions {
             size_ratio_percent,
             level0_file_num_compaction_trigger,
@@ -425,6 +434,7 @@ pub fn check_compaction_ratio(storage: Arc<MiniLsm>) {
                 "we found {num_iters} iterators in your implementation, (num_memtables={num_memtables}, num_tiers={num_tiers}) did you use concat iterators?"
             );
         }
+        _ => unreachable!(),
     }
 }
 